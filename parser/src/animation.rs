@@ -0,0 +1,500 @@
+//! Keyframe tracks declared with `animate` blocks
+use crate::generator::build_transform;
+use crate::types::{Action, AnimationTarget, BodyDef, KeyframeValue};
+use crate::Data;
+use raytracer::{
+    units::color::QuantColor,
+    units::objects::{ObjectType, Shape},
+    units::tuple::{Point, Tuple, Vector},
+    units::{Matrix, Transformable, IDENTITY_MATRIX},
+    world::animation::Track,
+    world::{Collider, Environment, RigidSphere, Simulation},
+};
+
+/// Properties that can be animated for each target
+const CAMERA_PROPERTIES: [&str; 4] = ["from", "to", "up", "field-of-view"];
+const LIGHT_PROPERTIES: [&str; 2] = ["at", "intensity"];
+const OBJECT_PROPERTIES: [&str; 5] = ["translate", "scale", "rotate-x", "rotate-y", "rotate-z"];
+const MATERIAL_PROPERTIES: [&str; 13] = [
+    "color",
+    "ambient",
+    "diffuse",
+    "specular",
+    "shine",
+    "reflect",
+    "transparent",
+    "refractive-index",
+    "roughness",
+    "metallic",
+    "specular-tint",
+    "absorption",
+    "density",
+];
+/// Properties animated with triples, every other property is animated with numbers
+const TRIPLE_PROPERTIES: [&str; 9] = [
+    "from",
+    "to",
+    "up",
+    "at",
+    "intensity",
+    "translate",
+    "scale",
+    "color",
+    "absorption",
+];
+
+/// Single animated property of a scene element
+#[derive(Debug)]
+pub struct AnimationTrack {
+    pub target: AnimationTarget,
+    pub name: Option<String>,
+    pub property: String,
+    pub track: Track<KeyframeValue>,
+}
+
+/// Path of an object moved by the physics simulation
+#[derive(Debug)]
+pub struct Trajectory {
+    pub name: String,
+    /// position of the object before the simulation
+    pub start: Point,
+    pub track: Track<Point>,
+}
+
+/// All animated properties of a scene
+#[derive(Debug, Default)]
+pub struct Animations {
+    pub tracks: Vec<AnimationTrack>,
+    pub trajectories: Vec<Trajectory>,
+}
+
+impl Animations {
+    /// Collects the tracks of every `animate` block and runs every `simulate` block in the scene
+    ///
+    /// Returns an error for properties that can't be animated, are animated twice or get
+    /// the wrong kind of value, and for bodies that aren't named spheres.
+    pub fn from_data(data: &Data) -> Result<Animations, String> {
+        let mut tracks = Vec::new();
+        let mut trajectories = Vec::new();
+        for action in data {
+            if let Action::Simulate {
+                simulate,
+                duration,
+                timestep,
+                gravity,
+                wind,
+            } = action
+            {
+                let environment = Environment {
+                    gravity: *gravity,
+                    wind: *wind,
+                };
+                trajectories.extend(simulate_bodies(
+                    data,
+                    simulate,
+                    environment,
+                    *timestep,
+                    *duration,
+                )?);
+            }
+            if let Action::Animate {
+                animate,
+                name,
+                property,
+                keyframes,
+            } = action
+            {
+                let properties: &[&str] = match animate {
+                    AnimationTarget::Camera => &CAMERA_PROPERTIES,
+                    AnimationTarget::Light => &LIGHT_PROPERTIES,
+                    AnimationTarget::Object => &OBJECT_PROPERTIES,
+                    AnimationTarget::Material => &MATERIAL_PROPERTIES,
+                };
+                if !properties.contains(&property.as_str()) {
+                    return Err(format!(
+                        "Property {} can't be animated on {:?}",
+                        property, animate
+                    ));
+                }
+                if tracks.iter().any(|t: &AnimationTrack| {
+                    t.target == *animate && t.name == *name && t.property == *property
+                }) {
+                    return Err(format!(
+                        "Property {} of {:?} is animated twice",
+                        property, animate
+                    ));
+                }
+                let triple = TRIPLE_PROPERTIES.contains(&property.as_str());
+                if keyframes
+                    .iter()
+                    .any(|k| matches!(k.value, KeyframeValue::Triple(_)) != triple)
+                {
+                    let kind = if triple { "triples" } else { "numbers" };
+                    return Err(format!(
+                        "Property {} must be animated with {}",
+                        property, kind
+                    ));
+                }
+
+                let mut track = Track::new();
+                for keyframe in keyframes {
+                    track.add_keyframe(keyframe.time, keyframe.value, keyframe.easing);
+                }
+                tracks.push(AnimationTrack {
+                    target: *animate,
+                    name: name.clone(),
+                    property: property.clone(),
+                    track,
+                });
+            }
+        }
+        Ok(Animations {
+            tracks,
+            trajectories,
+        })
+    }
+
+    /// Returns the animated value of a property at given time, if it is animated
+    pub fn value(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+    ) -> Option<KeyframeValue> {
+        self.tracks
+            .iter()
+            .find(|t| t.target == target && t.name.as_deref() == name && t.property == property)
+            .and_then(|t| t.track.value_at(time))
+    }
+
+    /// Returns the animated number, or `value` when the property isn't animated with numbers
+    pub fn scalar(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+        value: f64,
+    ) -> f64 {
+        match self.value(target, name, property, time) {
+            Some(KeyframeValue::Scalar(v)) => v,
+            _ => value,
+        }
+    }
+
+    /// Returns the animated triple, or None when the property isn't animated with triples
+    pub fn triple(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+    ) -> Option<[f64; 3]> {
+        match self.value(target, name, property, time) {
+            Some(KeyframeValue::Triple(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the animated point, or `value` when the property isn't animated
+    pub fn point(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+        value: Point,
+    ) -> Point {
+        self.triple(target, name, property, time)
+            .map_or(value, Point::from)
+    }
+
+    /// Returns the animated vector, or `value` when the property isn't animated
+    pub fn vector(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+        value: Vector,
+    ) -> Vector {
+        self.triple(target, name, property, time)
+            .map_or(value, |v| Vector::new(v[0], v[1], v[2]))
+    }
+
+    /// Returns the animated color, or `value` when the property isn't animated
+    pub fn color(
+        &self,
+        target: AnimationTarget,
+        name: Option<&str>,
+        property: &str,
+        time: f64,
+        value: QuantColor,
+    ) -> QuantColor {
+        self.triple(target, name, property, time)
+            .map_or(value, |v| {
+                QuantColor::new(
+                    v[0].round() as i64,
+                    v[1].round() as i64,
+                    v[2].round() as i64,
+                )
+            })
+    }
+
+    /// Returns the world space transformation an animated object gets at given time
+    ///
+    /// Scale is applied first, then rotation around x, y and z, then translation.
+    /// Simulated movement is applied after all of them.
+    pub fn object_transform(&self, name: &str, time: f64) -> Matrix {
+        let mut matrix = IDENTITY_MATRIX;
+        if let Some(trajectory) = self.trajectories.iter().find(|t| t.name == name) {
+            if let Some(position) = trajectory.track.value_at(time) {
+                let offset = position - trajectory.start;
+                matrix = Matrix::translate(offset.x, offset.y, offset.z);
+            }
+        }
+
+        let name = Some(name);
+        let object = AnimationTarget::Object;
+        if let Some(t) = self.triple(object, name, "translate", time) {
+            matrix = matrix * Matrix::translate(t[0], t[1], t[2]);
+        }
+        matrix = matrix
+            * Matrix::rotate_z(self.scalar(object, name, "rotate-z", time, 0.))
+            * Matrix::rotate_y(self.scalar(object, name, "rotate-y", time, 0.))
+            * Matrix::rotate_x(self.scalar(object, name, "rotate-x", time, 0.));
+        if let Some(s) = self.triple(object, name, "scale", time) {
+            matrix = matrix * Matrix::scale(s[0], s[1], s[2]);
+        }
+        matrix
+    }
+}
+
+/// Runs the simulation of the given bodies against every other object of the scene
+///
+/// Objects are taken with their initial transform, body spheres must be scaled uniformly.
+fn simulate_bodies(
+    data: &Data,
+    bodies: &[BodyDef],
+    environment: Environment,
+    timestep: f64,
+    duration: f64,
+) -> Result<Vec<Trajectory>, String> {
+    let mut simulation = Simulation::new(environment, timestep);
+    let mut names = Vec::new();
+    let mut spheres = Vec::new();
+    for action in data {
+        if let Action::AddObject {
+            name,
+            object_type,
+            transform,
+            ..
+        } = action
+        {
+            let shape = Shape::new(*object_type).transform(build_transform(transform));
+            match (
+                name,
+                bodies.iter().find(|b| Some(&b.object) == name.as_ref()),
+            ) {
+                (Some(name), Some(body)) => {
+                    if *object_type != ObjectType::Sphere {
+                        return Err(format!(
+                            "Only spheres can be simulated, {} is not one",
+                            name
+                        ));
+                    }
+                    names.push(name.clone());
+                    spheres.push((shape, body));
+                }
+                _ => simulation.colliders.push(Collider::from_shape(&shape)),
+            }
+        }
+    }
+    for body in bodies {
+        if !names.contains(&body.object) {
+            return Err(format!("No object named {} to simulate", body.object));
+        }
+    }
+
+    for (shape, body) in spheres {
+        if let Collider::Sphere(center, radius) = Collider::from_shape(&shape) {
+            simulation.bodies.push(
+                RigidSphere::new(center, radius)
+                    .set_velocity(body.velocity)
+                    .set_mass(body.mass)
+                    .set_restitution(body.restitution)
+                    .set_friction(body.friction),
+            );
+        }
+    }
+
+    let starts: Vec<Point> = simulation.bodies.iter().map(|b| b.position).collect();
+    Ok(simulation
+        .run(duration)
+        .into_iter()
+        .zip(names)
+        .zip(starts)
+        .map(|((track, name), start)| Trajectory { name, start, track })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value() {
+        let data: Data = serde_yaml::from_str(
+            "
+- animate: object
+  name: ball
+  property: translate
+  keyframes:
+    - time: 0
+      value: [0, 0, 0]
+    - time: 2
+      value: [0, 4, 0]
+      easing: step
+- animate: camera
+  property: field-of-view
+  keyframes:
+    - time: 0
+      value: 1
+      easing:
+        bezier: [0.42, 0, 0.58, 1]
+    - time: 1
+      value: 2
+",
+        )
+        .unwrap();
+        let animations = Animations::from_data(&data).unwrap();
+        assert_eq!(animations.tracks.len(), 2);
+
+        let camera = AnimationTarget::Camera;
+        let fov = animations.scalar(camera, None, "field-of-view", 0.5, 0.);
+        assert!((fov - 1.5).abs() < 1e-6);
+        assert_eq!(animations.scalar(camera, None, "from", 0.5, 7.), 7.);
+
+        let m = animations.object_transform("ball", 1.);
+        assert_eq!(Point::from(m * Point::new(0, 0, 0)), Point::new(0, 2, 0));
+        assert_eq!(animations.object_transform("other", 1.), IDENTITY_MATRIX);
+    }
+
+    #[test]
+    fn duplicate_tracks() {
+        let track = "
+- animate: material
+  name: glass
+  property: refractive-index
+  keyframes:
+    - time: 0
+      value: 1.5
+";
+        let data: Data = serde_yaml::from_str(&track.repeat(2)).unwrap();
+        assert_eq!(
+            Animations::from_data(&data).unwrap_err(),
+            "Property refractive-index of Material is animated twice"
+        );
+    }
+
+    #[test]
+    fn invalid_tracks() {
+        let error = |yaml: &str| {
+            let data: Data = serde_yaml::from_str(yaml).unwrap();
+            Animations::from_data(&data).unwrap_err()
+        };
+        let unknown = "
+- animate: camera
+  property: shine
+  keyframes: []
+";
+        assert_eq!(error(unknown), "Property shine can't be animated on Camera");
+
+        let triple = "
+- animate: camera
+  property: field-of-view
+  keyframes:
+    - time: 0
+      value: [1, 2, 3]
+";
+        assert_eq!(
+            error(triple),
+            "Property field-of-view must be animated with numbers"
+        );
+
+        let mixed = "
+- animate: object
+  name: ball
+  property: translate
+  keyframes:
+    - time: 0
+      value: [0, 0, 0]
+    - time: 1
+      value: 2
+";
+        assert_eq!(
+            error(mixed),
+            "Property translate must be animated with triples"
+        );
+
+        // Simulated bodies must be named spheres
+        let simulate = "
+- simulate:
+    - object: ball
+  duration: 1
+  timestep: 0.1
+";
+        assert_eq!(error(simulate), "No object named ball to simulate");
+        let plane = "
+- add object:
+  name: ball
+  type: plane
+  material: red
+  transform: []
+";
+        assert_eq!(
+            error(&(plane.to_string() + simulate)),
+            "Only spheres can be simulated, ball is not one"
+        );
+    }
+
+    #[test]
+    fn simulate() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add object:
+  name: ball
+  type: sphere
+  material: red
+  transform:
+    - translate: [0, 5, 0]
+- add object:
+  type: plane
+  material: red
+  transform: []
+- simulate:
+    - object: ball
+      velocity: [1, 0, 0]
+      restitution: 0.5
+  duration: 3
+  timestep: 0.01
+",
+        )
+        .unwrap();
+        let animations = Animations::from_data(&data).unwrap();
+        assert_eq!(animations.trajectories.len(), 1);
+        assert_eq!(animations.trajectories[0].start, Point::new(0, 5, 0));
+
+        // The ball starts where it was placed
+        assert_eq!(animations.object_transform("ball", 0.), IDENTITY_MATRIX);
+
+        // The ball moves sideways and never falls through the floor
+        for step in 1..30 {
+            let m = animations.object_transform("ball", step as f64 / 10.);
+            let center = Point::from(m * Point::new(0, 5, 0));
+            assert!(center.x > 0.);
+            assert!(center.y >= 1. - 1e-6);
+        }
+    }
+}
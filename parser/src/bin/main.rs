@@ -1,15 +1,371 @@
-use parser::generator::{generate_world, read_file};
-use std::{env, time::Instant};
+use parser::{
+    animation::Animations,
+    generator::{generate_animated_frame, generate_output, read_file, Assets},
+};
+use raytracer::render::{
+    Aov, AovLayer, Camera, Canvas, Denoiser, ExrPrecision, Output, RenderJob, Stats, Timings,
+    ToneMap, World,
+};
+use raytracer::world::Background;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use std::{env, fs, path::Path, process, str::FromStr};
+
+/// Command line options
+///
+/// `parser <scene.yaml> [--frames <first> <last>] [--fps <fps>] [--output <path>]
+/// [--exposure <stops>] [--tone-map <clamp|reinhard|aces>] [--srgb | --linear]
+/// [--format <png|ppm|hdr|pfm|exr>] [--exr-float] [--aov <name,name,...>] [--transparent]
+/// [--denoise] [--denoise-radius <pixels>] [--denoise-strength <value>] [--threads <count>]
+/// [--stats] [--stats-json <path>]`
+///
+/// Output options override the `set output` block of the scene.
+/// The image format follows the output extension, `--format` sets it for frame sequences.
+/// Aovs become layers of EXR files and separate `<name>_<aov>` images in other formats.
+/// Denoising filters the image guided by normal and albedo Aovs, rendered just for it.
+/// Giving its radius or strength turns it on as well.
+/// Renders use every core unless a number of threads is given.
+/// Stats of all frames are printed as a summary or written as JSON, with the time of each phase.
+/// Ctrl-C cancels the render and exits with status 130, frames already written are kept.
+struct Options {
+    scene: String,
+    frames: Option<(usize, usize)>,
+    fps: f64,
+    output: Option<String>,
+    exposure: Option<f64>,
+    tone_map: Option<ToneMap>,
+    srgb: Option<bool>,
+    format: String,
+    exr_precision: ExrPrecision,
+    aovs: Vec<Aov>,
+    transparent: bool,
+    denoise: bool,
+    denoise_radius: Option<usize>,
+    denoise_strength: Option<f64>,
+    threads: usize,
+    stats: bool,
+    stats_json: Option<String>,
+}
+
+/// Returns the argument at `i`, the value of option `name`
+fn value<'a>(args: &'a [String], i: usize, name: &str) -> Result<&'a str, String> {
+    args.get(i)
+        .map(String::as_str)
+        .ok_or_else(|| format!("Missing value for {}", name))
+}
+
+/// Parses the argument at `i`, the value of option `name`
+fn number<T: FromStr>(args: &[String], i: usize, name: &str) -> Result<T, String> {
+    let value = value(args, i, name)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, name))
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            scene: args.get(1).ok_or("No scene file given!")?.clone(),
+            frames: None,
+            fps: 24.,
+            output: None,
+            exposure: None,
+            tone_map: None,
+            srgb: None,
+            format: "png".to_string(),
+            exr_precision: ExrPrecision::Half,
+            aovs: Vec::new(),
+            transparent: false,
+            denoise: false,
+            denoise_radius: None,
+            denoise_strength: None,
+            threads: 0,
+            stats: false,
+            stats_json: None,
+        };
+        let mut i = 2;
+        while i < args.len() {
+            let name = args[i].as_str();
+            match name {
+                "--frames" => {
+                    let first = number(args, i + 1, name)?;
+                    let last = number(args, i + 2, name)?;
+                    options.frames = Some((first, last));
+                    i += 3;
+                }
+                "--fps" => {
+                    options.fps = number(args, i + 1, name)?;
+                    i += 2;
+                }
+                "--output" => {
+                    options.output = Some(value(args, i + 1, name)?.to_string());
+                    i += 2;
+                }
+                "--exposure" => {
+                    options.exposure = Some(number(args, i + 1, name)?);
+                    i += 2;
+                }
+                "--tone-map" => {
+                    options.tone_map = Some(match value(args, i + 1, name)? {
+                        "clamp" => ToneMap::Clamp,
+                        "reinhard" => ToneMap::Reinhard,
+                        "aces" => ToneMap::Aces,
+                        other => return Err(format!("Unknown tone map {}", other)),
+                    });
+                    i += 2;
+                }
+                "--srgb" => {
+                    options.srgb = Some(true);
+                    i += 1;
+                }
+                "--linear" => {
+                    options.srgb = Some(false);
+                    i += 1;
+                }
+                "--format" => {
+                    options.format = value(args, i + 1, name)?.to_string();
+                    i += 2;
+                }
+                "--exr-float" => {
+                    options.exr_precision = ExrPrecision::Float;
+                    i += 1;
+                }
+                "--aov" => {
+                    options.aovs = value(args, i + 1, name)?
+                        .split(',')
+                        .map(|aov| Aov::from_name(aov).ok_or(format!("Unknown aov {}", aov)))
+                        .collect::<Result<_, _>>()?;
+                    i += 2;
+                }
+                "--transparent" => {
+                    options.transparent = true;
+                    i += 1;
+                }
+                "--denoise" => {
+                    options.denoise = true;
+                    i += 1;
+                }
+                "--denoise-radius" => {
+                    options.denoise_radius = Some(number(args, i + 1, name)?);
+                    i += 2;
+                }
+                "--denoise-strength" => {
+                    options.denoise_strength = Some(number(args, i + 1, name)?);
+                    i += 2;
+                }
+                "--threads" => {
+                    options.threads = number(args, i + 1, name)?;
+                    i += 2;
+                }
+                "--stats" => {
+                    options.stats = true;
+                    i += 1;
+                }
+                "--stats-json" => {
+                    options.stats_json = Some(value(args, i + 1, name)?.to_string());
+                    i += 2;
+                }
+                other => return Err(format!("Unknown option {}", other)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Renders a frame with the requested Aovs and output pipeline, returns its stats too
+    ///
+    /// Frames share the job, so they also share its render threads.
+    /// Returns None when the job was cancelled.
+    fn render(
+        &self,
+        world: World,
+        camera: Camera,
+        output: Output,
+        job: &RenderJob,
+    ) -> Option<(Canvas, Vec<AovLayer>, Stats)> {
+        let world = if self.transparent {
+            world.set_background(Background::Transparent)
+        } else {
+            world
+        };
+        let denoiser = self.denoiser();
+        let mut aovs = self.aovs.clone();
+        if denoiser.is_some() {
+            for aov in [Aov::Normal, Aov::Albedo] {
+                if !aovs.contains(&aov) {
+                    aovs.push(aov);
+                }
+            }
+        }
+        let camera = camera.set_threads(self.threads);
+        let rendered = camera.render_job(world, &aovs, job);
+        eprintln!();
+        let (mut canvas, mut layers) = rendered?;
+        if let Some(denoiser) = denoiser {
+            let layer = |aov| layers.iter().find(|layer| layer.aov == aov);
+            match (layer(Aov::Normal), layer(Aov::Albedo)) {
+                (Some(normals), Some(albedo)) => denoiser.denoise(&mut canvas, normals, albedo),
+                _ => eprintln!("Skipped denoising, the normal and albedo aovs are missing"),
+            }
+            layers.retain(|layer| self.aovs.contains(&layer.aov));
+        }
+        canvas.set_output(output);
+        Some((canvas, layers, job.stats()))
+    }
+
+    /// Returns the denoiser of the options, if any of them asks for denoising
+    fn denoiser(&self) -> Option<Denoiser> {
+        if !self.denoise && self.denoise_radius.is_none() && self.denoise_strength.is_none() {
+            return None;
+        }
+        let denoiser = self
+            .denoise_radius
+            .map_or(Denoiser::default(), Denoiser::new);
+        Some(match self.denoise_strength {
+            Some(strength) => denoiser.set_strength(strength),
+            None => denoiser,
+        })
+    }
+
+    /// Writes the canvas and its Aovs in the format of the path's extension
+    fn write(&self, canvas: &Canvas, layers: &[AovLayer], path: &str) {
+        let path = Path::new(path);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+        if extension.eq_ignore_ascii_case("exr") {
+            canvas.write_exr_layers(path, layers, self.exr_precision);
+            return;
+        }
+        canvas.write(path);
+        for layer in layers {
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+            let name = format!("{}_{}.{}", stem, layer.aov.name(), extension);
+            layer.to_canvas().write(path.with_file_name(name));
+        }
+    }
+
+    /// Prints the stats and writes them as JSON when asked to
+    fn report(&self, stats: &Stats, timings: &Timings) {
+        if self.stats {
+            println!("{}\n{}", stats, timings);
+        }
+        if let Some(path) = &self.stats_json {
+            let json = format!(
+                "{{\"stats\": {}, \"timings\": {}}}\n",
+                stats.to_json(),
+                timings.to_json()
+            );
+            fs::write(path, json).expect("Failed to write stats");
+        }
+    }
+
+    /// Applies the output options on top of the scene's output pipeline
+    fn output(&self, output: Output) -> Output {
+        Output {
+            exposure: self.exposure.unwrap_or(output.exposure),
+            tone_map: self.tone_map.unwrap_or(output.tone_map),
+            srgb: self.srgb.unwrap_or(output.srgb),
+        }
+    }
+}
 
 fn main() {
-    let start = Instant::now();
     let args: Vec<String> = env::args().collect();
+    let options = Options::parse(&args).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+
+    // Ctrl-C stops the render at the next pixel instead of killing the process
+    let job = RenderJob::new().on_progress(|progress| eprint!("\r{}", progress.bar(30)));
+    let cancel = job.cancel_flag();
+    ctrlc::set_handler(move || cancel.store(true, Ordering::Relaxed))
+        .expect("Failed to set the Ctrl-C handler");
+    let mut timings = Timings::default();
+    let mut stats = Stats::default();
+    let start = Instant::now();
+    let data = read_file(&options.scene).unwrap();
+    timings.parse = start.elapsed();
+
+    let start = Instant::now();
+    let animations = Animations::from_data(&data).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    let assets = Assets::from_data(&data);
+    let output = options.output(generate_output(&data));
+    timings.build += start.elapsed();
+    let (times, path) = match options.frames {
+        Some((first, last)) => (
+            (first..=last)
+                .map(|frame| (frame, frame as f64 / options.fps))
+                .collect(),
+            options.output.as_deref().unwrap_or("./images/frame"),
+        ),
+        None => (
+            vec![(0, 0.)],
+            options
+                .output
+                .as_deref()
+                .unwrap_or("./images/yaml_generated.png"),
+        ),
+    };
+    for (frame, time) in times {
+        let start = Instant::now();
+        let (w, c) = generate_animated_frame(&data, &animations, &assets, time);
+        timings.build += start.elapsed();
+
+        let start = Instant::now();
+        let (canvas, layers, frame_stats) =
+            options.render(w, c, output, &job).unwrap_or_else(|| {
+                eprintln!("Render cancelled");
+                process::exit(130);
+            });
+        stats = stats.merge(&frame_stats);
+        timings.render += start.elapsed();
+
+        let start = Instant::now();
+        match options.frames {
+            Some(_) => {
+                options.write(
+                    &canvas,
+                    &layers,
+                    &format!("{}_{:04}.{}", path, frame, options.format),
+                );
+                println!("Rendered frame {}", frame);
+            }
+            None => options.write(&canvas, &layers, path),
+        }
+        timings.write += start.elapsed();
+    }
+    options.report(&stats, &timings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        let options = Options::parse(&args("parser scene.yaml --frames 2 5 --threads 4")).unwrap();
+        assert_eq!(options.frames, Some((2, 5)));
+        assert_eq!(options.threads, 4);
 
-    let data = read_file(args[1].clone()).unwrap();
-    let (w, c) = generate_world(data);
-    let canvas = c.render(w);
-    canvas.write_png("./images/yaml_generated.png");
+        // Missing and invalid values are errors instead of panics
+        assert!(Options::parse(&args("parser scene.yaml --frames 2")).is_err());
+        assert!(Options::parse(&args("parser scene.yaml --output")).is_err());
+        assert!(Options::parse(&args("parser scene.yaml --fps fast")).is_err());
+        assert!(Options::parse(&args("parser scene.yaml --aov depth,nothing")).is_err());
+        assert!(Options::parse(&args("parser")).is_err());
+        assert!(Options::parse(&args("parser scene.yaml --denoise-radius -1")).is_err());
 
-    let duration = start.elapsed();
-    println!("Execution took: {:?}", duration);
+        // Denoising is off by default and its options turn it on
+        assert_eq!(options.denoiser(), None);
+        let options = Options::parse(&args("parser scene.yaml --denoise")).unwrap();
+        assert_eq!(options.denoiser(), Some(Denoiser::default()));
+        let options = Options::parse(&args(
+            "parser scene.yaml --denoise-strength 2 --denoise-radius 3",
+        ))
+        .unwrap();
+        assert_eq!(options.denoiser(), Some(Denoiser::new(3).set_strength(2.)));
+    }
 }
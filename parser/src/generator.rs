@@ -1,13 +1,24 @@
 use crate::{
-    types::{Action, TransformActions},
+    animation::Animations,
+    types::{
+        Action, AnimationTarget, BackgroundDef, BumpDef, DispersionDef, EnvironmentMapDef,
+        IntegratorType, MaterialModel, MediumDef, PhaseDef, ProjectionType, TransformActions,
+    },
     Data,
 };
 use raytracer::{
-    render::{Camera, World},
-    units::{objects::Shape, Matrix, Transformable},
-    world::{Material, PointLight},
+    render::{
+        Adaptive, AmbientOcclusion, Camera, Caustics, Integrator, Output, PhotonMap, Projection,
+        World,
+    },
+    units::color::QuantColor,
+    units::{objects::Shape, Matrix, Transformable, IDENTITY_MATRIX},
+    world::{
+        Background, Bump, Dispersion, EnvironmentMap, Material, Medium, Microfacet, Phase,
+        PointLight, Shading, Texture,
+    },
 };
-use std::{collections::HashMap, error::Error, fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, error::Error, fs::File, io::BufReader, path::Path, sync::Arc};
 
 pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Data, Box<dyn Error>> {
     let file = File::open(path)?;
@@ -17,6 +28,94 @@ pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Data, Box<dyn Error>> {
 }
 
 pub fn generate_world(data: Data) -> (World, Camera) {
+    generate_frame(&data, 0.)
+}
+
+/// Generates the world and camera with every animated property evaluated at given time
+///
+/// Panics on invalid animations, `Animations::from_data` reports them as errors.
+pub fn generate_frame(data: &Data, time: f64) -> (World, Camera) {
+    let animations = Animations::from_data(data).unwrap_or_else(|error| panic!("{}", error));
+    generate_animated_frame(data, &animations, &Assets::from_data(data), time)
+}
+
+/// Environment maps and bump textures of a scene, loaded once and shared by every frame
+#[derive(Debug, Default)]
+pub struct Assets {
+    pub environments: Vec<(EnvironmentMapDef, Arc<EnvironmentMap>)>,
+    pub textures: Vec<(String, Arc<Texture>)>,
+}
+
+impl Assets {
+    /// Loads every environment map used as a background and every bump map in the scene
+    pub fn from_data(data: &Data) -> Assets {
+        let mut environments: Vec<(EnvironmentMapDef, Arc<EnvironmentMap>)> = Vec::new();
+        let mut textures: Vec<(String, Arc<Texture>)> = Vec::new();
+        for action in data {
+            match action {
+                Action::SetBackground {
+                    background: BackgroundDef::Map(map),
+                } => {
+                    if environments.iter().any(|(def, _)| def == map) {
+                        continue;
+                    }
+                    let environment = EnvironmentMap::load(&map.path)
+                        .unwrap_or_else(|e| {
+                            panic!("Can't load environment map {}: {}", map.path, e)
+                        })
+                        .set_intensity(map.intensity)
+                        .set_rotation(map.rotation);
+                    environments.push((map.clone(), Arc::new(environment)));
+                }
+                Action::DefineMaterial {
+                    bump: Some(BumpDef::NormalMap { path, .. } | BumpDef::HeightMap { path, .. }),
+                    ..
+                } => {
+                    if textures.iter().any(|(loaded, _)| loaded == path) {
+                        continue;
+                    }
+                    let texture = Texture::load(path)
+                        .unwrap_or_else(|e| panic!("Can't load bump map {}: {}", path, e));
+                    textures.push((path.clone(), Arc::new(texture)));
+                }
+                _ => {}
+            }
+        }
+        Assets {
+            environments,
+            textures,
+        }
+    }
+
+    /// Returns the loaded environment map of a definition
+    fn environment(&self, map: &EnvironmentMapDef) -> Arc<EnvironmentMap> {
+        self.environments
+            .iter()
+            .find(|(def, _)| def == map)
+            .map(|(_, environment)| Arc::clone(environment))
+            .expect("Environment map not loaded")
+    }
+
+    /// Returns the loaded bump texture of a path
+    fn texture(&self, path: &str) -> Arc<Texture> {
+        self.textures
+            .iter()
+            .find(|(loaded, _)| loaded == path)
+            .map(|(_, texture)| Arc::clone(texture))
+            .expect("Bump map not loaded")
+    }
+}
+
+/// Generates a frame from animations and assets collected once for the whole sequence
+///
+/// Simulations only run when the animations are collected and images are only read when
+/// the assets are, so rendering many frames should reuse them.
+pub fn generate_animated_frame(
+    data: &Data,
+    animations: &Animations,
+    assets: &Assets,
+    time: f64,
+) -> (World, Camera) {
     let mut camera: Option<Camera> = None;
     let mut light: Option<PointLight> = None;
     let mut objects = Vec::<Shape>::new();
@@ -33,33 +132,104 @@ pub fn generate_world(data: Data) -> (World, Camera) {
                 from,
                 to,
                 up,
+                samples,
+                aperture,
+                focal_distance,
+                aperture_blades,
+                aperture_rotation,
+                projection,
+                view_width,
+                shutter_open,
+                shutter_close,
+                integrator,
+                max_depth,
+                sequence,
+                adaptive,
             } => {
-                camera = {
-                    Some(
-                        Camera::new(height, width, field_of_view)
-                            .transform(Matrix::view_transform(from, to, up)),
-                    )
-                }
+                let target = AnimationTarget::Camera;
+                let field_of_view =
+                    animations.scalar(target, None, "field-of-view", time, *field_of_view);
+                let from = animations.point(target, None, "from", time, *from);
+                let to = animations.point(target, None, "to", time, *to);
+                let up = animations.vector(target, None, "up", time, *up);
+                let projection = match projection {
+                    ProjectionType::Perspective => Projection::Perspective,
+                    ProjectionType::Orthographic => Projection::Orthographic(*view_width),
+                    ProjectionType::Fisheye => Projection::Fisheye,
+                    ProjectionType::Equirectangular => Projection::Equirectangular,
+                };
+                let integrator = match integrator {
+                    IntegratorType::Whitted => Integrator::Whitted,
+                    IntegratorType::Path => Integrator::PathTraced(*max_depth),
+                    IntegratorType::Bdpt => Integrator::Bidirectional(*max_depth),
+                    IntegratorType::Ao => Integrator::AmbientOcclusion(
+                        generate_ambient_occlusion(data).unwrap_or_default(),
+                    ),
+                };
+                camera =
+                    {
+                        Some(
+                            Camera::new(*width, *height, field_of_view)
+                                .set_samples(*samples)
+                                .set_aperture(*aperture, *focal_distance)
+                                .set_aperture_shape(*aperture_blades, *aperture_rotation)
+                                .set_projection(projection)
+                                .set_shutter(*shutter_open, *shutter_close)
+                                .set_integrator(integrator)
+                                .set_sequence(*sequence)
+                                .set_adaptive(adaptive.map(|a| {
+                                    Adaptive::new(a.min_samples, a.max_samples, a.threshold)
+                                }))
+                                .transform(Matrix::view_transform(from, to, up)),
+                        )
+                    }
+            }
+            Action::AddLight {
+                at,
+                intensity,
+                transparent_shadows,
+            } => {
+                let target = AnimationTarget::Light;
+                let at = animations.point(target, None, "at", time, *at);
+                let intensity = animations.color(target, None, "intensity", time, *intensity);
+                light = Some(PointLight::new(at, intensity));
+                w.transparent_shadows = *transparent_shadows;
             }
-            Action::AddLight { at, intensity } => light = Some(PointLight::new(at, intensity)),
             Action::AddObject {
+                name,
                 object_type,
                 material,
                 transform,
+                end_transform,
+                motion,
+                casts_shadow,
+                visible_to_camera,
+                visible_in_reflections,
+                interior,
             } => {
-                let mut object = Shape::new(object_type);
-
-                for transformation in transform {
-                    object = match transformation {
-                        TransformActions::Scale(n) => object.scale(n[0], n[1], n[2]),
-                        TransformActions::Translate(n) => object.translate(n[0], n[1], n[2]),
-                        TransformActions::RotateX(deg) => object.rotate_x(deg),
-                        TransformActions::RotateY(deg) => object.rotate_y(deg),
-                        TransformActions::RotateZ(deg) => object.rotate_z(deg),
-                    }
+                let animation = match name {
+                    Some(name) => animations.object_transform(name, time),
+                    None => IDENTITY_MATRIX,
+                };
+                let mut object = Shape::new(*object_type)
+                    .transform(animation * build_transform(transform))
+                    .set_casts_shadow(*casts_shadow)
+                    .set_visible(*visible_to_camera, *visible_in_reflections)
+                    .set_interior(interior.as_ref().map(build_medium));
+
+                if let Some(motion) = motion {
+                    let keyframes: Vec<(f64, Matrix)> = motion
+                        .iter()
+                        .map(|k| (k.time, animation * build_transform(&k.transform)))
+                        .collect();
+                    object = object.set_keyframes(&keyframes);
+                } else if let Some(end_transform) = end_transform {
+                    let (open, close) = shutter(data);
+                    object =
+                        object.set_motion(animation * build_transform(end_transform), open, close);
                 }
 
-                object.material = *definitions.get(&material).expect("No Material");
+                object.material = definitions.get(material).expect("No Material").clone();
 
                 objects.push(object);
             }
@@ -73,32 +243,664 @@ pub fn generate_world(data: Data) -> (World, Camera) {
                 reflect,
                 transparent,
                 refractive_index,
+                model,
+                roughness,
+                metallic,
+                specular_tint,
+                absorption,
+                density,
+                dispersion,
+                bump,
             } => {
+                let target = AnimationTarget::Material;
+                let key = Some(name.as_str());
+                let scalar =
+                    |property, value| animations.scalar(target, key, property, time, value);
+                let shading = match model {
+                    MaterialModel::Phong => Shading::Phong,
+                    MaterialModel::Microfacet => Shading::Microfacet(Microfacet::new(
+                        scalar("roughness", *roughness),
+                        scalar("metallic", *metallic),
+                        scalar("specular-tint", *specular_tint),
+                    )),
+                };
                 definitions.insert(
-                    name,
-                    Material::new(color)
-                        .set_specular(specular)
-                        .set_ambient(ambient)
-                        .set_diffuse(diffuse)
-                        .set_shine(shine)
-                        .set_reflect(reflect)
-                        .set_transparency(transparent)
-                        .set_refractive_index(refractive_index),
+                    name.clone(),
+                    Material::new(animations.color(target, key, "color", time, *color))
+                        .set_specular(scalar("specular", *specular))
+                        .set_ambient(scalar("ambient", *ambient))
+                        .set_diffuse(scalar("diffuse", *diffuse))
+                        .set_shine(scalar("shine", *shine))
+                        .set_reflect(scalar("reflect", *reflect))
+                        .set_transparency(scalar("transparent", *transparent))
+                        .set_refractive_index(scalar("refractive-index", *refractive_index))
+                        .set_shading(shading)
+                        .set_absorption(
+                            animations.color(target, key, "absorption", time, *absorption),
+                            scalar("density", *density),
+                        )
+                        .set_dispersion(dispersion.map(|d| match d {
+                            DispersionDef::Cauchy([a, b]) => Dispersion::Cauchy(a, b),
+                            DispersionDef::Sellmeier { b, c } => Dispersion::Sellmeier(b, c),
+                        }))
+                        .set_bump(bump.as_ref().map(|bump| build_bump(bump, assets))),
                 );
             }
+            Action::SetBackground { background } => {
+                w.background = build_background(background, assets);
+            }
+            Action::SetAmbientOcclusion { .. } => {
+                w.ambient_occlusion = generate_ambient_occlusion(data);
+            }
+            Action::SetFog { fog } => {
+                w.fog = Some(build_medium(fog));
+            }
+            Action::Animate { .. }
+            | Action::Simulate { .. }
+            | Action::SetOutput { .. }
+            | Action::SetCaustics { .. } => {}
         }
     }
 
     w.light = light;
     w.objects = objects;
+    if let Some(caustics) = generate_caustics(data) {
+        w.caustics = Some(Arc::new(PhotonMap::build(&w, caustics)));
+    }
     (w, camera.expect("No camera!"))
 }
 
+/// Returns the output pipeline set in the scene, the last `set output` block wins
+pub fn generate_output(data: &Data) -> Output {
+    data.iter()
+        .fold(Output::default(), |output, action| match action {
+            Action::SetOutput {
+                tone_map,
+                exposure,
+                srgb,
+                ..
+            } => Output::default()
+                .set_tone_map(*tone_map)
+                .set_exposure(*exposure)
+                .set_srgb(*srgb),
+            _ => output,
+        })
+}
+
+/// Returns the ambient occlusion set in the scene, the last `ambient-occlusion` block wins
+pub fn generate_ambient_occlusion(data: &Data) -> Option<AmbientOcclusion> {
+    data.iter().fold(None, |ao, action| match action {
+        Action::SetAmbientOcclusion { ambient_occlusion } => Some(AmbientOcclusion::new(
+            ambient_occlusion.samples,
+            ambient_occlusion.distance,
+        )),
+        _ => ao,
+    })
+}
+
+/// Returns the shutter interval of the scene camera, end transforms span it
+fn shutter(data: &Data) -> (f64, f64) {
+    data.iter().fold((0., 0.), |shutter, action| match action {
+        Action::AddCamera {
+            shutter_open,
+            shutter_close,
+            ..
+        } => (*shutter_open, *shutter_close),
+        _ => shutter,
+    })
+}
+
+/// Returns the photon mapping set in the scene, the last `caustics` block wins
+pub fn generate_caustics(data: &Data) -> Option<Caustics> {
+    data.iter().fold(None, |caustics, action| match action {
+        Action::SetCaustics { caustics } => Some(Caustics::new(
+            caustics.photons,
+            caustics.nearest,
+            caustics.radius,
+        )),
+        _ => caustics,
+    })
+}
+
+/// Creates a participating medium
+fn build_medium(medium: &MediumDef) -> Medium {
+    let phase = match medium.phase {
+        PhaseDef::Isotropic => Phase::Isotropic,
+        PhaseDef::HenyeyGreenstein(g) => Phase::HenyeyGreenstein(g),
+    };
+    Medium::new(medium.absorption, medium.scattering).set_phase(phase)
+}
+
+/// Creates the world background, environment maps come from the loaded assets
+fn build_background(background: &BackgroundDef, assets: &Assets) -> Background {
+    let color = |c: &[i64; 3]| QuantColor::new(c[0], c[1], c[2]);
+    match background {
+        BackgroundDef::Transparent => Background::Transparent,
+        BackgroundDef::Color(c) => Background::Color(color(c)),
+        BackgroundDef::Gradient(top, bottom) => Background::Gradient(color(top), color(bottom)),
+        BackgroundDef::Map(map) => Background::Map(assets.environment(map)),
+    }
+}
+
+/// Creates the normal perturbation of a material, maps come from the loaded assets
+fn build_bump(bump: &BumpDef, assets: &Assets) -> Bump {
+    let load = |path: &String| assets.texture(path);
+    match bump {
+        BumpDef::Noise { strength, scale } => Bump::Noise {
+            strength: *strength,
+            scale: *scale,
+        },
+        BumpDef::NormalMap { path, strength } => Bump::NormalMap(load(path), *strength),
+        BumpDef::HeightMap { path, strength } => Bump::HeightMap(load(path), *strength),
+    }
+}
+
+/// Combines a list of transform actions into a single matrix, first action is applied last
+pub(crate) fn build_transform(actions: &[TransformActions]) -> Matrix {
+    actions
+        .iter()
+        .fold(IDENTITY_MATRIX, |matrix, action| match *action {
+            TransformActions::Scale(n) => matrix * Matrix::scale(n[0], n[1], n[2]),
+            TransformActions::Translate(n) => matrix * Matrix::translate(n[0], n[1], n[2]),
+            TransformActions::RotateX(r) => matrix * Matrix::rotate_x(r),
+            TransformActions::RotateY(r) => matrix * Matrix::rotate_y(r),
+            TransformActions::RotateZ(r) => matrix * Matrix::rotate_z(r),
+        })
+}
+
 #[cfg(test)]
 mod test {
-    // use super::*;
+    use super::*;
+    use raytracer::render::ToneMap;
+    use raytracer::units::sampling::Sequence;
+    use raytracer::units::tuple::{Point, Tuple};
     #[test]
     fn read_file() {
-        let f = super::read_file("./scene.yaml");
+        let _f = super::read_file("./scene.yaml");
+    }
+
+    #[test]
+    fn generate_camera() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+  projection: orthographic
+  view-width: 8
+  aperture: 0.1
+  focal-distance: 5
+  integrator: path
+  max-depth: 3
+  sequence: sobol
+  adaptive:
+    max-samples: 64
+",
+        )
+        .unwrap();
+        let (_, camera) = generate_world(data);
+        assert_eq!(camera.integrator, Integrator::PathTraced(3));
+        assert_eq!(camera.sequence, Sequence::Sobol);
+        assert_eq!(camera.adaptive, Some(Adaptive::new(16, 64, 0.01)));
+        assert_eq!(camera.hsize, 40);
+        assert_eq!(camera.vsize, 20);
+        assert_eq!(camera.projection, Projection::Orthographic(8.));
+        assert_eq!(camera.aperture, 0.1);
+        assert_eq!(camera.focal_distance, 5.);
+    }
+
+    #[test]
+    fn generate_motion() {
+        let data: Data = serde_yaml::from_str(
+            "
+- define material:
+  name: m
+  color: [255, 0, 0]
+- add object:
+  type: sphere
+  material: m
+  transform:
+    - translate: [1, 0, 0]
+  end-transform:
+    - translate: [3, 0, 0]
+- add object:
+  type: sphere
+  material: m
+  transform: []
+  motion:
+    - time: 2
+      transform:
+        - translate: [0, 0, 0]
+    - time: 3
+      transform:
+        - translate: [0, 2, 0]
+    - time: 4
+      transform:
+        - translate: [2, 2, 0]
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+  shutter-open: 2
+  shutter-close: 4
+",
+        )
+        .unwrap();
+        let (world, camera) = generate_world(data);
+        assert_eq!(camera.shutter_close, 4.);
+
+        // The end transform spans the shutter of the camera, wherever it is defined
+        let motion = world.objects[0].motion.as_ref().unwrap();
+        assert_eq!(motion.start, Matrix::translate(1, 0, 0));
+        assert_eq!(motion.end, Matrix::translate(3, 0, 0));
+        assert_eq!(motion.interpolate(3.), Matrix::translate(2, 0, 0));
+
+        // Keyframes move through every transform
+        let motion = world.objects[1].motion.as_ref().unwrap();
+        assert_eq!(motion.interpolate(2.5), Matrix::translate(0, 1, 0));
+        assert_eq!(motion.interpolate(3.5), Matrix::translate(1, 2, 0));
+    }
+
+    #[test]
+    fn generate_shadows() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- add light:
+  at: [0, 10, 0]
+  intensity: [255, 255, 255]
+  transparent-shadows: true
+- define material:
+  name: m
+  color: [255, 0, 0]
+- add object:
+  type: sphere
+  material: m
+  transform: []
+  casts-shadow: false
+  visible-to-camera: false
+- add object:
+  type: plane
+  material: m
+  transform: []
+",
+        )
+        .unwrap();
+        let (world, _) = generate_world(data);
+        assert!(world.transparent_shadows);
+        let sphere = &world.objects[0];
+        assert!(!sphere.casts_shadow && !sphere.visible_to_camera && sphere.visible_in_secondary);
+        let plane = &world.objects[1];
+        assert!(plane.casts_shadow && plane.visible_to_camera && plane.visible_in_secondary);
+    }
+
+    #[test]
+    fn generate_frame() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- add light:
+  at: [0, 10, 0]
+  intensity: [255, 255, 255]
+- define material:
+  name: m
+  color: [255, 0, 0]
+- add object:
+  name: ball
+  type: sphere
+  material: m
+  transform:
+    - scale: [2, 2, 2]
+- animate: object
+  name: ball
+  property: translate
+  keyframes:
+    - time: 0
+      value: [0, 0, 0]
+    - time: 1
+      value: [4, 0, 0]
+- animate: material
+  name: m
+  property: reflect
+  keyframes:
+    - time: 0
+      value: 0
+    - time: 1
+      value: 1
+- animate: light
+  property: at
+  keyframes:
+    - time: 0
+      value: [0, 10, 0]
+    - time: 1
+      value: [10, 10, 0]
+- animate: camera
+  property: field-of-view
+  keyframes:
+    - time: 0
+      value: 1
+    - time: 1
+      value: 2
+",
+        )
+        .unwrap();
+        let (world, camera) = super::generate_frame(&data, 0.5);
+        assert_eq!(camera.field_of_view, 1.5);
+        assert_eq!(world.light.unwrap().position, Point::new(5, 10, 0));
+        let ball = &world.objects[0];
+        assert_eq!(ball.material.reflect, 0.5);
+        assert_eq!(
+            Point::from(ball.transformation_matrix * Point::new(1, 0, 0)),
+            Point::new(4, 0, 0)
+        );
+    }
+
+    #[test]
+    fn generate_material() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- define material:
+  name: gold
+  color: [255, 200, 80]
+  bump:
+    noise:
+      strength: 0.2
+  model: microfacet
+  roughness: 0.3
+  metallic: 1
+- define material:
+  name: plastic
+  color: [255, 0, 0]
+  absorption: [255, 128, 0]
+  density: 0.5
+  dispersion:
+    cauchy: [1.5, 0.004]
+- add object:
+  type: sphere
+  material: gold
+  transform: []
+- add object:
+  type: sphere
+  material: plastic
+  transform: []
+",
+        )
+        .unwrap();
+        let (world, _) = generate_world(data);
+        assert_eq!(
+            world.objects[0].material.shading,
+            Shading::Microfacet(Microfacet::new(0.3, 1., 0.))
+        );
+        assert_eq!(world.objects[1].material.shading, Shading::Phong);
+        assert_eq!(
+            world.objects[0].material.bump,
+            Some(Bump::Noise {
+                strength: 0.2,
+                scale: 1.
+            })
+        );
+        assert_eq!(world.objects[1].material.bump, None);
+        assert_eq!(
+            world.objects[1].material.absorption,
+            QuantColor::new(255, 128, 0)
+        );
+        assert_eq!(world.objects[1].material.density, 0.5);
+        assert_eq!(world.objects[0].material.density, 0.);
+        assert_eq!(
+            world.objects[1].material.dispersion,
+            Some(Dispersion::Cauchy(1.5, 0.004))
+        );
+    }
+
+    #[test]
+    fn generate_ambient_occlusion() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+  integrator: ao
+- ambient-occlusion:
+    samples: 8
+    distance: 2.5
+",
+        )
+        .unwrap();
+        let (world, camera) = generate_world(data);
+        let ao = AmbientOcclusion::new(8, 2.5);
+        assert_eq!(world.ambient_occlusion, Some(ao));
+        assert_eq!(camera.integrator, Integrator::AmbientOcclusion(ao));
+        assert_eq!(super::generate_ambient_occlusion(&Vec::new()), None);
+    }
+
+    #[test]
+    fn generate_fog() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- fog:
+    scattering: 0.05
+- define material:
+  name: smoke
+  color: [255, 255, 255]
+- add object:
+  type: sphere
+  material: smoke
+  transform: []
+  interior:
+    absorption: 0.5
+    scattering: 1.5
+    phase:
+      henyey-greenstein: 0.6
+",
+        )
+        .unwrap();
+        let (world, _) = generate_world(data);
+        assert_eq!(world.fog, Some(Medium::new(0., 0.05)));
+        assert_eq!(
+            world.objects[0].interior,
+            Some(Medium::new(0.5, 1.5).set_phase(Phase::HenyeyGreenstein(0.6)))
+        );
+    }
+
+    #[test]
+    fn generate_caustics() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- add light:
+  at: [0, 10, 0]
+  intensity: [255, 255, 255]
+- caustics:
+    photons: 500
+    radius: 0.5
+- define material:
+  name: glass
+  color: [0, 0, 0]
+  transparent: 1.0
+  refractive_index: 1.5
+- add object:
+  type: sphere
+  material: glass
+  transform:
+    - translate: [0, 2, 0]
+- add object:
+  type: plane
+  material: glass
+  transform: []
+",
+        )
+        .unwrap();
+        assert_eq!(
+            super::generate_caustics(&data),
+            Some(Caustics::new(500, 50, 0.5))
+        );
+        let (world, _) = generate_world(data);
+        assert!(world.caustics.is_some());
+        assert_eq!(super::generate_caustics(&Vec::new()), None);
+    }
+
+    #[test]
+    fn generate_output() {
+        let data: Data = serde_yaml::from_str(
+            "
+- set output:
+  tone-map: aces
+  exposure: 1.5
+  srgb: true
+",
+        )
+        .unwrap();
+        let output = super::generate_output(&data);
+        assert_eq!(output.tone_map, ToneMap::Aces);
+        assert_eq!(output.exposure, 1.5);
+        assert!(output.srgb);
+        assert_eq!(super::generate_output(&Vec::new()), Output::default());
+
+        // Every field of the block is optional
+        let data: Data = serde_yaml::from_str("- set output:\n  exposure: 1\n").unwrap();
+        let output = super::generate_output(&data);
+        assert_eq!(output.tone_map, ToneMap::Clamp);
+        assert_eq!(output.exposure, 1.);
+    }
+
+    #[test]
+    fn generate_background() {
+        let data: Data = serde_yaml::from_str(
+            "
+- add camera:
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+- background:
+    gradient: [[255, 255, 255], [0, 0, 128]]
+",
+        )
+        .unwrap();
+        let (world, _) = generate_world(data);
+        assert_eq!(
+            world.background,
+            Background::Gradient(QuantColor::new(255, 255, 255), QuantColor::new(0, 0, 128))
+        );
+
+        let data: Data = serde_yaml::from_str("- background: transparent").unwrap();
+        assert!(matches!(data[0], Action::SetBackground { .. }));
+
+        // Environment maps are loaded from HDR images
+        let mut canvas = raytracer::render::Canvas::new(4, 2);
+        canvas.write_pixel(0, 0, QuantColor::new(510, 510, 510));
+        canvas.write_hdr("../target/environment.hdr");
+        let data: Data = serde_yaml::from_str(
+            "
+- background:
+    map:
+      path: ../target/environment.hdr
+      intensity: 2
+",
+        )
+        .unwrap();
+        let assets = Assets::from_data(&data);
+        match data[0] {
+            Action::SetBackground { ref background } => {
+                match build_background(background, &assets) {
+                    Background::Map(map) => {
+                        assert_eq!(map.intensity, 2.);
+                        assert_eq!(map.pixels[0], [2., 2., 2.]);
+                    }
+                    other => panic!("Expected a map, got {:?}", other),
+                }
+            }
+            _ => panic!("Expected a background"),
+        }
+
+        // Every frame shares the map loaded once for the scene
+        let (first, second) = match data[0] {
+            Action::SetBackground { ref background } => (
+                build_background(background, &assets),
+                build_background(background, &assets),
+            ),
+            _ => panic!("Expected a background"),
+        };
+        match (first, second) {
+            (Background::Map(a), Background::Map(b)) => assert!(Arc::ptr_eq(&a, &b)),
+            _ => panic!("Expected maps"),
+        }
+
+        // Bump maps are loaded once too and shared by the materials of every frame
+        raytracer::render::Canvas::new(2, 2).write_png("../target/normal_map.png");
+        let data: Data = serde_yaml::from_str(
+            "
+- define material:
+  name: tiles
+  color: [255, 255, 255]
+  bump:
+    normal-map:
+      path: ../target/normal_map.png
+- define material:
+  name: bricks
+  color: [255, 255, 255]
+  bump:
+    normal-map:
+      path: ../target/normal_map.png
+",
+        )
+        .unwrap();
+        let assets = Assets::from_data(&data);
+        assert_eq!(assets.textures.len(), 1);
+        let texture = |data: &Action| match data {
+            Action::DefineMaterial {
+                bump: Some(bump), ..
+            } => match build_bump(bump, &assets) {
+                Bump::NormalMap(texture, _) => texture,
+                other => panic!("Expected a normal map, got {:?}", other),
+            },
+            _ => panic!("Expected a material"),
+        };
+        assert!(Arc::ptr_eq(&texture(&data[0]), &texture(&data[1])));
     }
 }
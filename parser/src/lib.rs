@@ -1,3 +1,4 @@
+pub mod animation;
 pub mod generator;
 pub mod types;
 pub use types::Data;
@@ -1,11 +1,9 @@
 use raytracer::{
     self,
-    units::tuple::{Point, Vector},
-    units::{
-        color::{QuantColor, WHITE},
-        objects::ObjectType,
-    },
-    world::patterns::PatternType,
+    render::ToneMap,
+    units::tuple::{Point, Tuple, Vector},
+    units::{color::QuantColor, objects::ObjectType, sampling::Sequence},
+    world::animation::{Easing, Interpolate},
 };
 use serde::Deserialize;
 
@@ -24,6 +22,32 @@ pub enum Action {
         to: Point,
         #[serde(with = "VectorDef")]
         up: Vector,
+        #[serde(default = "default_samples")]
+        samples: usize,
+        #[serde(default)]
+        aperture: f64,
+        #[serde(rename = "focal-distance", default = "default_focal_distance")]
+        focal_distance: f64,
+        #[serde(rename = "aperture-blades", default)]
+        aperture_blades: usize,
+        #[serde(rename = "aperture-rotation", default)]
+        aperture_rotation: f64,
+        #[serde(default = "default_projection")]
+        projection: ProjectionType,
+        #[serde(rename = "view-width", default = "default_view_width")]
+        view_width: f64,
+        #[serde(rename = "shutter-open", default)]
+        shutter_open: f64,
+        #[serde(rename = "shutter-close", default)]
+        shutter_close: f64,
+        #[serde(default = "default_integrator")]
+        integrator: IntegratorType,
+        #[serde(rename = "max-depth", default = "default_max_depth")]
+        max_depth: usize,
+        #[serde(with = "SequenceDef", default)]
+        sequence: Sequence,
+        #[serde(default)]
+        adaptive: Option<AdaptiveDef>,
     },
 
     #[serde(rename = "add light")]
@@ -32,14 +56,30 @@ pub enum Action {
         at: Point,
         #[serde(with = "QuantColorDef")]
         intensity: QuantColor,
+        #[serde(rename = "transparent-shadows", default)]
+        transparent_shadows: bool,
     },
 
     #[serde(rename = "add object")]
     AddObject {
+        #[serde(default)]
+        name: Option<String>,
         #[serde(rename = "type", with = "ObjectTypeDef")]
         object_type: ObjectType,
         material: String,
         transform: Vec<TransformActions>,
+        #[serde(rename = "end-transform", default)]
+        end_transform: Option<Vec<TransformActions>>,
+        #[serde(default)]
+        motion: Option<Vec<MotionKeyframeDef>>,
+        #[serde(rename = "casts-shadow", default = "default_true")]
+        casts_shadow: bool,
+        #[serde(rename = "visible-to-camera", default = "default_true")]
+        visible_to_camera: bool,
+        #[serde(rename = "visible-in-reflections", default = "default_true")]
+        visible_in_reflections: bool,
+        #[serde(default)]
+        interior: Option<MediumDef>,
     },
 
     #[serde(rename = "define material")]
@@ -61,7 +101,176 @@ pub enum Action {
         transparent: f64,
         #[serde(default = "default_refractive_index")]
         refractive_index: f64,
+        #[serde(default = "default_model")]
+        model: MaterialModel,
+        #[serde(default = "default_roughness")]
+        roughness: f64,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(rename = "specular-tint", default)]
+        specular_tint: f64,
+        #[serde(with = "QuantColorDef", default = "default_absorption")]
+        absorption: QuantColor,
+        #[serde(default)]
+        density: f64,
+        #[serde(default)]
+        dispersion: Option<DispersionDef>,
+        #[serde(default)]
+        bump: Option<BumpDef>,
+    },
+
+    #[serde(rename = "animate")]
+    Animate {
+        animate: AnimationTarget,
+        #[serde(default)]
+        name: Option<String>,
+        property: String,
+        keyframes: Vec<KeyframeDef>,
+    },
+
+    #[serde(rename = "set output")]
+    SetOutput {
+        /// the `set output` key itself, it tells the block apart as every other field is optional
+        #[serde(rename = "set output")]
+        set_output: (),
+        #[serde(rename = "tone-map", with = "ToneMapDef", default)]
+        tone_map: ToneMap,
+        #[serde(default)]
+        exposure: f64,
+        #[serde(default)]
+        srgb: bool,
     },
+
+    #[serde(rename = "background")]
+    SetBackground { background: BackgroundDef },
+
+    #[serde(rename = "ambient-occlusion")]
+    SetAmbientOcclusion {
+        #[serde(rename = "ambient-occlusion")]
+        ambient_occlusion: AmbientOcclusionDef,
+    },
+
+    #[serde(rename = "fog")]
+    SetFog { fog: MediumDef },
+
+    #[serde(rename = "caustics")]
+    SetCaustics { caustics: CausticsDef },
+
+    #[serde(rename = "simulate")]
+    Simulate {
+        simulate: Vec<BodyDef>,
+        duration: f64,
+        #[serde(default = "default_timestep")]
+        timestep: f64,
+        #[serde(default = "default_gravity", with = "VectorDef")]
+        gravity: Vector,
+        #[serde(default = "default_zero_vector", with = "VectorDef")]
+        wind: Vector,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_samples() -> usize {
+    1
+}
+
+fn default_focal_distance() -> f64 {
+    1.
+}
+
+fn default_projection() -> ProjectionType {
+    ProjectionType::Perspective
+}
+
+fn default_integrator() -> IntegratorType {
+    IntegratorType::Whitted
+}
+
+fn default_model() -> MaterialModel {
+    MaterialModel::Phong
+}
+
+fn default_absorption() -> QuantColor {
+    QuantColor::new(255, 255, 255)
+}
+
+fn default_bump_scale() -> f64 {
+    1.
+}
+
+fn default_bump_strength() -> f64 {
+    1.
+}
+
+fn default_roughness() -> f64 {
+    0.5
+}
+
+fn default_ao_samples() -> usize {
+    16
+}
+
+fn default_ao_distance() -> f64 {
+    1.
+}
+
+fn default_min_samples() -> usize {
+    16
+}
+
+fn default_max_samples() -> usize {
+    256
+}
+
+fn default_threshold() -> f64 {
+    0.01
+}
+
+fn default_photons() -> usize {
+    100_000
+}
+
+fn default_photon_nearest() -> usize {
+    50
+}
+
+fn default_photon_radius() -> f64 {
+    0.25
+}
+
+fn default_max_depth() -> usize {
+    8
+}
+
+fn default_map_intensity() -> f64 {
+    1.
+}
+
+fn default_view_width() -> f64 {
+    1.
+}
+
+fn default_timestep() -> f64 {
+    1. / 240.
+}
+
+fn default_gravity() -> Vector {
+    Vector::new(0., -9.81, 0.)
+}
+
+fn default_zero_vector() -> Vector {
+    Vector::new(0, 0, 0)
+}
+
+fn default_mass() -> f64 {
+    1.
+}
+
+fn default_restitution() -> f64 {
+    1.
 }
 
 fn default_ambient() -> f64 {
@@ -96,6 +305,216 @@ pub enum ObjectTypeDef {
     Sphere,
     Plane,
 }
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectionType {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(remote = "Sequence", rename_all = "lowercase")]
+pub enum SequenceDef {
+    Random,
+    Halton,
+    Sobol,
+    R2,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(remote = "ToneMap", rename_all = "lowercase")]
+pub enum ToneMapDef {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+/// Background of the scene, gradients go from the top color to the bottom color
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundDef {
+    Transparent,
+    Color([i64; 3]),
+    Gradient([i64; 3], [i64; 3]),
+    Map(EnvironmentMapDef),
+}
+
+/// Equirectangular environment map loaded from an HDR or 8 bit image
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EnvironmentMapDef {
+    pub path: String,
+    #[serde(default = "default_map_intensity")]
+    pub intensity: f64,
+    #[serde(default)]
+    pub rotation: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegratorType {
+    Whitted,
+    Path,
+    Bdpt,
+    Ao,
+}
+
+/// Ambient occlusion darkening the ambient light, also used by the `ao` integrator
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct AmbientOcclusionDef {
+    #[serde(default = "default_ao_samples")]
+    pub samples: usize,
+    #[serde(default = "default_ao_distance")]
+    pub distance: f64,
+}
+
+/// Adaptive sampling of the camera, it replaces the fixed number of samples
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct AdaptiveDef {
+    #[serde(rename = "min-samples", default = "default_min_samples")]
+    pub min_samples: usize,
+    #[serde(rename = "max-samples", default = "default_max_samples")]
+    pub max_samples: usize,
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+}
+
+/// Photon mapping of light focused by mirrors and glass
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct CausticsDef {
+    #[serde(default = "default_photons")]
+    pub photons: usize,
+    #[serde(default = "default_photon_nearest")]
+    pub nearest: usize,
+    #[serde(default = "default_photon_radius")]
+    pub radius: f64,
+}
+
+/// Homogeneous participating medium, coefficients are per unit of distance
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct MediumDef {
+    #[serde(default)]
+    pub absorption: f64,
+    #[serde(default)]
+    pub scattering: f64,
+    #[serde(default)]
+    pub phase: PhaseDef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PhaseDef {
+    #[default]
+    Isotropic,
+    HenyeyGreenstein(f64),
+}
+
+/// Perturbation of the shading normal, maps are read with the object's texture coordinates
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BumpDef {
+    Noise {
+        strength: f64,
+        #[serde(default = "default_bump_scale")]
+        scale: f64,
+    },
+    NormalMap {
+        path: String,
+        #[serde(default = "default_bump_strength")]
+        strength: f64,
+    },
+    HeightMap {
+        path: String,
+        #[serde(default = "default_bump_strength")]
+        strength: f64,
+    },
+}
+
+/// Wavelength dependent refractive index, coefficients are for wavelengths in micrometres
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DispersionDef {
+    Cauchy([f64; 2]),
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaterialModel {
+    Phong,
+    Microfacet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationTarget {
+    Camera,
+    Light,
+    Object,
+    Material,
+}
+
+/// Transformation of a moving object at a time in the units of the camera shutter
+#[derive(Debug, Deserialize)]
+pub struct MotionKeyframeDef {
+    pub time: f64,
+    pub transform: Vec<TransformActions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyframeDef {
+    pub time: f64,
+    pub value: KeyframeValue,
+    #[serde(default, with = "EasingDef")]
+    pub easing: Easing,
+}
+
+/// Named sphere that is moved by the physics simulation
+#[derive(Debug, Deserialize)]
+pub struct BodyDef {
+    pub object: String,
+    #[serde(default = "default_zero_vector", with = "VectorDef")]
+    pub velocity: Vector,
+    #[serde(default = "default_mass")]
+    pub mass: f64,
+    #[serde(default = "default_restitution")]
+    pub restitution: f64,
+    #[serde(default)]
+    pub friction: f64,
+}
+
+/// Animated value, either a single number or a triple such as a point or a color
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum KeyframeValue {
+    Scalar(f64),
+    Triple([f64; 3]),
+}
+
+impl Interpolate for KeyframeValue {
+    fn interpolate(&self, other: &KeyframeValue, t: f64) -> KeyframeValue {
+        match (self, other) {
+            (KeyframeValue::Scalar(a), KeyframeValue::Scalar(b)) => {
+                KeyframeValue::Scalar(a.interpolate(b, t))
+            }
+            (KeyframeValue::Triple(a), KeyframeValue::Triple(b)) => {
+                KeyframeValue::Triple(a.interpolate(b, t))
+            }
+            // Mixed tracks are rejected when animations are read, hold the first value
+            (a, _) => *a,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(remote = "Easing", rename_all = "lowercase")]
+pub enum EasingDef {
+    Linear,
+    Step,
+    Bezier(f64, f64, f64, f64),
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(remote = "Point", rename_all = "lowercase", from = "[f64; 3]")]
 pub struct PointDef {
@@ -1,4 +1,4 @@
-use raytracer::render::{Camera, Canvas, World};
+use raytracer::render::{Camera, Canvas, RenderJob, World};
 use raytracer::units::color::{QuantColor, BLACK, RED, WHITE};
 use raytracer::units::objects::{ObjectType, Shape};
 use raytracer::units::tuple::{Point, Tuple, Vector};
@@ -7,10 +7,8 @@ use raytracer::world::patterns::{Pattern, PatternType};
 use raytracer::world::{tick, Environment, Material, PointLight, Projectile};
 use std::env;
 use std::f64::consts;
-use std::time::Instant;
 
 fn main() {
-    let start = Instant::now();
     let args: Vec<String> = env::args().collect();
 
     let (hsize, vsize, size): (usize, usize, usize) = if cfg!(debug_assertions) {
@@ -28,8 +26,16 @@ fn main() {
         "ok" => refraction_render(hsize, vsize),
         _ => println!("Command not recognized!"),
     }
-    let duration = start.elapsed();
-    println!("Execution took: {:?}", duration);
+}
+
+/// Renders the world, showing a progress bar while it runs
+fn render(camera: &Camera, world: World) -> Canvas {
+    let job = RenderJob::new().on_progress(|progress| eprint!("\r{}", progress.bar(30)));
+    let (canvas, _) = camera
+        .render_job(world, &[], &job)
+        .expect("Render was cancelled");
+    eprintln!();
+    canvas
 }
 
 fn simulate_projectile() {
@@ -114,9 +120,8 @@ fn draw_shadow(size: usize) {
                 let eyev = -r.direction;
                 let color = hit
                     .object
-                    .lightning(light, hit_point, eyev, hit_normal, false)
-                    .clamp();
-                canvas.write_pixel(x, y, color);
+                    .lightning(light, hit_point, eyev, hit_normal, false);
+                canvas.write_radiance(x, y, color);
             }
         }
     }
@@ -226,7 +231,7 @@ fn render_plane_world(hsize: usize, vsize: usize) {
         .set_light(Some(PointLight::new(Point::new(5, 5, 10), WHITE)))
         .set_objects(vec![left, right, middle, floor, center_wall, side_wall]);
 
-    let canvas = camera.render(world);
+    let canvas = render(&camera, world);
 
     canvas.write_png("./images/render_plane_world.png");
 }
@@ -277,18 +282,18 @@ fn render_reflect_scene(hsize: usize, vsize: usize) {
         .rotate_y(consts::FRAC_PI_2)
         .rotate_z(consts::FRAC_PI_2)
         .translate(-5, 0, 0)
-        .set_material(wall_material);
+        .set_material(wall_material.clone());
 
     let east_wall = Shape::new(ObjectType::Plane)
         .rotate_y(consts::FRAC_PI_2)
         .rotate_z(consts::FRAC_PI_2)
         .translate(5, 0, 0)
-        .set_material(wall_material);
+        .set_material(wall_material.clone());
 
     let north_wall = Shape::new(ObjectType::Plane)
         .rotate_x(consts::FRAC_PI_2)
         .translate(0, 0, 5)
-        .set_material(wall_material);
+        .set_material(wall_material.clone());
 
     let south_wall = Shape::new(ObjectType::Plane)
         .rotate_x(consts::FRAC_PI_2)
@@ -299,7 +304,7 @@ fn render_reflect_scene(hsize: usize, vsize: usize) {
         floor, ceiling, west_wall, north_wall, south_wall, east_wall,
     ]);
 
-    let result = camera.render(world);
+    let result = render(&camera, world);
     result.write_png("./images/render_refract_scene.png");
 }
 
@@ -341,6 +346,6 @@ fn refraction_render(hsize: usize, vsize: usize) {
         Vector::new(0, 1, 0),
     ));
 
-    let canvas = camera.render(world);
+    let canvas = render(&camera, world);
     canvas.write_png("./images/refractive_sphere.png");
 }
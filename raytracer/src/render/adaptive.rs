@@ -0,0 +1,131 @@
+//! Adaptive sampling, spending samples on pixels that are still noisy
+use crate::render::integrator::luminance;
+use crate::units::color::{rgb, QuantColor};
+
+/// Adaptive sampling settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adaptive {
+    /// Samples every pixel gets before its noise is estimated
+    pub min_samples: usize,
+    /// Samples a pixel gets at most
+    pub max_samples: usize,
+    /// Standard error of the mean luminance a pixel stops at, 1 is white
+    pub threshold: f64,
+}
+
+impl Adaptive {
+    /// Creates new Adaptive, a pixel gets at least two samples to estimate its variance
+    pub fn new(min_samples: usize, max_samples: usize, threshold: f64) -> Adaptive {
+        let min_samples = min_samples.max(2);
+        Adaptive {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+
+    /// Whether a pixel with these statistics has enough samples
+    pub fn is_converged(&self, stats: &PixelStats) -> bool {
+        stats.count >= self.max_samples
+            || (stats.count >= self.min_samples && stats.error() <= self.threshold)
+    }
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            min_samples: 16,
+            max_samples: 256,
+            threshold: 0.01,
+        }
+    }
+}
+
+/// Running mean and variance of the luminance of a pixel's samples
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PixelStats {
+    /// Number of samples taken
+    pub count: usize,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    squares: f64,
+}
+
+impl PixelStats {
+    /// Adds the color of a sample
+    pub fn add(&mut self, color: QuantColor) {
+        self.add_radiance(rgb(color));
+    }
+
+    /// Adds the radiance of a sample, 1.0 is white
+    pub fn add_radiance(&mut self, radiance: [f64; 3]) {
+        let value = luminance(&radiance);
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (value - self.mean);
+    }
+
+    /// Returns mean luminance of the samples
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Returns unbiased variance of the samples' luminance
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.
+        } else {
+            self.squares / (self.count - 1) as f64
+        }
+    }
+
+    /// Returns standard error of the mean, how far it likely is from the converged pixel
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            f64::INFINITY
+        } else {
+            (self.variance() / self.count as f64).sqrt()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::color::{BLACK, WHITE};
+    use crate::units::utils;
+
+    #[test]
+    fn pixel_stats() {
+        let mut stats = PixelStats::default();
+        assert_eq!(stats.error(), f64::INFINITY);
+        for color in [WHITE, BLACK, WHITE, BLACK] {
+            stats.add(color);
+        }
+        assert!(utils::float_eq(stats.mean(), 0.5));
+        assert!(utils::float_eq(stats.variance(), 1. / 3.));
+        assert!(utils::float_eq(stats.error(), (1. / 12_f64).sqrt()));
+    }
+
+    #[test]
+    fn is_converged() {
+        let adaptive = Adaptive::new(4, 8, 0.01);
+        let mut flat = PixelStats::default();
+        let mut noisy = PixelStats::default();
+        for i in 0..4 {
+            flat.add(WHITE);
+            noisy.add(if i % 2 == 0 { WHITE } else { BLACK });
+        }
+        // A flat pixel stops at the minimum, a noisy one runs to the maximum
+        assert!(adaptive.is_converged(&flat));
+        assert!(!adaptive.is_converged(&noisy));
+        for _ in 0..4 {
+            noisy.add(WHITE);
+        }
+        assert!(adaptive.is_converged(&noisy));
+
+        // The maximum is never below the minimum
+        assert_eq!(Adaptive::new(1, 0, 0.1).max_samples, 2);
+    }
+}
@@ -0,0 +1,346 @@
+//! Arbitrary output variables rendered next to the beauty image
+use crate::render::exr::ExrChannel;
+use crate::render::Canvas;
+use crate::units::color::QuantColor;
+use crate::units::tuple::{Point, Vector};
+
+/// Extra per pixel output of a render
+///
+/// Light passes are only shaded by the Whitted integrator, others leave them black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance from the camera along its viewing direction
+    Depth,
+    /// World space surface normal
+    Normal,
+    /// Surface color without lighting, from the pattern or base color
+    Albedo,
+    /// World space position
+    Position,
+    /// Index of the object in the world plus one, background is 0
+    ObjectIndex,
+    /// Index of the distinct material plus one, background is 0
+    MaterialIndex,
+    /// Light from the light source at the first hit
+    Direct,
+    /// Light arriving over secondary rays
+    Indirect,
+    /// Reflected light
+    Reflection,
+    /// Refracted light
+    Refraction,
+    /// Open fraction of the hemisphere above the first hit
+    Occlusion,
+    /// Samples taken in the pixel, images show them as a heatmap
+    Samples,
+}
+
+impl Aov {
+    /// Every supported Aov
+    pub const ALL: [Aov; 12] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectIndex,
+        Aov::MaterialIndex,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Reflection,
+        Aov::Refraction,
+        Aov::Occlusion,
+        Aov::Samples,
+    ];
+
+    /// Returns name used for layers and file names
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectIndex => "object",
+            Aov::MaterialIndex => "material",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Reflection => "reflection",
+            Aov::Refraction => "refraction",
+            Aov::Occlusion => "occlusion",
+            Aov::Samples => "samples",
+        }
+    }
+
+    /// Returns Aov with given name
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// Returns names of the channels the Aov is stored in
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::ObjectIndex | Aov::MaterialIndex => &["id"],
+            Aov::Occlusion => &["Y"],
+            Aov::Samples => &["count"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Returns true for ids, which can't be averaged between samples
+    pub fn is_index(&self) -> bool {
+        matches!(self, Aov::ObjectIndex | Aov::MaterialIndex)
+    }
+
+    /// Returns true for values of the whole pixel instead of its hits
+    pub fn is_per_pixel(&self) -> bool {
+        *self == Aov::Samples
+    }
+
+    /// Returns true for properties of the surface, averaged over the samples that hit it
+    ///
+    /// Light passes are averaged over all samples instead, so they add up to the image.
+    pub fn is_surface(&self) -> bool {
+        matches!(
+            self,
+            Aov::Depth | Aov::Normal | Aov::Albedo | Aov::Position | Aov::Occlusion
+        )
+    }
+}
+
+/// Everything a camera ray found at its first hit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AovSample {
+    pub position: Point,
+    pub normal: Vector,
+    pub albedo: QuantColor,
+    pub object: usize,
+    pub material: usize,
+    pub direct: [f64; 3],
+    pub reflection: [f64; 3],
+    pub refraction: [f64; 3],
+    /// Ambient occlusion, measured by the camera only when its Aov is rendered
+    pub occlusion: f64,
+}
+
+impl AovSample {
+    /// Returns value of an Aov, depth is measured along the ray
+    ///
+    /// # Arguments
+    ///
+    /// * `aov` - value to return
+    /// * `depth` - depth of the sample in camera space
+    pub fn value(&self, aov: Aov, depth: f64) -> [f32; 3] {
+        let color = |c: QuantColor| [c.r as f32 / 255., c.g as f32 / 255., c.b as f32 / 255.];
+        let radiance = |c: [f64; 3]| c.map(|c| c as f32);
+        match aov {
+            Aov::Depth => [depth as f32, 0., 0.],
+            Aov::Normal => [
+                self.normal.x as f32,
+                self.normal.y as f32,
+                self.normal.z as f32,
+            ],
+            Aov::Albedo => color(self.albedo),
+            Aov::Position => [
+                self.position.x as f32,
+                self.position.y as f32,
+                self.position.z as f32,
+            ],
+            Aov::ObjectIndex => [(self.object + 1) as f32, 0., 0.],
+            Aov::MaterialIndex => [(self.material + 1) as f32, 0., 0.],
+            Aov::Direct => radiance(self.direct),
+            Aov::Indirect => radiance([0, 1, 2].map(|i| self.reflection[i] + self.refraction[i])),
+            Aov::Reflection => radiance(self.reflection),
+            Aov::Refraction => radiance(self.refraction),
+            Aov::Occlusion => [self.occlusion as f32, 0., 0.],
+            Aov::Samples => [0., 0., 0.],
+        }
+    }
+}
+
+/// Rendered buffer of a single Aov, pixels are stored row by row
+///
+/// Pixels where camera rays miss every object stay 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovLayer {
+    pub aov: Aov,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl AovLayer {
+    /// Creates new empty AovLayer
+    pub fn new(aov: Aov, width: usize, height: usize) -> AovLayer {
+        AovLayer {
+            aov,
+            width,
+            height,
+            pixels: vec![[0.; 3]; width * height],
+        }
+    }
+
+    /// Returns the layer as EXR channels named `<aov>.<channel>`
+    pub fn exr_channels(&self) -> Vec<ExrChannel> {
+        self.aov
+            .channels()
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| {
+                ExrChannel::new(
+                    &format!("{}.{}", self.aov.name(), channel),
+                    self.pixels.iter().map(|p| p[i]).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the layer as a Canvas for 8 bit images, single channel layers are gray
+    ///
+    /// Values that don't fit between 0 and 1 are mapped into it:
+    /// * depth spans the range of the layer, from white nearby to black far away
+    /// * positions span the range of the layer on every axis
+    /// * normals map -1 to 0 and 1 to white
+    /// * indices get distinct colors
+    /// * sample counts become a heatmap from blue for the fewest to red for the most
+    ///
+    /// Pixels without a hit stay black.
+    pub fn to_canvas(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height);
+        let single = self.aov.channels().len() == 1;
+        let hit = |p: &&[f32; 3]| self.aov == Aov::Samples || **p != [0.; 3];
+        let ranges = [0, 1, 2].map(|c| {
+            self.pixels
+                .iter()
+                .filter(hit)
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), p| {
+                    (low.min(p[c]), high.max(p[c]))
+                })
+        });
+        let scale = |v: f32, c: usize| {
+            let (low, high) = ranges[c];
+            if high > low {
+                (v - low) / (high - low)
+            } else {
+                0.
+            }
+        };
+        let quant = |v: f32| (v * 255.).round() as i64;
+        for (i, p) in self.pixels.iter().enumerate() {
+            let color = if !hit(&p) {
+                QuantColor::new(0, 0, 0)
+            } else {
+                match self.aov {
+                    Aov::Samples => {
+                        let t = scale(p[0], 0);
+                        let ramp =
+                            |center: f32| quant((1.5 - (4. * (t - center)).abs()).clamp(0., 1.));
+                        QuantColor::new(ramp(0.75), ramp(0.5), ramp(0.25))
+                    }
+                    Aov::Depth => {
+                        let v = quant(1. - scale(p[0], 0));
+                        QuantColor::new(v, v, v)
+                    }
+                    Aov::Position => QuantColor::new(
+                        quant(scale(p[0], 0)),
+                        quant(scale(p[1], 1)),
+                        quant(scale(p[2], 2)),
+                    ),
+                    Aov::Normal => {
+                        let [r, g, b] = p.map(|v| quant(0.5 + 0.5 * v));
+                        QuantColor::new(r, g, b)
+                    }
+                    Aov::ObjectIndex | Aov::MaterialIndex => index_color(p[0] as usize),
+                    _ if single => QuantColor::new(quant(p[0]), quant(p[0]), quant(p[0])),
+                    _ => QuantColor::new(quant(p[0]), quant(p[1]), quant(p[2])),
+                }
+            };
+            canvas.write_pixel(i % self.width, i / self.width, color);
+        }
+        canvas
+    }
+}
+
+/// Returns a saturated color for an index, neighbouring indices get far apart hues
+fn index_color(index: usize) -> QuantColor {
+    let hue = (index as f64 * 0.618_033_988_749_895).fract() * 6.;
+    let channel = |offset: f64| {
+        let distance = ((hue - offset).rem_euclid(6.) - 3.).abs();
+        ((distance - 1.).clamp(0., 1.) * 255.).round() as i64
+    };
+    QuantColor::new(channel(0.), channel(4.), channel(2.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::tuple::Tuple;
+
+    #[test]
+    fn from_name() {
+        for aov in Aov::ALL {
+            assert_eq!(Aov::from_name(aov.name()), Some(aov));
+        }
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+
+    #[test]
+    fn exr_channels() {
+        let mut layer = AovLayer::new(Aov::Normal, 2, 1);
+        layer.pixels[1] = [0., 1., 0.];
+        let channels = layer.exr_channels();
+        assert_eq!(channels.len(), 3);
+        assert_eq!(channels[1], ExrChannel::new("normal.Y", vec![0., 1.]));
+
+        let layer = AovLayer::new(Aov::Depth, 2, 1);
+        assert_eq!(layer.exr_channels()[0].name, "depth.Z");
+    }
+
+    #[test]
+    fn value() {
+        let sample = AovSample {
+            position: Point::new(1, 2, 3),
+            normal: Vector::new(0, 1, 0),
+            albedo: QuantColor::new(255, 0, 0),
+            object: 0,
+            material: 2,
+            direct: [1., 1., 1.],
+            reflection: [0.2, 0., 0.],
+            refraction: [0., 0.2, 0.],
+            occlusion: 0.5,
+        };
+        assert_eq!(sample.value(Aov::Depth, 4.), [4., 0., 0.]);
+        assert_eq!(sample.value(Aov::Position, 4.), [1., 2., 3.]);
+        assert_eq!(sample.value(Aov::ObjectIndex, 4.), [1., 0., 0.]);
+        assert_eq!(sample.value(Aov::MaterialIndex, 4.), [3., 0., 0.]);
+        assert_eq!(sample.value(Aov::Indirect, 4.), [0.2, 0.2, 0.]);
+        assert_eq!(sample.value(Aov::Occlusion, 4.), [0.5, 0., 0.]);
+    }
+
+    #[test]
+    fn to_canvas() {
+        // Depth spans its range, far away is dark and misses are black
+        let mut layer = AovLayer::new(Aov::Depth, 3, 1);
+        layer.pixels[0] = [2., 0., 0.];
+        layer.pixels[1] = [12., 0., 0.];
+        let canvas = layer.to_canvas();
+        assert_eq!(canvas.get_pixel(0, 0), QuantColor::new(255, 255, 255));
+        assert_eq!(canvas.get_pixel(1, 0), QuantColor::new(0, 0, 0));
+
+        // Negative normal components stay visible
+        let mut layer = AovLayer::new(Aov::Normal, 2, 1);
+        layer.pixels[0] = [0., -1., 0.];
+        let canvas = layer.to_canvas();
+        assert_eq!(canvas.get_pixel(0, 0), QuantColor::new(128, 0, 128));
+        assert_eq!(canvas.get_pixel(1, 0), QuantColor::new(0, 0, 0));
+
+        // Indices get distinct colors
+        let mut layer = AovLayer::new(Aov::ObjectIndex, 4, 1);
+        layer.pixels = vec![[1., 0., 0.], [2., 0., 0.], [3., 0., 0.], [0.; 3]];
+        let canvas = layer.to_canvas();
+        let colors: Vec<QuantColor> = (0..3).map(|x| canvas.get_pixel(x, 0)).collect();
+        assert!(colors[0] != colors[1] && colors[1] != colors[2] && colors[0] != colors[2]);
+        assert!(colors.iter().all(|c| c.r.max(c.g).max(c.b) == 255));
+        assert_eq!(canvas.get_pixel(3, 0), QuantColor::new(0, 0, 0));
+    }
+}
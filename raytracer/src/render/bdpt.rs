@@ -0,0 +1,613 @@
+//! Bidirectional path tracing, joining paths from the camera with paths from the light
+use crate::render::integrator::luminance;
+use crate::render::{stats, Camera, Splats, World};
+use crate::units::color::rgb;
+use crate::units::sampling::{self, orthonormal_basis, Rng};
+use crate::units::tuple::{Point, Tuple, Vector};
+use crate::units::{Computations, Intersection, Ray};
+use crate::world::{Microfacet, Shading};
+use std::f64::consts;
+
+/// Where a vertex of a subpath lies
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// Point of a camera or light subpath
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: Kind,
+    point: Point,
+    /// Shading normal facing the side the subpath arrived from, unused off surfaces
+    normal: Vector,
+    bsdf: Option<Bsdf>,
+    /// Light or importance carried to the vertex divided by the density of the subpath
+    beta: [f64; 3],
+    /// Density per area of sampling the vertex from the previous one
+    pdf_fwd: f64,
+    /// Density per area of sampling the vertex from the next one, walking backwards
+    pdf_rev: f64,
+    /// Whether the subpath left the vertex by a mirror or glass lobe
+    delta: bool,
+}
+
+impl Vertex {
+    fn new(kind: Kind, point: Point, beta: [f64; 3]) -> Vertex {
+        Vertex {
+            kind,
+            point,
+            normal: Vector::new(0, 0, 0),
+            bsdf: None,
+            beta,
+            pdf_fwd: 1.,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    /// Returns density per area of `next` being sampled from this vertex
+    ///
+    /// # Arguments
+    /// * `camera` - camera of the render
+    /// * `prev` - vertex the subpath arrived from, None for the ends of a subpath
+    /// * `next` - vertex the subpath continues to
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = (next.point - self.point).normalize();
+        let pdf = match (self.kind, self.bsdf, prev) {
+            (Kind::Camera, _, _) => camera_pdf(camera, next.point),
+            (Kind::Light, _, _) => 1. / (4. * consts::PI),
+            (Kind::Surface, Some(bsdf), Some(prev)) => {
+                bsdf.pdf((prev.point - self.point).normalize(), direction)
+            }
+            _ => 0.,
+        };
+        convert(pdf, self, next)
+    }
+}
+
+/// Scattering of a surface point, the lobes of the path tracer
+///
+/// Phong highlights are left out, as every strategy has to see the same light.
+#[derive(Debug, Clone, Copy)]
+struct Bsdf {
+    normal: Vector,
+    albedo: [f64; 3],
+    base: [f64; 3],
+    microfacet: Option<Microfacet>,
+    /// Weight of the diffuse or microfacet lobe
+    diffuse: f64,
+    reflect: f64,
+    transparent: f64,
+    reflectv: Vector,
+    refractv: Option<Vector>,
+    over_point: Point,
+    under_point: Point,
+}
+
+/// Direction picked by a Bsdf
+struct BsdfSample {
+    origin: Point,
+    direction: Vector,
+    /// Scattering times the cosine divided by the density
+    weight: [f64; 3],
+    /// Density per solid angle, zero for mirror and glass
+    pdf: f64,
+    /// Whether it came from the mirror or glass lobe
+    delta: bool,
+}
+
+impl Bsdf {
+    fn new(c: &Computations) -> Bsdf {
+        let object = c.object.at_time(c.time);
+        let material = &object.material;
+        let microfacet = match material.shading {
+            Shading::Microfacet(microfacet) => Some(microfacet),
+            Shading::Phong => None,
+        };
+        let albedo = object
+            .base_color(c.over_point)
+            .map(|a| a * material.diffuse);
+        let (reflect, transparent) = if microfacet.is_some() {
+            (0., material.transparent)
+        } else if material.reflect > 0. && material.transparent > 0. {
+            let reflectance = c.schlick();
+            (
+                material.reflect * reflectance,
+                material.transparent * (1. - reflectance),
+            )
+        } else {
+            (material.reflect, material.transparent)
+        };
+        Bsdf {
+            normal: c.normalv,
+            albedo,
+            base: object.base_color(c.over_point),
+            microfacet,
+            diffuse: if microfacet.is_some() {
+                1.
+            } else {
+                luminance(&albedo)
+            },
+            reflect,
+            transparent,
+            reflectv: c.reflectv,
+            refractv: c.refractv(),
+            over_point: c.over_point,
+            under_point: c.under_point,
+        }
+    }
+
+    fn lobes(&self) -> f64 {
+        self.diffuse + self.reflect + self.transparent
+    }
+
+    /// Returns scattering between two directions without the cosine, mirror and glass lobes
+    /// never connect
+    fn f(&self, wo: Vector, wi: Vector) -> [f64; 3] {
+        let cos = wi.dot(self.normal);
+        if cos <= 0. || wo.dot(self.normal) <= 0. {
+            return [0.; 3];
+        }
+        match self.microfacet {
+            Some(microfacet) => microfacet
+                .eval(self.base, self.normal, wo, wi)
+                .map(|v| v / cos),
+            None => self.albedo.map(|a| a / consts::PI),
+        }
+    }
+
+    /// Returns density per solid angle of sampling `wi` when arriving from `wo`
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64 {
+        let cos = wi.dot(self.normal);
+        if cos <= 0. || self.lobes() <= 0. {
+            return 0.;
+        }
+        let probability = self.diffuse / self.lobes();
+        match self.microfacet {
+            Some(microfacet) => probability * microfacet.pdf(self.normal, wo, wi),
+            None => probability * cos / consts::PI,
+        }
+    }
+
+    fn sample(&self, wo: Vector, rng: &mut Rng) -> Option<BsdfSample> {
+        let lobes = self.lobes();
+        if lobes <= 0. {
+            return None;
+        }
+        let pick = rng.next_f64() * lobes;
+        if pick < self.diffuse {
+            let direction = match self.microfacet {
+                Some(microfacet) => {
+                    let u = [rng.next_f64(), rng.next_f64(), rng.next_f64()];
+                    microfacet.sample(self.base, self.normal, wo, u)?.0
+                }
+                None => {
+                    let (x, y, z) = sampling::cosine_hemisphere(rng.next_f64(), rng.next_f64());
+                    let (tangent, bitangent) = orthonormal_basis(self.normal);
+                    tangent * x + bitangent * y + self.normal * z
+                }
+            };
+            let pdf = self.pdf(wo, direction);
+            if pdf <= 0. {
+                return None;
+            }
+            let cos = direction.dot(self.normal);
+            Some(BsdfSample {
+                origin: self.over_point,
+                direction,
+                weight: self.f(wo, direction).map(|f| f * cos / pdf),
+                pdf,
+                delta: false,
+            })
+        } else if pick < self.diffuse + self.reflect {
+            Some(BsdfSample {
+                origin: self.over_point,
+                direction: self.reflectv,
+                weight: [lobes; 3],
+                pdf: 0.,
+                delta: true,
+            })
+        } else {
+            Some(BsdfSample {
+                origin: self.under_point,
+                direction: self.refractv?,
+                weight: [lobes; 3],
+                pdf: 0.,
+                delta: true,
+            })
+        }
+    }
+}
+
+/// Returns color seen by a camera ray and whether it hit any object
+///
+/// Light subpaths reaching the camera directly are splatted onto the canvas, scaled by
+/// one light path per camera ray. Only the point light is a light source, the background
+/// is seen by camera subpaths leaving the scene. Media only attenuate connections.
+///
+/// # Arguments
+/// * `world` - world to render
+/// * `camera` - pinhole camera the ray comes from
+/// * `ray` - camera ray
+/// * `max_depth` - most bounces of a full path
+/// * `rng` - random numbers of the sample
+/// * `splats` - splats of the canvas
+pub fn trace(
+    world: &World,
+    camera: &Camera,
+    ray: Ray,
+    max_depth: usize,
+    rng: &mut Rng,
+    splats: &Splats,
+) -> ([f64; 3], bool) {
+    let mut camera_path = vec![Vertex::new(Kind::Camera, camera.position(), [1.; 3])];
+    let pdf = camera_pdf(camera, ray.origin + ray.direction);
+    let escaped = random_walk(
+        world,
+        ray,
+        [1.; 3],
+        pdf,
+        max_depth + 2,
+        rng,
+        &mut camera_path,
+    );
+    let mut radiance = escaped.unwrap_or([0.; 3]);
+
+    let mut light_path = Vec::new();
+    if let Some(light) = world.light {
+        // Point light intensity is the irradiance it gives, like on surfaces
+        let emitted = rgb(light.intensity).map(|i| i * consts::PI);
+        light_path.push(Vertex::new(Kind::Light, light.position, emitted));
+        let (x, y, z) = sampling::uniform_cone(rng.next_f64(), rng.next_f64(), -1.);
+        let pdf = 1. / (4. * consts::PI);
+        let ray = Ray::new(light.position, Vector::new(x, y, z))
+            .set_time(ray.time)
+            .set_secondary(true);
+        let beta = emitted.map(|e| e / pdf);
+        random_walk(world, ray, beta, pdf, max_depth + 1, rng, &mut light_path);
+    }
+
+    for t in 1..=camera_path.len() {
+        for s in 1..=light_path.len() {
+            if (s == 1 && t == 1) || s + t - 2 > max_depth {
+                continue;
+            }
+            let contribution = connect(world, camera, &light_path, &camera_path, s, t, ray.time);
+            let (value, pixel) = match contribution {
+                Some(contribution) => contribution,
+                None => continue,
+            };
+            let weight = mis_weight(camera, &light_path, &camera_path, s, t);
+            let value = value.map(|v| v * weight);
+            match pixel {
+                Some((x, y)) => splats.splat(x, y, value),
+                None => {
+                    for (r, v) in radiance.iter_mut().zip(value) {
+                        *r += v;
+                    }
+                }
+            }
+        }
+    }
+
+    (radiance, camera_path.len() > 1)
+}
+
+/// Extends a subpath by bouncing a ray through the scene
+///
+/// Returns the background seen by a camera subpath leaving the scene.
+///
+/// # Arguments
+/// * `world` - world to trace in
+/// * `ray` - ray leaving the last vertex of the path
+/// * `beta` - throughput of the ray
+/// * `pdf` - density per solid angle of the ray's direction
+/// * `max_vertices` - length the path stops at
+/// * `rng` - random numbers of the path
+/// * `path` - subpath holding its first vertex
+fn random_walk(
+    world: &World,
+    ray: Ray,
+    beta: [f64; 3],
+    pdf: f64,
+    max_vertices: usize,
+    rng: &mut Rng,
+    path: &mut Vec<Vertex>,
+) -> Option<[f64; 3]> {
+    let mut ray = ray;
+    let mut beta = beta;
+    let mut pdf_fwd = pdf;
+    while path.len() < max_vertices {
+        let intersections = world.intersect(ray);
+        let hit = match Intersection::hit(intersections.clone()) {
+            Some(hit) if hit.object.interior.is_some() => {
+                ray = ray.continue_past(hit.t);
+                continue;
+            }
+            Some(hit) => hit,
+            None if path[0].kind == Kind::Camera => {
+                let background = world.background.radiance(ray.direction);
+                return Some([0, 1, 2].map(|i| beta[i] * background[i]));
+            }
+            None => return None,
+        };
+        let c = hit.computations(ray, &intersections);
+        if let Some(medium) = c.medium {
+            let transmittance = medium.material.transmittance(c.t);
+            beta = [0, 1, 2].map(|i| beta[i] * transmittance[i]);
+        }
+        let prev = path.len() - 1;
+        if path[prev].kind == Kind::Light {
+            // Without falloff the light covers the area around the first hit with its intensity
+            beta = beta.map(|b| b * c.t.powi(2));
+        }
+        let bsdf = Bsdf::new(&c);
+        let mut vertex = Vertex::new(Kind::Surface, c.over_point, beta);
+        vertex.normal = c.normalv;
+        vertex.bsdf = Some(bsdf);
+        vertex.pdf_fwd = convert(pdf_fwd, &path[prev], &vertex);
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let sample = match bsdf.sample(c.eyev, rng) {
+            Some(sample) => sample,
+            None => break,
+        };
+        beta = [0, 1, 2].map(|i| beta[i] * sample.weight[i]);
+        let pdf_rev = if sample.delta {
+            pdf_fwd = 0.;
+            0.
+        } else {
+            pdf_fwd = sample.pdf;
+            bsdf.pdf(sample.direction, c.eyev)
+        };
+        let last = path.len() - 1;
+        path[last].delta = sample.delta;
+        path[last - 1].pdf_rev = convert(pdf_rev, &path[last], &path[last - 1]);
+        ray = Ray::new(sample.origin, sample.direction)
+            .set_time(ray.time)
+            .set_secondary(true);
+        let refracted = sample.direction.dot(c.normalv) < 0.;
+        stats::record(|s| {
+            if !sample.delta {
+                s.diffuse_rays += 1;
+            } else if refracted {
+                s.refraction_rays += 1;
+            } else {
+                s.reflection_rays += 1;
+            }
+        });
+        stats::reach_depth(last);
+    }
+    None
+}
+
+/// Canvas position a light path is splatted to
+type Pixel = (usize, usize);
+
+/// Returns light of the path joining the first `s` light and `t` camera vertices, without
+/// its weight, and the pixel it lands on when it is splatted
+fn connect(
+    world: &World,
+    camera: &Camera,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+    time: f64,
+) -> Option<([f64; 3], Option<Pixel>)> {
+    let qs = &light_path[s - 1];
+    let pt = &camera_path[t - 1];
+    let to_camera = pt.point - qs.point;
+    let distance = to_camera.magnitude();
+    let direction = to_camera.normalize();
+
+    let (value, pixel) = if t == 1 {
+        // The light subpath is seen by the camera
+        let (x, y, cos) = camera.project(qs.point)?;
+        let f = qs
+            .bsdf?
+            .f((light_path[s - 2].point - qs.point).normalize(), direction);
+        let importance = 1. / (film_area(camera) * cos.powi(3) * distance.powi(2));
+        let cos_q = direction.dot(qs.normal).abs();
+        let value = [0, 1, 2].map(|i| qs.beta[i] * f[i] * cos_q * importance);
+        (value, Some((x as usize, y as usize)))
+    } else if s == 1 {
+        // The camera subpath finds the light
+        let wo = (camera_path[t - 2].point - pt.point).normalize();
+        let f = pt.bsdf?.f(wo, direction * -1.);
+        let cos_p = direction.dot(pt.normal).abs();
+        let value = [0, 1, 2].map(|i| pt.beta[i] * f[i] * cos_p * qs.beta[i]);
+        (value, None)
+    } else {
+        let f_q = qs
+            .bsdf?
+            .f((light_path[s - 2].point - qs.point).normalize(), direction);
+        let wo = (camera_path[t - 2].point - pt.point).normalize();
+        let f_p = pt.bsdf?.f(wo, direction * -1.);
+        let g = direction.dot(qs.normal).abs() * direction.dot(pt.normal).abs() / distance.powi(2);
+        let value = [0, 1, 2].map(|i| qs.beta[i] * f_q[i] * g * f_p[i] * pt.beta[i]);
+        (value, None)
+    };
+    if value == [0.; 3] {
+        return None;
+    }
+    let visible = world.transmittance(qs.point, direction, distance, time);
+    let value = [0, 1, 2].map(|i| value[i] * visible[i]);
+    (value != [0.; 3]).then_some((value, pixel))
+}
+
+/// Returns the power heuristic weight of the strategy joining `s` light and `t` camera
+/// vertices against every other way of sampling the same path
+fn mis_weight(
+    camera: &Camera,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> f64 {
+    if s + t == 2 {
+        return 1.;
+    }
+    let mut light_path = light_path[..s].to_vec();
+    let mut camera_path = camera_path[..t].to_vec();
+
+    // Densities of the vertices next to the connection as if sampled across it
+    let qs = light_path[s - 1];
+    let pt = camera_path[t - 1];
+    let qs_minus = if s >= 2 {
+        Some(light_path[s - 2])
+    } else {
+        None
+    };
+    let pt_minus = if t >= 2 {
+        Some(camera_path[t - 2])
+    } else {
+        None
+    };
+    camera_path[t - 1].pdf_rev = qs.pdf(camera, qs_minus.as_ref(), &pt);
+    camera_path[t - 1].delta = false;
+    if let Some(pt_minus) = pt_minus {
+        camera_path[t - 2].pdf_rev = pt.pdf(camera, Some(&qs), &pt_minus);
+    }
+    light_path[s - 1].pdf_rev = pt.pdf(camera, pt_minus.as_ref(), &qs);
+    light_path[s - 1].delta = false;
+    if let Some(qs_minus) = qs_minus {
+        light_path[s - 2].pdf_rev = qs.pdf(camera, Some(&pt), &qs_minus);
+    }
+
+    let remap = |pdf: f64| if pdf != 0. { pdf * pdf } else { 1. };
+    let mut sum = 0.;
+    let mut ratio = 1.;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_path[i].pdf_rev) / remap(camera_path[i].pdf_fwd);
+        if !camera_path[i].delta && !camera_path[i - 1].delta {
+            sum += ratio;
+        }
+    }
+    let mut ratio = 1.;
+    for i in (0..s).rev() {
+        ratio *= remap(light_path[i].pdf_rev) / remap(light_path[i].pdf_fwd);
+        // The point light can't be hit, so it never ends a camera subpath
+        let delta_before = i == 0 || light_path[i - 1].delta;
+        if !light_path[i].delta && !delta_before {
+            sum += ratio;
+        }
+    }
+    1. / (1. + sum)
+}
+
+/// Converts density per solid angle at one vertex into density per area at another
+fn convert(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+    let offset = to.point - from.point;
+    let distance = offset.dot(offset);
+    if distance == 0. {
+        return 0.;
+    }
+    let cos = match to.kind {
+        Kind::Surface => (offset.normalize().dot(to.normal)).abs(),
+        Kind::Camera | Kind::Light => 1.,
+    };
+    pdf * cos / distance
+}
+
+/// Returns density per solid angle of the camera shooting a ray toward a point
+fn camera_pdf(camera: &Camera, point: Point) -> f64 {
+    match camera.project(point) {
+        Some((_, _, cos)) => 1. / (film_area(camera) * cos.powi(3)),
+        None => 0.,
+    }
+}
+
+/// Returns area of the canvas one unit in front of the camera
+fn film_area(camera: &Camera) -> f64 {
+    4. * camera.half_width * camera.half_height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Canvas, Integrator};
+    use crate::units::color::{QuantColor, WHITE};
+    use crate::units::objects::{ObjectType, Shape};
+    use crate::units::{Matrix, Transformable};
+    use crate::world::{Material, PointLight};
+
+    fn mean(canvas: &Canvas) -> f64 {
+        let pixels = canvas.radiance.iter();
+        pixels.map(|p| p[1] as f64 * 255.).sum::<f64>() / (canvas.width * canvas.height) as f64
+    }
+
+    #[test]
+    fn trace() {
+        let floor = Shape::new(ObjectType::Plane).set_material(
+            Material::new(WHITE)
+                .set_diffuse(0.8)
+                .set_specular(0.)
+                .set_ambient(0.),
+        );
+        let light = PointLight::new(Point::new(0, 3, 0), QuantColor::new(128, 128, 128));
+        let world = || {
+            World::new()
+                .set_objects(vec![floor.clone()])
+                .set_light(Some(light))
+        };
+        let camera = Camera::new(8, 8, consts::FRAC_PI_3)
+            .set_samples(64)
+            .transform(Matrix::view_transform(
+                Point::new(0, 4, -2),
+                Point::new(0, 0, 0),
+                Vector::new(0, 1, 0),
+            ));
+
+        // Joining light paths gives the same picture as path tracing
+        let path = camera.set_integrator(Integrator::PathTraced(3));
+        let bidirectional = camera.set_integrator(Integrator::Bidirectional(3));
+        let expected = mean(&path.render(world()));
+        let actual = mean(&bidirectional.render(world()));
+        assert!(expected > 10.);
+        assert!((actual - expected).abs() < 0.1 * expected);
+
+        // Light paths reach the camera and are splatted
+        let mut canvas = Canvas::new(8, 8);
+        let splats = Splats::new(8, 8);
+        let ray = camera.ray_for_pixel(4, 4);
+        let mut rng = Rng::new(5);
+        for _ in 0..50 {
+            let (_, hit) = super::trace(&world(), &camera, ray, 3, &mut rng, &splats);
+            assert!(hit);
+        }
+        canvas.add_splats(splats, 50.);
+        assert!(mean(&canvas) > 0.);
+    }
+
+    #[test]
+    fn reproducible() {
+        // Splats from many threads add up to the same image as from one
+        let floor =
+            Shape::new(ObjectType::Plane).set_material(Material::new(WHITE).set_reflect(0.3));
+        let ball = Shape::new(ObjectType::Sphere).translate(0, 1, 0);
+        let world = || {
+            World::new()
+                .set_objects(vec![floor.clone(), ball.clone()])
+                .set_light(Some(PointLight::new(Point::new(-2, 4, -2), WHITE)))
+        };
+        let camera = Camera::new(16, 16, consts::FRAC_PI_3)
+            .set_samples(8)
+            .set_integrator(Integrator::Bidirectional(4))
+            .transform(Matrix::view_transform(
+                Point::new(0, 3, -5),
+                Point::new(0, 1, 0),
+                Vector::new(0, 1, 0),
+            ));
+        let single = camera.set_threads(1).render(world());
+        let many = camera.set_threads(8).render(world());
+        assert!(single.radiance.iter().any(|p| p[1] > 0.));
+        assert_eq!(single.radiance, many.radiance);
+    }
+}
@@ -1,9 +1,27 @@
-use crate::render::{Canvas, World};
-use crate::units::tuple::{Point, Tuple};
-use crate::units::Ray;
+use crate::render::job::Tracker;
+use crate::render::{
+    bdpt, stats, Adaptive, Aov, AovLayer, Canvas, Integrator, PixelStats, RenderJob, Splats, World,
+};
+use crate::units::sampling::{self, Rng, Sequence};
+use crate::units::tuple::{Point, Tuple, Vector};
+use crate::units::{utils, Ray};
 use crate::units::{Matrix, Transformable, IDENTITY_MATRIX};
+use crate::world::Background;
 use rayon::prelude::*;
-use std::sync::Mutex;
+use std::f64::consts;
+
+/// Projection describes how pixels are mapped onto rays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens perspective driven by `field_of_view`
+    Perspective,
+    /// Parallel rays, holds the width of the view in world units
+    Orthographic(f64),
+    /// Equidistant fisheye, `field_of_view` is the angle across the longer side
+    Fisheye,
+    /// Full 360x180 degree panorama
+    Equirectangular,
+}
 
 pub struct Camera {
     pub hsize: usize,
@@ -13,6 +31,30 @@ pub struct Camera {
     pub pixel_size: f64,
     pub half_height: f64,
     pub half_width: f64,
+    /// Samples taken per pixel, 1 samples the pixel center
+    pub samples: usize,
+    /// Lens diameter, 0.0 is a pinhole camera
+    pub aperture: f64,
+    /// Distance to the plane that is in perfect focus
+    pub focal_distance: f64,
+    /// Number of aperture blades, 0 is a round aperture
+    pub aperture_blades: usize,
+    /// Rotation of the aperture polygon in radians
+    pub aperture_rotation: f64,
+    /// Projection used to generate rays
+    pub projection: Projection,
+    /// Time the shutter opens at
+    pub shutter_open: f64,
+    /// Time the shutter closes at
+    pub shutter_close: f64,
+    /// Algorithm computing the color of each sample
+    pub integrator: Integrator,
+    /// Numbers placing the samples of a pixel
+    pub sequence: Sequence,
+    /// Varies the samples per pixel by noise instead of taking `samples` everywhere
+    pub adaptive: Option<Adaptive>,
+    /// Number of render threads, 0 uses every core
+    pub threads: usize,
 }
 
 impl Camera {
@@ -33,34 +75,472 @@ impl Camera {
             pixel_size: (half_width * 2.) / (hsize as f64),
             half_height,
             half_width,
+            samples: 1,
+            aperture: 0.,
+            focal_distance: 1.,
+            aperture_blades: 0,
+            aperture_rotation: 0.,
+            projection: Projection::Perspective,
+            shutter_open: 0.,
+            shutter_close: 0.,
+            integrator: Integrator::Whitted,
+            sequence: Sequence::Random,
+            adaptive: None,
+            threads: 0,
+        }
+    }
+
+    pub fn set_integrator(&self, integrator: Integrator) -> Camera {
+        Camera {
+            integrator,
+            ..*self
+        }
+    }
+
+    /// Sets the sequence spreading samples over the pixel, the lens and the shutter interval
+    pub fn set_sequence(&self, sequence: Sequence) -> Camera {
+        Camera { sequence, ..*self }
+    }
+
+    /// Sets adaptive sampling, it replaces the fixed number of samples
+    pub fn set_adaptive(&self, adaptive: Option<Adaptive>) -> Camera {
+        Camera { adaptive, ..*self }
+    }
+
+    /// Sets the number of render threads, 0 uses every core
+    pub fn set_threads(&self, threads: usize) -> Camera {
+        Camera { threads, ..*self }
+    }
+
+    /// Returns most samples a pixel can get
+    pub fn max_samples(&self) -> usize {
+        self.adaptive.map_or(self.samples, |a| a.max_samples)
+    }
+
+    /// Sets the exposure interval, samples are spread over it to produce motion blur
+    pub fn set_shutter(&self, shutter_open: f64, shutter_close: f64) -> Camera {
+        Camera {
+            shutter_open,
+            shutter_close,
+            ..*self
         }
     }
 
+    pub fn set_projection(&self, projection: Projection) -> Camera {
+        Camera {
+            projection,
+            ..*self
+        }
+    }
+
+    pub fn set_samples(&self, samples: usize) -> Camera {
+        Camera {
+            samples: samples.max(1),
+            ..*self
+        }
+    }
+
+    /// Turns the camera into a thin lens camera
+    ///
+    /// # Arguments
+    /// * `aperture` - lens diameter
+    /// * `focal_distance` - distance to the plane in focus
+    pub fn set_aperture(&self, aperture: f64, focal_distance: f64) -> Camera {
+        Camera {
+            aperture,
+            focal_distance,
+            ..*self
+        }
+    }
+
+    /// Sets polygonal aperture shape, giving the bokeh its polygonal look
+    ///
+    /// # Arguments
+    /// * `blades` - number of aperture blades, 0 is a round aperture
+    /// * `rotation` - rotation of the polygon in radians
+    pub fn set_aperture_shape(&self, blades: usize, rotation: f64) -> Camera {
+        Camera {
+            aperture_blades: blades,
+            aperture_rotation: rotation,
+            ..*self
+        }
+    }
+
+    /// Whether every ray starts at one point and light can be projected onto the canvas
+    pub fn is_pinhole(&self) -> bool {
+        self.projection == Projection::Perspective && self.aperture <= 0.
+    }
+
+    /// Returns position of the camera in the world
+    pub fn position(&self) -> Point {
+        Point::from(self.transformation_matrix.invert().unwrap() * Point::new(0, 0, 0))
+    }
+
+    /// Returns canvas position of a world point seen by a pinhole camera and the cosine
+    /// between the viewing direction and the direction to the point, None off the canvas
+    pub fn project(&self, point: Point) -> Option<(f64, f64, f64)> {
+        let view = Point::from(self.transformation_matrix * point);
+        if view.z >= 0. {
+            return None;
+        }
+        let px = (self.half_width + view.x / view.z) / self.pixel_size;
+        let py = (self.half_height + view.y / view.z) / self.pixel_size;
+        if px < 0. || py < 0. || px >= self.hsize as f64 || py >= self.vsize as f64 {
+            return None;
+        }
+        let cos = -view.z / (view - Point::new(0, 0, 0)).magnitude();
+        Some((px, py, cos))
+    }
+
     pub fn ray_for_pixel(&self, x: usize, y: usize) -> Ray {
-        let xoffset = (x as f64 + 0.5) * self.pixel_size;
-        let yoffset = (y as f64 + 0.5) * self.pixel_size;
+        self.ray_for_sample(x as f64 + 0.5, y as f64 + 0.5, (0.5, 0.5))
+    }
 
-        let world_x = self.half_width as f64 - xoffset;
-        let world_y = self.half_height as f64 - yoffset;
+    /// Returns a ray through the given canvas position and lens sample
+    ///
+    /// # Arguments
+    /// * `px` - x position on the canvas in pixels
+    /// * `py` - y position on the canvas in pixels
+    /// * `lens` - point on the unit square that is mapped onto the aperture
+    pub fn ray_for_sample(&self, px: f64, py: f64, lens: (f64, f64)) -> Ray {
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => self.perspective(px, py, lens),
+            Projection::Orthographic(view_width) => self.orthographic(px, py, view_width),
+            Projection::Fisheye => self.fisheye(px, py),
+            Projection::Equirectangular => self.equirectangular(px, py),
+        };
 
         let invert_transform = self.transformation_matrix.invert().unwrap();
-        let pixel = Point::from(invert_transform * Point::new(world_x, world_y, -1.));
-        let origin = Point::from(invert_transform * Point::new(0, 0, 0));
-        let direction = (pixel - origin).normalize();
+        let origin = Point::from(invert_transform * origin);
+        let direction = Vector::from(invert_transform * direction).normalize();
         Ray::new(origin, direction)
     }
 
+    /// Camera space origin and direction of a perspective ray
+    fn perspective(&self, px: f64, py: f64, lens: (f64, f64)) -> (Point, Vector) {
+        let xoffset = px * self.pixel_size;
+        let yoffset = py * self.pixel_size;
+
+        let world_x = self.half_width - xoffset;
+        let world_y = self.half_height - yoffset;
+
+        if self.aperture <= 0. {
+            let pixel = Point::new(world_x, world_y, -1.);
+            return (Point::new(0, 0, 0), pixel - Point::new(0, 0, 0));
+        }
+
+        let (lens_x, lens_y) = self.sample_aperture(lens);
+        let focus = Point::new(
+            world_x * self.focal_distance,
+            world_y * self.focal_distance,
+            -self.focal_distance,
+        );
+        let origin = Point::new(lens_x, lens_y, 0.);
+        (origin, focus - origin)
+    }
+
+    /// Camera space origin and direction of an orthographic ray
+    fn orthographic(&self, px: f64, py: f64, view_width: f64) -> (Point, Vector) {
+        let pixel_size = view_width / self.hsize as f64;
+        let x = view_width / 2. - px * pixel_size;
+        let y = (self.vsize as f64 * pixel_size) / 2. - py * pixel_size;
+        (Point::new(x, y, 0.), Vector::new(0, 0, -1))
+    }
+
+    /// Whether a canvas position shows the scene, a fisheye image ends at its circle
+    pub fn sees(&self, px: f64, py: f64) -> bool {
+        match self.projection {
+            Projection::Fisheye => {
+                let (x, y) = self.fisheye_offset(px, py);
+                x.powi(2) + y.powi(2) <= 1.
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns canvas position relative to the fisheye circle, 1 is its radius
+    fn fisheye_offset(&self, px: f64, py: f64) -> (f64, f64) {
+        let radius = self.hsize.max(self.vsize) as f64 / 2.;
+        let x = (self.hsize as f64 / 2. - px) / radius;
+        let y = (self.vsize as f64 / 2. - py) / radius;
+        (x, y)
+    }
+
+    /// Camera space origin and direction of an equidistant fisheye ray
+    fn fisheye(&self, px: f64, py: f64) -> (Point, Vector) {
+        let (x, y) = self.fisheye_offset(px, py);
+        let r = (x.powi(2) + y.powi(2)).sqrt();
+        let theta = (r * self.field_of_view / 2.).min(consts::PI);
+        let phi = y.atan2(x);
+        let direction = Vector::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+        (Point::new(0, 0, 0), direction)
+    }
+
+    /// Camera space origin and direction of an equirectangular panorama ray
+    fn equirectangular(&self, px: f64, py: f64) -> (Point, Vector) {
+        let longitude = (0.5 - px / self.hsize as f64) * 2. * consts::PI;
+        let latitude = (0.5 - py / self.vsize as f64) * consts::PI;
+        let direction = Vector::new(
+            longitude.sin() * latitude.cos(),
+            latitude.sin(),
+            -longitude.cos() * latitude.cos(),
+        );
+        (Point::new(0, 0, 0), direction)
+    }
+
+    /// Maps a unit square sample onto the aperture in camera space
+    fn sample_aperture(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        let radius = self.aperture / 2.;
+        let (x, y) = if self.aperture_blades >= 3 {
+            sampling::regular_polygon(u, v, self.aperture_blades, self.aperture_rotation)
+        } else {
+            sampling::concentric_disk(u, v)
+        };
+        (x * radius, y * radius)
+    }
+
+    /// Returns the ray of one sample of a pixel and the sample's Rng for the integrator
+    ///
+    /// A single sample goes through the pixel center, multiple samples are jittered
+    /// over the pixel. Pixel position, lens position and time come from the sequence,
+    /// rotated per pixel, or from the sample's own Rng where the sequence doesn't reach.
+    /// There is no ray where the camera doesn't see the sample's position.
+    fn sample_ray(&self, x: usize, y: usize, sample: usize) -> (Option<Ray>, Rng) {
+        let mut rng = Rng::for_sample(x, y, sample);
+        let mut rotation = Rng::for_pixel(x, y);
+        let offsets = [(); 5].map(|_| rotation.next_f64());
+        let mut next = |dimension: usize| match self.sequence.sample(sample, dimension) {
+            Some(value) => sampling::cranley_patterson(value, offsets[dimension]),
+            None => rng.next_f64(),
+        };
+        let (px, py) = if self.max_samples() == 1 {
+            (x as f64 + 0.5, y as f64 + 0.5)
+        } else {
+            (x as f64 + next(0), y as f64 + next(1))
+        };
+        let lens = (next(2), next(3));
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * next(4);
+        let ray = self
+            .sees(px, py)
+            .then(|| self.ray_for_sample(px, py, lens).set_time(time));
+        (ray, rng)
+    }
+
+    /// Whether the integrator splats light paths onto the canvas
+    fn splats_light_paths(&self) -> bool {
+        matches!(self.integrator, Integrator::Bidirectional(_)) && self.is_pinhole()
+    }
+
+    /// Returns radiance of a camera ray and whether it hit any object, from the integrator
+    ///
+    /// Bidirectional path tracing splats light paths reaching the camera, without splats
+    /// it path traces.
+    fn trace(
+        &self,
+        world: &World,
+        ray: Ray,
+        rng: &mut Rng,
+        splats: Option<&Splats>,
+    ) -> ([f64; 3], bool) {
+        match (self.integrator, splats) {
+            (Integrator::Bidirectional(max_depth), Some(splats)) => {
+                bdpt::trace(world, self, ray, max_depth, rng, splats)
+            }
+            (integrator, _) => integrator.trace(world, ray, rng),
+        }
+    }
+
+    /// Computes averaged radiance and coverage of all samples of a pixel and the number of
+    /// samples, Aov values are written to `values`
+    ///
+    /// Indices can't be blended, so they are taken from the first sample that hits.
+    /// Surface Aovs are averaged over the samples that hit, light passes over all samples.
+    /// Other integrators than Whitted only gather the geometry of the first hit, their light
+    /// passes stay black.
+    /// With adaptive sampling the pixel stops taking samples once its noise is low enough.
+    fn pixel_samples(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        aovs: &[Aov],
+        values: &mut [[f32; 3]],
+        splats: Option<&Splats>,
+    ) -> ([f64; 3], f64, usize) {
+        let mut sum = [0.; 3];
+        let mut hits = 0;
+        let mut surfaces = 0;
+        let mut stats = PixelStats::default();
+        for sample in 0..self.max_samples() {
+            if self.adaptive.is_some_and(|a| a.is_converged(&stats)) {
+                break;
+            }
+            let (ray, mut rng) = self.sample_ray(x, y, sample);
+            let ray = match ray {
+                Some(ray) => ray,
+                None => {
+                    stats.add_radiance([0.; 3]);
+                    continue;
+                }
+            };
+            stats::record(|s| s.camera_rays += 1);
+            let (color, hit) = if aovs.is_empty() {
+                self.trace(world, ray, &mut rng, splats)
+            } else {
+                let (color, hit, sample) = if self.integrator == Integrator::Whitted {
+                    world.color_at_with_aovs(ray, 5)
+                } else {
+                    let (color, hit) = self.trace(world, ray, &mut rng, splats);
+                    (color, hit, world.first_hit(ray))
+                };
+                if let Some(mut hit) = sample {
+                    if aovs.contains(&Aov::Occlusion) {
+                        let ao = world.ambient_occlusion.unwrap_or_default();
+                        let point = hit.position + hit.normal * utils::EPSILON;
+                        hit.occlusion = ao.visibility(world, point, hit.normal, ray.time, &mut rng);
+                    }
+                    let depth = self.depth(hit.position);
+                    for (value, aov) in values.iter_mut().zip(aovs) {
+                        let v = hit.value(*aov, depth);
+                        if !aov.is_index() {
+                            for (channel, v) in value.iter_mut().zip(v) {
+                                *channel += v;
+                            }
+                        } else if surfaces == 0 {
+                            *value = v;
+                        }
+                    }
+                    surfaces += 1;
+                }
+                (color, hit)
+            };
+            if hit {
+                hits += 1;
+            }
+            for (s, c) in sum.iter_mut().zip(color) {
+                *s += c;
+            }
+            stats.add_radiance(color);
+        }
+        let n = stats.count;
+        for (value, aov) in values.iter_mut().zip(aovs) {
+            if aov.is_per_pixel() {
+                *value = [n as f32, 0., 0.];
+            } else if aov.is_surface() {
+                // Misses don't blend surfaces toward 0 at silhouettes
+                *value = value.map(|v| v / surfaces.max(1) as f32);
+            } else if !aov.is_index() {
+                *value = value.map(|v| v / n as f32);
+            }
+        }
+        let coverage = hits as f64 / n as f64;
+        (sum.map(|s| s / n as f64), coverage, n)
+    }
+
+    /// Returns distance of a world point from the camera along its viewing direction
+    fn depth(&self, position: Point) -> f64 {
+        let view = Point::from(self.transformation_matrix * position);
+        match self.projection {
+            Projection::Perspective | Projection::Orthographic(_) => -view.z,
+            Projection::Fisheye | Projection::Equirectangular => {
+                (view - Point::new(0, 0, 0)).magnitude()
+            }
+        }
+    }
+
     pub fn render(&self, world: World) -> Canvas {
-        let canvas = Mutex::new(Canvas::new(self.hsize, self.vsize));
-        (0..self.vsize).into_par_iter().for_each(|y| {
-            (0..self.hsize).into_par_iter().for_each(|x| {
-                let ray = self.ray_for_pixel(x, y);
-                let color = world.color_at(ray, 5);
-                let mut canvas = canvas.lock().unwrap();
-                canvas.write_pixel(x, y, color);
+        self.render_with_aovs(world, &[]).0
+    }
+
+    /// Renders the beauty image together with a layer for every requested Aov
+    ///
+    /// With a transparent world background the canvas is transparent and keeps coverage as alpha.
+    pub fn render_with_aovs(&self, world: World, aovs: &[Aov]) -> (Canvas, Vec<AovLayer>) {
+        self.render_job(world, aovs, &RenderJob::new())
+            .expect("Render without a job can't be cancelled")
+    }
+
+    /// Renders like `render_with_aovs`, reporting progress to the job and stopping when
+    /// it is cancelled
+    ///
+    /// Rows are rendered in parallel straight into the canvas, on the global thread pool or,
+    /// with a set number of `threads`, on the job's pool, reused by its following renders.
+    /// Every thread counts its own stats, merged into the job's after every row.
+    /// Returns None when the render was cancelled.
+    pub fn render_job(
+        &self,
+        world: World,
+        aovs: &[Aov],
+        job: &RenderJob,
+    ) -> Option<(Canvas, Vec<AovLayer>)> {
+        let tracker = Tracker::new(job, self.hsize * self.vsize, self.vsize);
+        let result = if self.threads == 0 {
+            self.render_rows(&world, aovs, &tracker)
+        } else {
+            job.pool(self.threads)
+                .install(|| self.render_rows(&world, aovs, &tracker))
+        };
+        (!job.is_cancelled()).then_some(result)
+    }
+
+    /// Renders every row of the canvas in parallel on the current thread pool
+    fn render_rows(
+        &self,
+        world: &World,
+        aovs: &[Aov],
+        tracker: &Tracker,
+    ) -> (Canvas, Vec<AovLayer>) {
+        let (width, height) = (self.hsize, self.vsize);
+        let mut canvas = Canvas::new(width, height);
+        canvas.transparent = world.background == Background::Transparent;
+        let splats = self
+            .splats_light_paths()
+            .then(|| Splats::new(width, height));
+        // Aov values of a pixel lie next to each other, at least one slot keeps rows aligned
+        let stride = aovs.len().max(1);
+        let mut values = vec![[0_f32; 3]; width * height * stride];
+        let samples: usize = canvas
+            .radiance
+            .par_chunks_mut(width)
+            .zip(canvas.coverage.par_chunks_mut(width))
+            .zip(values.par_chunks_mut(width * stride))
+            .enumerate()
+            .map(|(y, ((radiance, coverage), values))| {
+                let mut samples = 0;
+                for x in 0..width {
+                    if tracker.is_cancelled() {
+                        stats::take();
+                        return samples;
+                    }
+                    let values = &mut values[x * stride..x * stride + aovs.len()];
+                    let (color, covered, count) =
+                        self.pixel_samples(world, x, y, aovs, values, splats.as_ref());
+                    radiance[x] = color.map(|c| c as f32);
+                    coverage[x] = covered;
+                    samples += count;
+                }
+                tracker.finish_tile(width, &stats::take());
+                samples
             })
-        });
-        canvas.into_inner().unwrap()
+            .sum();
+        if let Some(splats) = splats {
+            // Light paths start once per camera sample, spread over all pixels
+            canvas.add_splats(splats, samples as f64 / (width * height) as f64);
+        }
+        let layers = aovs
+            .iter()
+            .enumerate()
+            .map(|(i, aov)| {
+                let mut layer = AovLayer::new(*aov, width, height);
+                layer.pixels = values.iter().skip(i).step_by(stride).copied().collect();
+                layer
+            })
+            .collect();
+        (canvas, layers)
     }
 }
 
@@ -121,10 +601,10 @@ impl Transformable for Camera {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::units::color::QuantColor;
+    use crate::units::color::{QuantColor, BLACK, WHITE};
     use crate::units::tuple::{Tuple, Vector};
     use crate::units::utils;
-    use std::f64::consts;
+    use crate::world::Medium;
     #[test]
     fn new() {
         let c = Camera::new(160, 120, consts::FRAC_PI_2);
@@ -173,6 +653,102 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ray_for_sample() {
+        // A pinhole camera ignores the lens sample
+        let c = Camera::new(201, 101, consts::FRAC_PI_2);
+        let r = c.ray_for_sample(100.5, 50.5, (0.1, 0.9));
+        assert_eq!(r.origin, Point::new(0, 0, 0));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+
+        // A lens sample moves the origin, but the ray still passes the focal point
+        let c = Camera::new(201, 101, consts::FRAC_PI_2).set_aperture(0.5, 4.);
+        let r = c.ray_for_sample(100.5, 50.5, (1., 0.5));
+        assert_eq!(r.origin, Point::new(0.25, 0., 0.));
+        assert_eq!(
+            r.position((4_f64.powi(2) + 0.25_f64.powi(2)).sqrt()),
+            Point::new(0, 0, -4)
+        );
+
+        // The lens center is the pinhole ray
+        let r = c.ray_for_sample(100.5, 50.5, (0.5, 0.5));
+        assert_eq!(r.origin, Point::new(0, 0, 0));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+
+        // Polygonal apertures stay within the lens radius
+        let c = c.set_aperture_shape(5, 0.3);
+        let r = c.ray_for_sample(100.5, 50.5, (0.77, 0.21));
+        assert!((r.origin - Point::new(0, 0, 0)).magnitude() <= 0.25);
+    }
+
+    #[test]
+    fn projection() {
+        // An orthographic camera shoots parallel rays
+        let c =
+            Camera::new(200, 100, consts::FRAC_PI_2).set_projection(Projection::Orthographic(4.));
+        let r = c.ray_for_pixel(0, 0);
+        assert_eq!(r.origin, Point::new(1.99, 0.99, 0.));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+        let r = c.ray_for_pixel(199, 99);
+        assert_eq!(r.origin, Point::new(-1.99, -0.99, 0.));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+
+        // A fisheye looks straight ahead in the center and sideways at the edge
+        let c = Camera::new(200, 200, consts::PI).set_projection(Projection::Fisheye);
+        let r = c.ray_for_sample(100., 100., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+        let r = c.ray_for_sample(0., 100., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(1, 0, 0));
+
+        // Fisheye corners outside the circle stay empty
+        assert!(c.sees(0., 100.));
+        assert!(!c.sees(10., 10.));
+        let corners = c
+            .set_samples(4)
+            .render(World::new().set_background(Background::Color(WHITE)));
+        assert_eq!(corners.get_pixel(0, 0), BLACK);
+        assert_eq!(corners.get_coverage(0, 0), 0.);
+        assert_eq!(corners.get_pixel(100, 0), WHITE);
+
+        // An equirectangular panorama covers the whole sphere
+        let c =
+            Camera::new(400, 200, consts::FRAC_PI_2).set_projection(Projection::Equirectangular);
+        let r = c.ray_for_sample(200., 100., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+        let r = c.ray_for_sample(0., 100., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(0, 0, 1));
+        let r = c.ray_for_sample(100., 100., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(1, 0, 0));
+        let r = c.ray_for_sample(200., 0., (0.5, 0.5));
+        assert_eq!(r.direction, Vector::new(0, 1, 0));
+
+        // Projections respect the camera transformation
+        let c = c.translate(0, -2, 0);
+        let r = c.ray_for_sample(200., 100., (0.5, 0.5));
+        assert_eq!(r.origin, Point::new(0, 2, 0));
+        assert_eq!(r.direction, Vector::new(0, 0, -1));
+    }
+
+    #[test]
+    fn project() {
+        // A point on a pixel ray lands in the pixel center
+        let c = Camera::new(201, 101, consts::FRAC_PI_2).transform(Matrix::view_transform(
+            Point::new(1, 2, -5),
+            Point::new(0, 0, 0),
+            Vector::new(0, 1, 0),
+        ));
+        assert!(c.is_pinhole());
+        assert_eq!(c.position(), Point::new(1, 2, -5));
+        let r = c.ray_for_pixel(30, 70);
+        let (px, py, cos) = c.project(r.position(3.)).unwrap();
+        assert!(utils::float_eq(px, 30.5) && utils::float_eq(py, 70.5));
+        assert!(cos > 0. && cos < 1.);
+
+        // Points behind the camera or off the canvas are not seen
+        assert_eq!(c.project(Point::new(2, 4, -10)), None);
+        assert_eq!(c.project(Point::new(100, 0, 0)), None);
+    }
+
     #[test]
     fn render() {
         let w = World::default();
@@ -183,6 +759,207 @@ mod tests {
         ));
         let image = c.render(w);
 
-        assert_eq!(image.get_pixel(5, 5), QuantColor::new(96, 120, 72));
+        assert_eq!(image.get_pixel(5, 5), QuantColor::new(97, 121, 73));
+
+        // Samples are spread over the shutter interval
+        let shutter = Camera::new(11, 11, consts::FRAC_PI_2)
+            .set_samples(8)
+            .set_shutter(1., 2.);
+        for sample in 0..8 {
+            let r = shutter.sample_ray(3, 4, sample).0.unwrap();
+            assert!(r.time >= 1. && r.time < 2.);
+        }
+
+        // Rendering with a lens and multiple samples is reproducible
+        let c = c.set_samples(4).set_aperture(0.2, 4.);
+        let a = c.render(World::default());
+        let b = c.render(World::default());
+        assert_eq!(a.get_pixel(5, 5), b.get_pixel(5, 5));
+        assert_eq!(a.get_pixel(2, 7), b.get_pixel(2, 7));
+    }
+
+    #[test]
+    fn render_with_aovs() {
+        let c = Camera::new(11, 11, consts::FRAC_PI_2).transform(Matrix::view_transform(
+            Point::new(0, 0, -5),
+            Point::new(0, 0, 0),
+            Vector::new(0, 1, 0),
+        ));
+        let aovs = [Aov::Depth, Aov::Normal, Aov::ObjectIndex, Aov::Direct];
+        let (image, layers) = c.render_with_aovs(World::default(), &aovs);
+        assert_eq!(image.get_pixel(5, 5), QuantColor::new(97, 121, 73));
+        assert_eq!(layers.len(), 4);
+
+        // The center pixel hits the front of the outer sphere
+        let center = 5 * 11 + 5;
+        assert!((layers[0].pixels[center][0] - 4.).abs() < 1e-5);
+        assert_eq!(layers[1].pixels[center], [0., 0., -1.]);
+        assert_eq!(layers[2].pixels[center][0], 1.);
+        assert!((layers[3].pixels[center][1] - 0.47583).abs() < 1e-5);
+
+        // Missed pixels stay empty
+        assert_eq!(layers[0].pixels[0], [0., 0., 0.]);
+        assert_eq!(layers[2].pixels[0], [0., 0., 0.]);
+
+        // Path tracing only gathers geometry of the first hit
+        let c = c.set_integrator(Integrator::PathTraced(2));
+        let (_, layers) = c.render_with_aovs(World::default(), &aovs);
+        assert_eq!(layers[1].pixels[center], [0., 0., -1.]);
+        assert_eq!(layers[2].pixels[center][0], 1.);
+        assert_eq!(layers[3].pixels[center], [0., 0., 0.]);
+    }
+
+    #[test]
+    fn coverage() {
+        let c = Camera::new(11, 11, consts::FRAC_PI_2)
+            .set_samples(16)
+            .transform(Matrix::view_transform(
+                Point::new(0, 0, -5),
+                Point::new(0, 0, 0),
+                Vector::new(0, 1, 0),
+            ));
+        let world = World::default().set_background(Background::Transparent);
+        let image = c.render(world);
+        assert!(image.transparent);
+        assert_eq!(image.get_coverage(5, 5), 1.);
+        assert_eq!(image.get_coverage(0, 0), 0.);
+
+        // Edges of the sphere are partially covered
+        let partial = (0..11).any(|x| {
+            let coverage = image.get_coverage(x, 5);
+            coverage > 0. && coverage < 1.
+        });
+        assert!(partial);
+
+        // Surface Aovs of partially covered pixels average only the hits
+        let world = World::default().set_background(Background::Transparent);
+        let (image, layers) = c.render_with_aovs(world, &[Aov::Normal]);
+        let x = (0..11)
+            .find(|&x| image.get_coverage(x, 5) > 0. && image.get_coverage(x, 5) < 1.)
+            .unwrap();
+        let [nx, ny, nz] = layers[0].pixels[5 * 11 + x];
+        assert!((nx * nx + ny * ny + nz * nz).sqrt() > 0.9);
+
+        // Fog scatters light into every pixel and keeps the surfaces behind it
+        let fog = World::default().set_fog(Some(Medium::new(0., 0.05)));
+        let (image, layers) = c.render_with_aovs(fog, &[Aov::Normal]);
+        assert_eq!(image.get_coverage(0, 0), 1.);
+        assert_eq!(image.get_coverage(5, 5), 1.);
+        assert!(layers[0].pixels[5 * 11 + 5][2] < -0.9);
+        assert_eq!(layers[0].pixels[0], [0., 0., 0.]);
+
+        // The background shows where rays miss
+        let world = World::default().set_background(Background::Color(WHITE));
+        let image = c.render(world);
+        assert!(!image.transparent);
+        assert_eq!(image.get_pixel(0, 0), WHITE);
+    }
+
+    #[test]
+    fn adaptive() {
+        let c = Camera::new(11, 11, consts::FRAC_PI_2)
+            .set_adaptive(Some(Adaptive::new(4, 64, 0.001)))
+            .transform(Matrix::view_transform(
+                Point::new(0, 0, -5),
+                Point::new(0, 0, 0),
+                Vector::new(0, 1, 0),
+            ));
+        let world = World::default().set_background(Background::Color(WHITE));
+        let (image, layers) = c.render_with_aovs(world, &[Aov::Samples]);
+        let counts = &layers[0].pixels;
+
+        // The flat background stops at the minimum, the sphere's edge takes the most
+        assert_eq!(counts[0][0], 4.);
+        assert!(counts.iter().any(|count| count[0] == 64.));
+        assert_eq!(image.get_pixel(0, 0), WHITE);
+        let partial = (0..11).any(|x| {
+            let coverage = image.get_coverage(x, 5);
+            coverage > 0. && coverage < 1.
+        });
+        assert!(partial);
+
+        // The heatmap goes from blue to red
+        let heatmap = layers[0].to_canvas();
+        assert_eq!(heatmap.get_pixel(0, 0), QuantColor::new(0, 0, 128));
+        let most = counts.iter().position(|count| count[0] == 64.).unwrap();
+        assert_eq!(
+            heatmap.get_pixel(most % 11, most / 11),
+            QuantColor::new(128, 0, 0)
+        );
+    }
+
+    #[test]
+    fn render_job() {
+        let c = Camera::new(11, 11, consts::FRAC_PI_2).transform(Matrix::view_transform(
+            Point::new(0, 0, -5),
+            Point::new(0, 0, 0),
+            Vector::new(0, 1, 0),
+        ));
+
+        // Every row reports its progress, the last report covers the image
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let job = RenderJob::new().on_progress(move |p| sender.send(p).unwrap());
+        assert!(c.render_job(World::default(), &[], &job).is_some());
+        let reports: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(reports.len(), 11);
+        let last = reports.iter().max_by_key(|p| p.pixels).unwrap();
+        assert_eq!((last.pixels, last.tiles), (121, 11));
+        assert_eq!(last.fraction(), 1.);
+
+        // Every sample casts one camera ray, the sphere adds shadow rays and tests
+        let stats = job.stats();
+        assert_eq!(stats.camera_rays, 121);
+        assert!(stats.shadow_rays > 0);
+        assert!(stats.primitive_tests > stats.rays());
+        let mirror = Camera::new(11, 11, consts::FRAC_PI_2)
+            .set_integrator(Integrator::PathTraced(3))
+            .set_samples(4)
+            .transform(Matrix::view_transform(
+                Point::new(0, 0, -5),
+                Point::new(0, 0, 0),
+                Vector::new(0, 1, 0),
+            ));
+        let world = World::default();
+        mirror.render_job(world, &[], &job).unwrap();
+        let stats = job.stats();
+        assert_eq!(stats.camera_rays, 484);
+        assert!(stats.diffuse_rays > 0 && stats.max_depth <= 3);
+
+        // A cancelled job stops without an image
+        let job = RenderJob::new();
+        job.cancel();
+        assert!(c.render_job(World::default(), &[], &job).is_none());
+    }
+
+    #[test]
+    fn sequence() {
+        let c = Camera::new(11, 11, consts::FRAC_PI_2)
+            .set_samples(8)
+            .set_aperture(0.2, 5.)
+            .set_integrator(Integrator::PathTraced(2))
+            .transform(Matrix::view_transform(
+                Point::new(0, 0, -5),
+                Point::new(0, 0, 0),
+                Vector::new(0, 1, 0),
+            ));
+        let world = || World::default().set_background(Background::Color(WHITE));
+        for sequence in [
+            Sequence::Random,
+            Sequence::Halton,
+            Sequence::Sobol,
+            Sequence::R2,
+        ] {
+            let c = c.set_sequence(sequence);
+            // Renders don't depend on the number of threads
+            let single = c.set_threads(1).render(world());
+            assert_eq!(single.radiance, c.set_threads(3).render(world()).radiance);
+
+            // The sphere edge still gets partial coverage
+            let partial = (0..11).any(|x| {
+                let coverage = single.get_coverage(x, 5);
+                coverage > 0. && coverage < 1.
+            });
+            assert!(partial);
+        }
     }
 }
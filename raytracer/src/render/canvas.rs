@@ -1,10 +1,15 @@
 //! Canvas and all it's functions
 extern crate image;
-use crate::units::color::QuantColor;
-use image::{ImageBuffer, ImageFormat, Rgb, RgbImage};
+use crate::render::aov::AovLayer;
+use crate::render::exr::{self, ExrChannel, ExrPrecision};
+use crate::render::output::Output;
+use crate::units::color::{quantize, rgb, QuantColor};
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
 /// Canvas is a datastructure that represents image canvas
 #[derive(Debug)]
 pub struct Canvas {
@@ -12,13 +17,65 @@ pub struct Canvas {
     pub width: usize,
     /// Canvas height
     pub height: usize,
-    /// Pixel storage of QuantColors
-    pub pixels: Vec<Vec<QuantColor>>,
+    /// Linear radiance of every pixel where 1.0 is white, row by row in one contiguous buffer
+    ///
+    /// Every format is written from it, so renders keep precision below a QuantColor step
+    /// until the output pipeline rounds them.
+    pub radiance: Vec<[f32; 3]>,
+    /// Fraction of each pixel covered by objects, row by row
+    pub coverage: Vec<f64>,
+    /// Write coverage as alpha, pixels then hold colors premultiplied by their coverage
+    pub transparent: bool,
+    /// Pipeline used when the canvas is written to a file
+    pub output: Output,
 }
 
 pub struct CanvasPart {
     pub size: usize,
-    pub pixels: Vec<Vec<QuantColor>>,
+    pub radiance: Vec<[f32; 3]>,
+}
+
+/// Light added to the pixels of a canvas from any thread, kept apart from the pixels
+/// while they are rendered
+///
+/// Light is summed in fixed point, integer sums don't depend on the order threads add
+/// them in, so renders are the same whatever the number of threads.
+#[derive(Debug)]
+pub struct Splats {
+    width: usize,
+    /// Channels in units of `SPLAT_SCALE`, where `SPLAT_SCALE` is white
+    values: Vec<AtomicI64>,
+}
+
+/// Fixed point steps per unit of white in splats, far finer than an 8 bit step while a
+/// pixel can still gather two billion times white
+const SPLAT_SCALE: f64 = (1_u64 << 32) as f64;
+
+impl Splats {
+    /// Creates new empty Splats for a canvas of given width and height
+    pub fn new(width: usize, height: usize) -> Splats {
+        Splats {
+            width,
+            values: std::iter::repeat_with(|| AtomicI64::new(0))
+                .take(width * height * 3)
+                .collect(),
+        }
+    }
+
+    /// Adds light to a pixel, safe to call from many threads at once
+    ///
+    /// # Arguments
+    /// * `x` - x coordinate
+    /// * `y` - y coordinate
+    /// * `color` - light per channel, 1.0 is white
+    pub fn splat(&self, x: usize, y: usize, color: [f64; 3]) {
+        for (i, &value) in color.iter().enumerate() {
+            let value = (value * SPLAT_SCALE).round() as i64;
+            if value != 0 {
+                self.values[(y * self.width + x) * 3 + i].fetch_add(value, Ordering::Relaxed);
+            }
+        }
+    }
 }
 
 impl Canvas {
@@ -37,17 +94,13 @@ impl Canvas {
     /// let c = Canvas::new(10, 10);
     /// ```
     pub fn new(width: usize, height: usize) -> Canvas {
-        let v = std::iter::repeat_with(|| {
-            std::iter::repeat_with(|| QuantColor::new(0, 0, 0))
-                .take(width)
-                .collect()
-        })
-        .take(height as usize)
-        .collect();
         Canvas {
             width,
             height,
-            pixels: v,
+            radiance: vec![[0.; 3]; width * height],
+            coverage: vec![1.; width * height],
+            transparent: false,
+            output: Output::default(),
         }
     }
 
@@ -77,10 +130,28 @@ impl Canvas {
         if self.height <= y {
             panic!("y = {} out of range, maximum height: {}", y, self.height);
         }
-        self.pixels[y][x] = color;
+        self.radiance[y * self.width + x] = rgb(color).map(|c| c as f32);
+    }
+
+    /// Writes linear radiance of a pixel at given coordinates, 1.0 is white
+    ///
+    /// If the coordinates are out of bounds it panics
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - x coordinate
+    /// * `y` - y coordinate
+    /// * `radiance` - unclamped radiance per channel
+    pub fn write_radiance(&mut self, x: usize, y: usize, radiance: [f64; 3]) {
+        self.radiance[y * self.width + x] = radiance.map(|c| c as f32);
+    }
+
+    /// Returns linear radiance of a pixel at given coordinates
+    pub fn get_radiance(&self, x: usize, y: usize) -> [f32; 3] {
+        self.radiance[y * self.width + x]
     }
 
-    /// Returns a pixel at given coordinates
+    /// Returns a pixel at given coordinates, quantized from its radiance
     ///
     /// If the coordinates are out of bounds it panics
     ///
@@ -102,7 +173,41 @@ impl Canvas {
         if self.height <= y {
             panic!("y out of range, maximum height: {}", self.height);
         }
-        self.pixels[y][x]
+        quantize(self.radiance[y * self.width + x].map(f64::from))
+    }
+
+    /// Writes coverage of a pixel at given coordinates
+    ///
+    /// If the coordinates are out of bounds it panics
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - x coordinate
+    /// * `y` - y coordinate
+    /// * `coverage` - covered fraction between 0 and 1
+    pub fn write_coverage(&mut self, x: usize, y: usize, coverage: f64) {
+        self.coverage[y * self.width + x] = coverage;
+    }
+
+    /// Returns coverage of a pixel at given coordinates
+    pub fn get_coverage(&self, x: usize, y: usize) -> f64 {
+        self.coverage[y * self.width + x]
+    }
+
+    /// Adds splats divided by the number of light paths per pixel to the radiance
+    pub fn add_splats(&mut self, splats: Splats, paths_per_pixel: f64) {
+        for (i, splat) in splats.values.into_iter().enumerate() {
+            let value = splat.into_inner() as f64 / SPLAT_SCALE;
+            if value == 0. {
+                continue;
+            }
+            self.radiance[i / 3][i % 3] += (value / paths_per_pixel) as f32;
+        }
+    }
+
+    /// Sets the output pipeline used when writing files
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
     }
 
     /// Writes PPM file
@@ -112,14 +217,14 @@ impl Canvas {
     /// * `path` - a location to write
     pub fn write_ppm<P: AsRef<Path>>(&self, path: P) {
         let mut file = File::create(path).unwrap();
-        let headers: String = format!("P3\n{} {}\n255\n", self.height, self.width);
+        let headers: String = format!("P3\n{} {}\n255\n", self.width, self.height);
         file.write_all(headers.as_bytes()).unwrap();
-        for row in self.pixels.iter() {
+        for row in self.radiance.chunks(self.width) {
             let mut v: Vec<String> = Vec::new();
             for pixel in row.iter() {
-                v.push((pixel.r).to_string());
-                v.push((pixel.g).to_string());
-                v.push((pixel.b).to_string());
+                for channel in self.output.map(*pixel) {
+                    v.push(channel.to_string());
+                }
             }
             for item in v.iter() {
                 file.write_all(format!("{} ", item).as_bytes()).unwrap();
@@ -135,29 +240,139 @@ impl Canvas {
     /// * `path` - a location to write
     ///
     pub fn write_png<P: AsRef<Path>>(&self, path: P) {
+        if self.transparent {
+            return self.write_png_rgba(path);
+        }
         let mut image: RgbImage = ImageBuffer::new(self.width as u32, self.height as u32);
-        for column in 0..(self.width - 1) {
-            for x in 0..(self.height - 1) {
-                let r = self.pixels[x as usize][column as usize].r as u8;
-                let g = self.pixels[x as usize][column as usize].g as u8;
-                let b = self.pixels[x as usize][column as usize].b as u8;
-                image.put_pixel(column as u32, x as u32, Rgb([r, g, b]));
-            }
+        for (i, pixel) in self.radiance.iter().enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            image.put_pixel(x as u32, y as u32, Rgb(self.output.map(*pixel)));
+        }
+        image.save_with_format(path, ImageFormat::Png).unwrap();
+    }
+
+    /// Writes PNG file with coverage as alpha and straight colors
+    fn write_png_rgba<P: AsRef<Path>>(&self, path: P) {
+        let mut image: RgbaImage = ImageBuffer::new(self.width as u32, self.height as u32);
+        for (i, (pixel, &alpha)) in self.radiance.iter().zip(&self.coverage).enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            let color = if alpha > 0. {
+                pixel.map(|c| c / alpha as f32)
+            } else {
+                *pixel
+            };
+            let [r, g, b] = self.output.map(color);
+            let a = (alpha.clamp(0., 1.) * 255.).round() as u8;
+            image.put_pixel(x as u32, y as u32, Rgba([r, g, b, a]));
         }
         image.save_with_format(path, ImageFormat::Png).unwrap();
     }
+
+    /// Writes Radiance HDR file with unclamped radiance
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - a location to write
+    pub fn write_hdr<P: AsRef<Path>>(&self, path: P) {
+        let data: Vec<Rgb<f32>> = self.radiance.iter().copied().map(Rgb).collect();
+        let file = File::create(path).unwrap();
+        HdrEncoder::new(file)
+            .encode(&data, self.width, self.height)
+            .unwrap();
+    }
+
+    /// Writes PFM file with unclamped radiance
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - a location to write
+    pub fn write_pfm<P: AsRef<Path>>(&self, path: P) {
+        let mut file = std::io::BufWriter::new(File::create(path).unwrap());
+        let headers = format!("PF\n{} {}\n-1.0\n", self.width, self.height);
+        file.write_all(headers.as_bytes()).unwrap();
+        // PFM stores the bottom row first
+        for row in self.radiance.chunks(self.width).rev() {
+            for pixel in row {
+                for channel in pixel {
+                    file.write_all(&channel.to_le_bytes()).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Writes OpenEXR file with unclamped radiance
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - a location to write
+    /// * `precision` - half or full float channels
+    pub fn write_exr<P: AsRef<Path>>(&self, path: P, precision: ExrPrecision) {
+        self.write_exr_layers(path, &[], precision);
+    }
+
+    /// Writes OpenEXR file with the beauty image and a layer for every Aov
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - a location to write
+    /// * `layers` - rendered Aovs of the same size as the canvas
+    /// * `precision` - half or full float channels
+    pub fn write_exr_layers<P: AsRef<Path>>(
+        &self,
+        path: P,
+        layers: &[AovLayer],
+        precision: ExrPrecision,
+    ) {
+        let mut channels: Vec<ExrChannel> = ["R", "G", "B"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ExrChannel::new(name, self.radiance.iter().map(|p| p[i]).collect()))
+            .collect();
+        if self.transparent {
+            let alpha = self.coverage.iter().map(|a| *a as f32).collect();
+            channels.push(ExrChannel::new("A", alpha));
+        }
+        for layer in layers {
+            channels.extend(layer.exr_channels());
+        }
+        exr::write_exr(path, self.width, self.height, &channels, precision).unwrap();
+    }
+
+    /// Writes the canvas in the format matching the file extension
+    ///
+    /// Supported extensions are png, ppm, hdr, pfm and exr, which is written with half floats.
+    /// Unknown extensions are written as PNG.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - a location to write
+    pub fn write<P: AsRef<Path>>(&self, path: P) {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("ppm") => self.write_ppm(path),
+            Some("hdr") => self.write_hdr(path),
+            Some("pfm") => self.write_pfm(path),
+            Some("exr") => self.write_exr(path, ExrPrecision::Half),
+            _ => self.write_png(path),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rayon::prelude::*;
     #[test]
     fn new() {
         let canvas = Canvas::new(10, 20);
         assert_eq!(10, canvas.width);
         assert_eq!(20, canvas.height);
-        assert_eq!(20, canvas.pixels.len());
-        assert_eq!(10, canvas.pixels[0].len());
+        assert_eq!(200, canvas.radiance.len());
+        assert_eq!(200, canvas.coverage.len());
     }
     #[test]
     fn write_pixel() {
@@ -167,16 +382,145 @@ mod tests {
         assert_eq!(c, canvas.get_pixel(5, 5));
     }
 
+    #[test]
+    fn splat() {
+        // Splats from many threads add up and land on the pixels when resolved
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_pixel(2, 1, QuantColor::new(10, 10, 10));
+        let splats = Splats::new(4, 2);
+        (0..1000)
+            .into_par_iter()
+            .for_each(|_| splats.splat(2, 1, [0.002, 0.004, 0.]));
+        splats.splat(0, 0, [1. / 1024., 0., 0.]);
+        canvas.add_splats(splats, 2.);
+        assert_eq!(canvas.get_pixel(2, 1), QuantColor::new(265, 520, 10));
+        assert_eq!(canvas.get_pixel(1, 1), QuantColor::new(0, 0, 0));
+
+        // Sums don't depend on the order splats arrive in
+        let values = [0.1, 0.2, 0.3];
+        let (forward, backward) = (Splats::new(1, 1), Splats::new(1, 1));
+        values.iter().for_each(|&v| forward.splat(0, 0, [v; 3]));
+        values
+            .iter()
+            .rev()
+            .for_each(|&v| backward.splat(0, 0, [v; 3]));
+        let sums = |splats: Splats| splats.values.into_iter().map(|v| v.into_inner());
+        assert!(sums(forward).eq(sums(backward)));
+
+        // Splats below an 8 bit step are kept by the radiance, the pixel agrees with it
+        assert_eq!(canvas.get_radiance(0, 0), [1. / 2048., 0., 0.]);
+        assert_eq!(canvas.get_pixel(0, 0), QuantColor::new(0, 0, 0));
+    }
+
     #[test]
     fn write_ppm() {
         let mut canvas = Canvas::new(10, 20);
         canvas.write_pixel(3, 3, QuantColor::new(30, 50, 40));
         canvas.write_ppm("../target/image.ppm");
+        let ppm = std::fs::read_to_string("../target/image.ppm").unwrap();
+        assert!(ppm.starts_with("P3\n10 20\n255\n"));
     }
     #[test]
     fn write_jpg() {
         let mut canvas = Canvas::new(10, 20);
         canvas.write_pixel(3, 3, QuantColor::new(0, 130, 50));
+        canvas.write_pixel(9, 19, QuantColor::new(0, 130, 50));
         canvas.write_png("../target/image.jpg");
+        // The file holds a PNG whatever its extension
+        let data = std::fs::read("../target/image.jpg").unwrap();
+        let image = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(9, 19), &Rgb([0, 130, 50]));
+    }
+
+    #[test]
+    fn write_png_rgba() {
+        // Transparent canvases keep partial coverage as alpha
+        let mut canvas = Canvas::new(4, 2);
+        canvas.transparent = true;
+        canvas.write_pixel(1, 1, QuantColor::new(100, 50, 0));
+        canvas.write_coverage(1, 1, 0.5);
+        canvas.write_coverage(0, 0, 0.);
+        canvas.write_png("../target/image_rgba.png");
+        let image = image::open("../target/image_rgba.png").unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(1, 1), &Rgba([200, 100, 0, 128]));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(3, 1), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn write_png_exposure() {
+        // Exposure brings up light finer than an 8 bit step instead of banding it
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_radiance(1, 1, [0.001, 0.0015, 0.]);
+        canvas.set_output(Output::default().set_exposure(8.));
+        canvas.write_png("../target/image_exposure.png");
+        let image = image::open("../target/image_exposure.png")
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(image.get_pixel(1, 1), &Rgb([65, 98, 0]));
+
+        // Straight colors of transparent pixels come from the radiance too
+        canvas.transparent = true;
+        canvas.write_coverage(1, 1, 0.5);
+        canvas.write_png("../target/image_exposure_rgba.png");
+        let image = image::open("../target/image_exposure_rgba.png")
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.get_pixel(1, 1), &Rgba([131, 196, 0, 128]));
+    }
+
+    #[test]
+    fn write_hdr() {
+        // Radiance above white survives the round trip
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_pixel(1, 1, QuantColor::new(510, 255, 0));
+        canvas.write("../target/image.hdr");
+        let file = std::io::BufReader::new(File::open("../target/image.hdr").unwrap());
+        let data = image::codecs::hdr::HdrDecoder::new(file)
+            .unwrap()
+            .read_image_hdr()
+            .unwrap();
+        assert_eq!(data[5], Rgb([2., 1., 0.]));
+    }
+
+    #[test]
+    fn write_radiance() {
+        // Radiance keeps steps finer than a QuantColor, the pixel gets the nearest one
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_radiance(1, 1, [0.001, 2.5, 0.]);
+        assert_eq!(canvas.get_radiance(1, 1), [0.001, 2.5, 0.]);
+        assert_eq!(canvas.get_pixel(1, 1), QuantColor::new(0, 638, 0));
+        canvas.write("../target/image_fine.pfm");
+        let data = std::fs::read("../target/image_fine.pfm").unwrap();
+        let red = b"PF\n4 2\n-1.0\n".len() + 12;
+        assert_eq!(&data[red..red + 4], &0.001_f32.to_le_bytes());
+    }
+
+    #[test]
+    fn write_pfm() {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_pixel(1, 0, QuantColor::new(765, 0, 0));
+        canvas.write("../target/image.pfm");
+        let data = std::fs::read("../target/image.pfm").unwrap();
+        let header = b"PF\n4 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + 4 * 2 * 3 * 4);
+
+        // The top row is stored last
+        let red = header.len() + (4 + 1) * 12;
+        assert_eq!(&data[red..red + 4], &3_f32.to_le_bytes());
+    }
+
+    #[test]
+    fn write_exr() {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_pixel(3, 1, QuantColor::new(0, 0, 510));
+        canvas.write_exr("../target/image.exr", ExrPrecision::Float);
+        let data = std::fs::read("../target/image.exr").unwrap();
+        assert_eq!(&data[..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+        // The last sample of the last line is the blue pixel, stored in the B channel first
+        let last_line = data.len() - 4 * 3 * 4;
+        assert_eq!(&data[last_line + 12..last_line + 16], &2_f32.to_le_bytes());
     }
 }
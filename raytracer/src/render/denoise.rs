@@ -0,0 +1,188 @@
+//! Denoising of low sample renders, guided by normal and albedo Aovs
+use crate::render::{AovLayer, Canvas};
+use rayon::prelude::*;
+
+/// Albedo added before dividing it out of a pixel, keeps black surfaces from blowing up
+const ALBEDO_EPSILON: f64 = 0.01;
+
+/// Smallest spread over the distance, narrower ones would only weigh the center pixel
+const MIN_SIGMA_SPATIAL: f64 = 0.5;
+
+/// Joint bilateral filter, neighbours count less the more they differ in distance,
+/// normal, albedo and color
+///
+/// Lighting is filtered with albedo divided out, so textures stay sharp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    /// Neighbours up to this many pixels away are blended
+    pub radius: usize,
+    /// Spread of the weights over the distance in pixels
+    pub sigma_spatial: f64,
+    /// Spread over the difference of lighting, keeps shadow edges the guides don't show
+    /// apart, 1 is white
+    pub sigma_color: f64,
+    /// Spread over the difference of normals
+    pub sigma_normal: f64,
+    /// Spread over the difference of albedo
+    pub sigma_albedo: f64,
+}
+
+impl Denoiser {
+    /// Creates new Denoiser blending neighbours up to a radius
+    pub fn new(radius: usize) -> Denoiser {
+        Denoiser {
+            radius,
+            sigma_spatial: (radius as f64 / 2.).max(MIN_SIGMA_SPATIAL),
+            ..Denoiser::default()
+        }
+    }
+
+    /// Sets how different the lighting of neighbours can be and still get blended
+    pub fn set_strength(&self, strength: f64) -> Denoiser {
+        Denoiser {
+            sigma_color: strength,
+            ..*self
+        }
+    }
+
+    /// Replaces the pixels of a canvas by their filtered values, a radius of 0 keeps them
+    ///
+    /// # Arguments
+    /// * `canvas` - rendered image
+    /// * `normals` - normal Aov of the render
+    /// * `albedo` - albedo Aov of the render
+    pub fn denoise(&self, canvas: &mut Canvas, normals: &AovLayer, albedo: &AovLayer) {
+        if self.radius == 0 {
+            return;
+        }
+        let (width, height) = (canvas.width, canvas.height);
+        let sigma_spatial = self.sigma_spatial.max(MIN_SIGMA_SPATIAL);
+        let guide = |layer: &AovLayer, i: usize| layer.pixels[i].map(f64::from);
+        let lighting: Vec<[f64; 3]> = (0..width * height)
+            .map(|i| {
+                let color = canvas.radiance[i];
+                let albedo = guide(albedo, i);
+                [0, 1, 2].map(|c| color[c] as f64 / (albedo[c] + ALBEDO_EPSILON))
+            })
+            .collect();
+        // Lighting differences are taken on a box blur, so the noise doesn't decide them
+        let blurred: Vec<[f64; 3]> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let (mut sum, mut count) = ([0.; 3], 0.);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        for (s, l) in sum.iter_mut().zip(lighting[ny * width + nx]) {
+                            *s += l;
+                        }
+                        count += 1.;
+                    }
+                }
+                sum.map(|s| s / count)
+            })
+            .collect();
+        let radius = self.radius as isize;
+        canvas
+            .radiance
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, radiance) in row.iter_mut().enumerate() {
+                    let center = y * width + x;
+                    let mut sum = [0.; 3];
+                    let mut total = 0.;
+                    for dy in -radius..=radius {
+                        for dx in -radius..=radius {
+                            let (nx, ny) = (x as isize + dx, y as isize + dy);
+                            if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                                continue;
+                            }
+                            let other = ny as usize * width + nx as usize;
+                            let exponent = (dx * dx + dy * dy) as f64 / sigma_spatial.powi(2)
+                                + distance(blurred[center], blurred[other])
+                                    / self.sigma_color.powi(2)
+                                + distance(guide(normals, center), guide(normals, other))
+                                    / self.sigma_normal.powi(2)
+                                + distance(guide(albedo, center), guide(albedo, other))
+                                    / self.sigma_albedo.powi(2);
+                            let weight = (-exponent / 2.).exp();
+                            for (s, l) in sum.iter_mut().zip(lighting[other]) {
+                                *s += weight * l;
+                            }
+                            total += weight;
+                        }
+                    }
+                    let albedo = guide(albedo, center);
+                    let filtered = [0, 1, 2].map(|c| sum[c] / total * (albedo[c] + ALBEDO_EPSILON));
+                    *radiance = filtered.map(|c| c as f32);
+                }
+            });
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            radius: 6,
+            sigma_spatial: 3.,
+            sigma_color: 1.,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.05,
+        }
+    }
+}
+
+/// Returns squared distance of two triples
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Aov;
+    use crate::units::color::QuantColor;
+    use crate::units::sampling::Rng;
+
+    #[test]
+    fn denoise() {
+        // Left half faces +x and is white, right half faces +y and is gray
+        let (width, height) = (16, 8);
+        let mut canvas = Canvas::new(width, height);
+        let mut normals = AovLayer::new(Aov::Normal, width, height);
+        let mut albedo = AovLayer::new(Aov::Albedo, width, height);
+        let mut rng = Rng::new(7);
+        for y in 0..height {
+            for x in 0..width {
+                let left = x < width / 2;
+                normals.pixels[y * width + x] = if left { [1., 0., 0.] } else { [0., 1., 0.] };
+                albedo.pixels[y * width + x] = [0.5; 3];
+                let level = if left { 200. } else { 60. };
+                let noisy = (level * 2. * rng.next_f64()) as i64;
+                canvas.write_pixel(x, y, QuantColor::new(noisy, noisy, noisy));
+            }
+        }
+        let error = |canvas: &Canvas| {
+            (0..width * height)
+                .map(|i| {
+                    let level = if i % width < width / 2 { 200. } else { 60. };
+                    (canvas.get_pixel(i % width, i / width).g as f64 - level).powi(2)
+                })
+                .sum::<f64>()
+        };
+        let before = error(&canvas);
+        Denoiser::default().denoise(&mut canvas, &normals, &albedo);
+
+        // Noise goes down a lot and the edge between the halves stays
+        assert!(error(&canvas) < before / 4.);
+        assert!(canvas.get_pixel(width / 2 - 1, 4).g > 150);
+        assert!(canvas.get_pixel(width / 2, 4).g < 100);
+
+        // Tiny radii keep the image finite, a radius of 0 leaves it alone
+        let denoised = canvas.radiance.clone();
+        Denoiser::new(0).denoise(&mut canvas, &normals, &albedo);
+        assert_eq!(canvas.radiance, denoised);
+        Denoiser::new(1).denoise(&mut canvas, &normals, &albedo);
+        assert!(canvas.radiance.iter().flatten().all(|c| c.is_finite()));
+    }
+}
@@ -0,0 +1,218 @@
+//! Minimal uncompressed OpenEXR writer
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Storage type of the channels in an OpenEXR file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExrPrecision {
+    /// 16 bit floats
+    #[default]
+    Half,
+    /// 32 bit floats
+    Float,
+}
+
+impl ExrPrecision {
+    /// Pixel type id used in the channel list
+    fn pixel_type(&self) -> i32 {
+        match self {
+            ExrPrecision::Half => 1,
+            ExrPrecision::Float => 2,
+        }
+    }
+
+    /// Bytes a single sample takes
+    fn size(&self) -> usize {
+        match self {
+            ExrPrecision::Half => 2,
+            ExrPrecision::Float => 4,
+        }
+    }
+}
+
+/// Single named channel of an image, samples are stored row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExrChannel {
+    /// channel name, layers are separated by a dot as in `normal.X`
+    pub name: String,
+    /// width * height samples
+    pub samples: Vec<f32>,
+}
+
+impl ExrChannel {
+    /// Creates new ExrChannel
+    pub fn new(name: &str, samples: Vec<f32>) -> ExrChannel {
+        ExrChannel {
+            name: name.to_string(),
+            samples,
+        }
+    }
+}
+
+/// Writes channels to a scanline OpenEXR file without compression
+///
+/// # Arguments
+///
+/// * `path` - a location to write
+/// * `width` - image width
+/// * `height` - image height
+/// * `channels` - channels to write, in any order
+/// * `precision` - storage type of every channel
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+    precision: ExrPrecision,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encode(width, height, channels, precision))?;
+    file.flush()
+}
+
+/// Encodes the whole file into memory
+fn encode(
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+    precision: ExrPrecision,
+) -> Vec<u8> {
+    // Readers expect the channel list sorted by name
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for channel in &channels {
+        assert_eq!(channel.samples.len(), width * height, "Wrong channel size");
+    }
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    let mut list = Vec::new();
+    for channel in &channels {
+        list.extend_from_slice(channel.name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&precision.pixel_type().to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1_i32.to_le_bytes());
+        list.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut out, "channels", "chlist", &list);
+    attribute(&mut out, "compression", "compression", &[0]);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    attribute(&mut out, "dataWindow", "box2i", &window);
+    attribute(&mut out, "displayWindow", "box2i", &window);
+    attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut out, "pixelAspectRatio", "float", &1_f32.to_le_bytes());
+    attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut out, "screenWindowWidth", "float", &1_f32.to_le_bytes());
+    out.push(0);
+
+    // Every scanline is its own block, the offset table points at each of them
+    let line_size = width * channels.len() * precision.size();
+    let first_line = out.len() + height * 8;
+    for y in 0..height {
+        let offset = (first_line + y * (line_size + 8)) as u64;
+        out.extend_from_slice(&offset.to_le_bytes());
+    }
+    for y in 0..height {
+        out.extend_from_slice(&(y as i32).to_le_bytes());
+        out.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for channel in &channels {
+            for sample in &channel.samples[y * width..(y + 1) * width] {
+                match precision {
+                    ExrPrecision::Half => out.extend_from_slice(&to_half(*sample).to_le_bytes()),
+                    ExrPrecision::Float => out.extend_from_slice(&sample.to_le_bytes()),
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Appends a header attribute
+fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+/// Converts a float to the bits of the nearest half float
+pub fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, every NaN becomes a quiet NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal half, shift the mantissa with its implicit leading one
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | (half + round) as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    // Round to nearest, a carry into the exponent is still correct
+    let round = (mantissa >> 12) & 1;
+    sign | (half + round) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_half() {
+        assert_eq!(super::to_half(0.), 0);
+        assert_eq!(super::to_half(1.), 0x3c00);
+        assert_eq!(super::to_half(-2.), 0xc000);
+        assert_eq!(super::to_half(0.5), 0x3800);
+        assert_eq!(super::to_half(65504.), 0x7bff);
+        assert_eq!(super::to_half(1e6), 0x7c00);
+        assert_eq!(super::to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(super::to_half(5.960_464_5e-8), 0x0001);
+        assert_eq!(super::to_half(1e-10), 0);
+    }
+
+    #[test]
+    fn encode() {
+        let channels = [
+            ExrChannel::new("R", vec![1., 2.]),
+            ExrChannel::new("G", vec![3., 4.]),
+        ];
+        let bytes = super::encode(2, 1, &channels, ExrPrecision::Half);
+        assert_eq!(&bytes[0..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+        // The single scanline holds G before R
+        let line = &bytes[bytes.len() - 8..];
+        assert_eq!(line, &[0x00, 0x42, 0x00, 0x44, 0x00, 0x3c, 0x00, 0x40]);
+
+        // The offset table points at the scanline block
+        let offset_at = bytes.len() - 16 - 8;
+        let mut offset = [0; 8];
+        offset.copy_from_slice(&bytes[offset_at..offset_at + 8]);
+        assert_eq!(u64::from_le_bytes(offset) as usize, bytes.len() - 16);
+
+        let bytes = super::encode(2, 1, &channels, ExrPrecision::Float);
+        assert_eq!(&bytes[bytes.len() - 4..], &2_f32.to_le_bytes());
+    }
+}
@@ -0,0 +1,409 @@
+//! Light transport algorithms that turn camera rays into colors
+use crate::render::{stats, AmbientOcclusion, World};
+use crate::units::color::rgb;
+use crate::units::sampling::{self, orthonormal_basis, Rng};
+use crate::units::tuple::Point;
+use crate::units::{Intersection, Ray};
+use crate::world::{Background, Medium, Shading};
+use std::f64::consts;
+
+/// Integrator used by the Camera
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    /// Recursive ray tracing of direct light, mirror reflections and refractions
+    #[default]
+    Whitted,
+    /// Monte Carlo path tracing with diffuse interreflection, holds the maximum bounce count
+    ///
+    /// Environment maps are importance sampled as a light source.
+    PathTraced(usize),
+    /// Clay render shading every hit by its ambient occlusion
+    AmbientOcclusion(AmbientOcclusion),
+    /// Bidirectional path tracing joining camera and light paths, holds the maximum bounce
+    /// count
+    ///
+    /// Needs a pinhole perspective camera to splat light paths, other cameras path trace.
+    Bidirectional(usize),
+}
+
+impl Integrator {
+    /// Returns radiance seen by a camera ray, 1.0 is white, and whether it hit any object
+    pub fn trace(&self, world: &World, ray: Ray, rng: &mut Rng) -> ([f64; 3], bool) {
+        match *self {
+            Integrator::Whitted => world.trace(ray, 5),
+            Integrator::PathTraced(max_depth) | Integrator::Bidirectional(max_depth) => {
+                path_trace(world, ray, max_depth, rng)
+            }
+            Integrator::AmbientOcclusion(ao) => {
+                let intersections = world.intersect(ray);
+                match Intersection::hit(intersections.clone()) {
+                    Some(hit) => {
+                        let c = hit.computations(ray, &intersections);
+                        let open = ao.visibility(world, c.over_point, c.normalv, c.time, rng);
+                        ([open; 3], true)
+                    }
+                    None => (world.background.radiance(ray.direction), false),
+                }
+            }
+        }
+    }
+}
+
+/// Follows a single path through the scene
+///
+/// Every hit adds the direct light of the point light and the environment map, then the path
+/// continues along the diffuse, reflective or refractive lobe, picked by their weights.
+/// Microfacet materials sample their glossy lobe in place of the diffuse and mirror lobes.
+/// Paths entering dispersive materials continue with a single color channel.
+/// Environment light found by diffuse bounces and by sampling the map is weighted with the
+/// power heuristic.
+/// Caustics of the point light are added from the world's photon map at every hit.
+/// In fog and in shapes filled with a medium the path scatters at a random distance picked
+/// by the transmittance, and the bounds of such shapes are crossed without using a bounce.
+fn path_trace(world: &World, ray: Ray, max_depth: usize, rng: &mut Rng) -> ([f64; 3], bool) {
+    let mut radiance = [0.; 3];
+    let mut throughput = [1.; 3];
+    let mut ray = ray;
+    let mut hit_any = false;
+    // Density of the last diffuse bounce, None after specular bounces
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut depth = 0;
+
+    while depth <= max_depth {
+        let intersections = world.intersect(ray);
+        let hit = Intersection::hit(intersections.clone());
+        if let Some(medium) = world.medium_at(ray, &intersections) {
+            let distance = hit.map_or(f64::INFINITY, |hit| hit.t);
+            let travel = -(1. - rng.next_f64()).ln() / medium.extinction();
+            if travel < distance {
+                hit_any = true;
+                let point = ray.position(travel);
+                throughput.iter_mut().for_each(|t| *t *= medium.albedo());
+                scatter_light(world, &medium, point, ray, rng, &throughput, &mut radiance);
+                if depth == max_depth {
+                    break;
+                }
+                let incoming = ray.direction.normalize();
+                let direction = medium
+                    .phase
+                    .sample(incoming, rng.next_f64(), rng.next_f64());
+                bsdf_pdf = Some(medium.phase.eval(incoming.dot(direction)));
+                ray = Ray::new(point, direction)
+                    .set_time(ray.time)
+                    .set_channel(ray.channel)
+                    .set_secondary(true);
+                if !roulette(depth, &mut throughput, rng) {
+                    break;
+                }
+                depth += 1;
+                stats::record(|s| s.scatter_rays += 1);
+                stats::reach_depth(depth);
+                continue;
+            }
+        }
+        let hit = match hit {
+            Some(hit) if hit.object.interior.is_some() => {
+                ray = ray.continue_past(hit.t);
+                continue;
+            }
+            Some(hit) => hit,
+            None => {
+                let weight = match (&world.background, bsdf_pdf) {
+                    (Background::Map(map), Some(pdf)) => {
+                        power_heuristic(pdf, map.pdf(ray.direction))
+                    }
+                    _ => 1.,
+                };
+                let background = world.background.radiance(ray.direction);
+                add(&mut radiance, &throughput, &background, weight);
+                break;
+            }
+        };
+        hit_any = true;
+        let mut c = hit.computations(ray, &intersections);
+        if ray.channel.is_none() && c.is_dispersive() {
+            // Dispersion splits the path, it carries one random channel from here on
+            let channel = ((rng.next_f64() * 3.) as usize).min(2);
+            for (i, t) in throughput.iter_mut().enumerate() {
+                *t *= if i == channel { 3. } else { 0. };
+            }
+            ray = ray.set_channel(Some(channel));
+            c = c.set_channel(channel);
+        }
+        if let Some(medium) = c.medium {
+            let transmittance = medium.material.transmittance(c.t);
+            throughput
+                .iter_mut()
+                .zip(transmittance)
+                .for_each(|(t, a)| *t *= a);
+        }
+        let object = c.object.at_time(c.time);
+        let material = &object.material;
+        let albedo = object
+            .base_color(c.over_point)
+            .map(|a| a * material.diffuse);
+        let base = object.base_color(c.over_point);
+        // Microfacet materials replace both the diffuse and the mirror lobe
+        let microfacet = match material.shading {
+            Shading::Microfacet(microfacet) => Some(microfacet),
+            Shading::Phong => None,
+        };
+
+        if let Some(light) = world.light {
+            let shadow = world.light_transmittance(c.over_point, c.time);
+            if shadow != [0.; 3] {
+                let direct = object.direct_light(light, c.over_point, c.eyev, c.normalv);
+                for i in 0..3 {
+                    radiance[i] += throughput[i] * direct[i] * shadow[i];
+                }
+            }
+        }
+        // Point lights can't be found by bounces, so light focused by glass comes from photons
+        if let Some(caustics) = &world.caustics {
+            let irradiance = caustics.irradiance(c.over_point, c.normalv);
+            for i in 0..3 {
+                radiance[i] += throughput[i] * albedo[i] * irradiance[i];
+            }
+        }
+
+        let (reflect, transparent) = if microfacet.is_some() {
+            (0., material.transparent)
+        } else if material.reflect > 0. && material.transparent > 0. {
+            let reflectance = c.schlick();
+            (
+                material.reflect * reflectance,
+                material.transparent * (1. - reflectance),
+            )
+        } else {
+            (material.reflect, material.transparent)
+        };
+        let diffuse = if microfacet.is_some() {
+            1.
+        } else {
+            luminance(&albedo)
+        };
+        let lobes = diffuse + reflect + transparent;
+        if lobes <= 0. {
+            break;
+        }
+        let diffuse_probability = diffuse / lobes;
+
+        if let (Background::Map(map), true) = (&world.background, diffuse > 0.) {
+            let (direction, light_pdf) = map.sample(rng.next_f64(), rng.next_f64());
+            let cos = direction.dot(c.normalv);
+            let visible = if cos > 0. && light_pdf > 0. {
+                world.transmittance(c.over_point, direction, f64::INFINITY, c.time)
+            } else {
+                [0.; 3]
+            };
+            if visible != [0.; 3] {
+                let (reflected, pdf) = match microfacet {
+                    Some(microfacet) => (
+                        microfacet.eval(base, c.normalv, c.eyev, direction),
+                        diffuse_probability * microfacet.pdf(c.normalv, c.eyev, direction),
+                    ),
+                    None => (
+                        albedo.map(|a| a * cos / consts::PI),
+                        diffuse_probability * cos / consts::PI,
+                    ),
+                };
+                let weight = power_heuristic(light_pdf, pdf) / light_pdf;
+                let light = map.radiance(direction);
+                for i in 0..3 {
+                    radiance[i] += throughput[i] * reflected[i] * light[i] * visible[i] * weight;
+                }
+            }
+        }
+
+        if depth == max_depth {
+            break;
+        }
+
+        let pick = rng.next_f64() * lobes;
+        let (origin, direction) = if let (Some(microfacet), true) = (microfacet, pick < diffuse) {
+            let u = [rng.next_f64(), rng.next_f64(), rng.next_f64()];
+            let (direction, weight, pdf) = match microfacet.sample(base, c.normalv, c.eyev, u) {
+                Some(sample) => sample,
+                None => break,
+            };
+            for (t, w) in throughput.iter_mut().zip(weight) {
+                *t *= w / diffuse_probability;
+            }
+            bsdf_pdf = Some(diffuse_probability * pdf);
+            (c.over_point, direction)
+        } else if pick < diffuse {
+            let (x, y, z) = sampling::cosine_hemisphere(rng.next_f64(), rng.next_f64());
+            let (tangent, bitangent) = orthonormal_basis(c.normalv);
+            for (t, a) in throughput.iter_mut().zip(albedo) {
+                *t *= a / diffuse_probability;
+            }
+            bsdf_pdf = Some(diffuse_probability * z / consts::PI);
+            (c.over_point, tangent * x + bitangent * y + c.normalv * z)
+        } else if pick < diffuse + reflect {
+            throughput.iter_mut().for_each(|t| *t *= lobes);
+            bsdf_pdf = None;
+            (c.over_point, c.reflectv)
+        } else {
+            let direction = match c.refractv() {
+                Some(direction) => direction,
+                None => break,
+            };
+            throughput.iter_mut().for_each(|t| *t *= lobes);
+            bsdf_pdf = None;
+            (c.under_point, direction)
+        };
+        ray = Ray::new(origin, direction)
+            .set_time(c.time)
+            .set_channel(ray.channel)
+            .set_secondary(true);
+
+        if !roulette(depth, &mut throughput, rng) {
+            break;
+        }
+        depth += 1;
+        stats::record(|s| {
+            if pick < diffuse {
+                s.diffuse_rays += 1;
+            } else if pick < diffuse + reflect {
+                s.reflection_rays += 1;
+            } else {
+                s.refraction_rays += 1;
+            }
+        });
+        stats::reach_depth(depth);
+    }
+
+    (radiance, hit_any)
+}
+
+/// Adds direct light of the point light and the environment map scattered at a point in a medium
+fn scatter_light(
+    world: &World,
+    medium: &Medium,
+    point: Point,
+    ray: Ray,
+    rng: &mut Rng,
+    throughput: &[f64; 3],
+    radiance: &mut [f64; 3],
+) {
+    let incoming = ray.direction.normalize();
+    if let Some(light) = world.light {
+        let direction = (light.position - point).normalize();
+        let shadow = world.light_transmittance(point, ray.time);
+        let phase = medium.phase.eval(incoming.dot(direction));
+        // Point light intensity is the irradiance it gives, like on surfaces
+        let intensity = rgb(light.intensity).map(|i| i * consts::PI * phase);
+        for i in 0..3 {
+            radiance[i] += throughput[i] * intensity[i] * shadow[i];
+        }
+    }
+    if let Background::Map(map) = &world.background {
+        let (direction, light_pdf) = map.sample(rng.next_f64(), rng.next_f64());
+        if light_pdf <= 0. {
+            return;
+        }
+        let visible = world.transmittance(point, direction, f64::INFINITY, ray.time);
+        let phase = medium.phase.eval(incoming.dot(direction));
+        let weight = phase * power_heuristic(light_pdf, phase) / light_pdf;
+        let light = map.radiance(direction);
+        for i in 0..3 {
+            radiance[i] += throughput[i] * light[i] * visible[i] * weight;
+        }
+    }
+}
+
+/// Russian roulette keeps long paths unbiased while ending most of them early
+///
+/// Returns false when the path ends.
+fn roulette(depth: usize, throughput: &mut [f64; 3], rng: &mut Rng) -> bool {
+    if depth < 3 {
+        return true;
+    }
+    let survive = throughput.iter().cloned().fold(0., f64::max).min(0.95);
+    if rng.next_f64() >= survive {
+        return false;
+    }
+    throughput.iter_mut().for_each(|t| *t /= survive);
+    true
+}
+
+/// Returns luminance of a linear color
+pub(crate) fn luminance(c: &[f64; 3]) -> f64 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+/// Adds weighted light reaching the camera through a path
+fn add(radiance: &mut [f64; 3], throughput: &[f64; 3], light: &[f64; 3], weight: f64) {
+    for i in 0..3 {
+        radiance[i] += throughput[i] * light[i] * weight;
+    }
+}
+
+/// Weight of a sample from the first of two strategies
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::color::{BLACK, WHITE};
+    use crate::units::objects::{ObjectType, Shape};
+    use crate::units::tuple::{Tuple, Vector};
+    use crate::world::{EnvironmentMap, Material};
+    use std::sync::Arc;
+
+    #[test]
+    fn power_heuristic() {
+        assert_eq!(super::power_heuristic(1., 1.), 0.5);
+        assert_eq!(super::power_heuristic(1., 0.), 1.);
+        assert_eq!(super::power_heuristic(0., 0.), 0.);
+    }
+
+    #[test]
+    fn trace() {
+        // Missed rays see the background in every integrator
+        let world = World::new().set_background(Background::Color(WHITE));
+        let ray = Ray::new(Point::new(0, 0, 0), Vector::new(0, 0, 1));
+        let mut rng = Rng::new(0);
+        assert_eq!(
+            Integrator::Whitted.trace(&world, ray, &mut rng),
+            ([1.; 3], false)
+        );
+        assert_eq!(
+            Integrator::PathTraced(4).trace(&world, ray, &mut rng),
+            ([1.; 3], false)
+        );
+
+        // A white diffuse floor under a uniform white sky reflects all of it
+        let map = EnvironmentMap::new(8, 4, vec![[1.; 3]; 32]);
+        let floor = Shape::new(ObjectType::Plane).set_material(
+            Material::new(WHITE)
+                .set_diffuse(1.)
+                .set_specular(0.)
+                .set_ambient(0.),
+        );
+        let world = World::new()
+            .set_objects(vec![floor])
+            .set_background(Background::Map(Arc::new(map)));
+        let ray = Ray::new(Point::new(0, 1, 0), Vector::new(0, -1, 0));
+        let mut sum = 0.;
+        for sample in 0..2000 {
+            let mut rng = Rng::for_sample(0, 0, sample);
+            let (color, hit) = Integrator::PathTraced(1).trace(&world, ray, &mut rng);
+            assert!(hit);
+            sum += color[1];
+        }
+        assert!((sum / 2000. - 1.).abs() < 0.04);
+
+        // Without any light a path traced surface stays black
+        let world = world.set_background(Background::Color(BLACK));
+        let (color, _) = Integrator::PathTraced(4).trace(&world, ray, &mut rng);
+        assert_eq!(color, [0.; 3]);
+    }
+}
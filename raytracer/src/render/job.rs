@@ -0,0 +1,234 @@
+//! Render jobs, reporting progress of a render and stopping it early
+use crate::render::Stats;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Snapshot of a running render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Pixels finished
+    pub pixels: usize,
+    /// Pixels of the whole image
+    pub total_pixels: usize,
+    /// Tiles finished, the camera renders one row per tile
+    pub tiles: usize,
+    /// Tiles of the whole image
+    pub total_tiles: usize,
+    /// Time since the render started
+    pub elapsed: Duration,
+    /// Estimated time until the render is done
+    pub remaining: Duration,
+    /// Rays of every kind traced per second so far
+    pub rays_per_second: f64,
+}
+
+impl Progress {
+    /// Returns finished fraction of the image
+    pub fn fraction(&self) -> f64 {
+        if self.total_pixels == 0 {
+            1.
+        } else {
+            self.pixels as f64 / self.total_pixels as f64
+        }
+    }
+
+    /// Returns the progress as a line of text for terminals
+    ///
+    /// # Arguments
+    /// * `width` - number of characters of the bar
+    pub fn bar(&self, width: usize) -> String {
+        let filled = (self.fraction() * width as f64).round() as usize;
+        let time = if self.pixels == self.total_pixels {
+            format!("done in {:.1?}", self.elapsed)
+        } else {
+            format!("ETA {:.0?}", self.remaining)
+        };
+        format!(
+            "[{}{}] {:>3.0}% {}/{} tiles, {}, {:.2} Mrays/s",
+            "#".repeat(filled),
+            "-".repeat(width - filled),
+            self.fraction() * 100.,
+            self.tiles,
+            self.total_tiles,
+            time,
+            self.rays_per_second / 1e6
+        )
+    }
+}
+
+/// Handle of a render, receives its progress and can cancel it from another thread
+///
+/// Progress is reported from the render threads after every tile, so the callback should
+/// be quick, like sending the `Progress` over a channel. A job can run many renders one
+/// after another, like the frames of an animation, which then share its thread pool.
+#[derive(Default)]
+pub struct RenderJob {
+    cancelled: Arc<AtomicBool>,
+    on_progress: Option<Box<dyn Fn(Progress) + Send + Sync>>,
+    stats: Mutex<Stats>,
+    /// Pool of renders with a set number of threads, built by the first of them
+    pool: Mutex<Option<Arc<ThreadPool>>>,
+}
+
+impl RenderJob {
+    /// Creates new RenderJob
+    pub fn new() -> RenderJob {
+        RenderJob::default()
+    }
+
+    /// Sets the function called with the progress after every finished tile
+    pub fn on_progress<F: Fn(Progress) + Send + Sync + 'static>(self, callback: F) -> RenderJob {
+        RenderJob {
+            on_progress: Some(Box::new(callback)),
+            ..self
+        }
+    }
+
+    /// Returns flag that cancels the render when set, to be shared with other threads
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    /// Asks the render to stop, tiles stop at their next pixel
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns counts of the finished tiles of the last render
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Returns the job's thread pool, built again only when the number of threads changes
+    pub(crate) fn pool(&self, threads: usize) -> Arc<ThreadPool> {
+        let mut pool = self.pool.lock().unwrap();
+        match &*pool {
+            Some(pool) if pool.current_num_threads() == threads => Arc::clone(pool),
+            _ => {
+                let built = ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("Failed to start render threads");
+                Arc::clone(pool.insert(Arc::new(built)))
+            }
+        }
+    }
+}
+
+/// Counters of a running render shared by the render threads
+pub(crate) struct Tracker<'a> {
+    job: &'a RenderJob,
+    start: Instant,
+    total_pixels: usize,
+    total_tiles: usize,
+    pixels: AtomicUsize,
+    tiles: AtomicUsize,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(job: &'a RenderJob, total_pixels: usize, total_tiles: usize) -> Tracker<'a> {
+        *job.stats.lock().unwrap() = Stats::default();
+        Tracker {
+            job,
+            start: Instant::now(),
+            total_pixels,
+            total_tiles,
+            pixels: AtomicUsize::new(0),
+            tiles: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.job.is_cancelled()
+    }
+
+    /// Counts a finished tile, merges its stats into the job's and reports the progress
+    pub(crate) fn finish_tile(&self, pixels: usize, stats: &Stats) {
+        let pixels = self.pixels.fetch_add(pixels, Ordering::Relaxed) + pixels;
+        let tiles = self.tiles.fetch_add(1, Ordering::Relaxed) + 1;
+        let rays = {
+            let mut total = self.job.stats.lock().unwrap();
+            *total = total.merge(stats);
+            total.rays()
+        };
+        if let Some(callback) = &self.job.on_progress {
+            callback(self.progress(pixels, tiles, rays, self.start.elapsed()));
+        }
+    }
+
+    /// Returns progress of the given counts, the remaining time assumes the pace so far
+    fn progress(&self, pixels: usize, tiles: usize, rays: u64, elapsed: Duration) -> Progress {
+        let remaining = if pixels == 0 {
+            Duration::ZERO
+        } else {
+            elapsed.mul_f64((self.total_pixels - pixels) as f64 / pixels as f64)
+        };
+        Progress {
+            pixels,
+            total_pixels: self.total_pixels,
+            tiles,
+            total_tiles: self.total_tiles,
+            elapsed,
+            remaining,
+            rays_per_second: rays as f64 / elapsed.as_secs_f64().max(1e-9),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn progress() {
+        let job = RenderJob::new();
+        let tracker = Tracker::new(&job, 100, 10);
+        let progress = tracker.progress(25, 2, 50, Duration::from_secs(2));
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(progress.remaining, Duration::from_secs(6));
+        assert_eq!(progress.rays_per_second, 25.);
+        assert_eq!(
+            progress.bar(8),
+            "[##------]  25% 2/10 tiles, ETA 6s, 0.00 Mrays/s"
+        );
+        let done = tracker.progress(100, 10, 200, Duration::from_secs(8));
+        assert!(done
+            .bar(4)
+            .starts_with("[####] 100% 10/10 tiles, done in 8.0s"));
+    }
+
+    #[test]
+    fn render_job() {
+        // Finished tiles reach the callback
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let job = RenderJob::new().on_progress(move |p| sink.lock().unwrap().push(p.pixels));
+        let tracker = Tracker::new(&job, 20, 2);
+        let stats = Stats {
+            camera_rays: 10,
+            max_depth: 1,
+            ..Stats::default()
+        };
+        tracker.finish_tile(10, &stats);
+        tracker.finish_tile(10, &stats);
+        assert_eq!(*reports.lock().unwrap(), vec![10, 20]);
+        assert_eq!(job.stats().camera_rays, 20);
+        assert_eq!(job.stats().max_depth, 1);
+
+        // Renders with the same number of threads share a pool
+        assert!(Arc::ptr_eq(&job.pool(2), &job.pool(2)));
+        assert_eq!(job.pool(3).current_num_threads(), 3);
+
+        // The flag cancels the job from anywhere
+        assert!(!job.is_cancelled());
+        job.cancel_flag().store(true, Ordering::Relaxed);
+        assert!(job.is_cancelled());
+    }
+}
@@ -1,7 +1,28 @@
 //! This module takes care of all your rendering needs
+pub mod adaptive;
+pub mod aov;
+pub mod bdpt;
 pub mod camera;
 pub mod canvas;
+pub mod denoise;
+pub mod exr;
+pub mod integrator;
+pub mod job;
+pub mod occlusion;
+pub mod output;
+pub mod photon;
+pub mod stats;
 pub mod world;
-pub use camera::Camera;
-pub use canvas::{Canvas, CanvasPart};
+pub use adaptive::{Adaptive, PixelStats};
+pub use aov::{Aov, AovLayer, AovSample};
+pub use camera::{Camera, Projection};
+pub use canvas::{Canvas, CanvasPart, Splats};
+pub use denoise::Denoiser;
+pub use exr::ExrPrecision;
+pub use integrator::Integrator;
+pub use job::{Progress, RenderJob};
+pub use occlusion::AmbientOcclusion;
+pub use output::{Output, ToneMap};
+pub use photon::{Caustics, PhotonMap};
+pub use stats::{Stats, Timings};
 pub use world::World;
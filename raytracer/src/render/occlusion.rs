@@ -0,0 +1,130 @@
+//! Ambient occlusion, how open the hemisphere above a point is
+use crate::render::{stats, World};
+use crate::units::sampling::{self, orthonormal_basis, Rng};
+use crate::units::tuple::{Point, Vector};
+use crate::units::Ray;
+
+/// Ambient occlusion settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientOcclusion {
+    /// Number of hemisphere rays per point
+    pub samples: usize,
+    /// Objects further than this don't occlude
+    pub distance: f64,
+}
+
+impl AmbientOcclusion {
+    /// Creates new AmbientOcclusion
+    pub fn new(samples: usize, distance: f64) -> AmbientOcclusion {
+        AmbientOcclusion { samples, distance }
+    }
+
+    /// Returns fraction of the hemisphere above a point that is open, weighted by the cosine
+    ///
+    /// Shapes occlude like they block light, by casting shadows, whether the camera sees
+    /// them or not.
+    ///
+    /// # Arguments
+    /// * `world` - world with the occluding objects
+    /// * `point` - point lifted above the surface, like `Computations::over_point`
+    /// * `normalv` - surface normal
+    /// * `time` - moment the objects are placed at
+    /// * `rng` - random numbers for the ray directions
+    pub fn visibility(
+        &self,
+        world: &World,
+        point: Point,
+        normalv: Vector,
+        time: f64,
+        rng: &mut Rng,
+    ) -> f64 {
+        if self.samples == 0 {
+            return 1.;
+        }
+        let (tangent, bitangent) = orthonormal_basis(normalv);
+        let open = (0..self.samples)
+            .filter(|_| {
+                let (x, y, z) = sampling::cosine_hemisphere(rng.next_f64(), rng.next_f64());
+                let direction = tangent * x + bitangent * y + normalv * z;
+                let ray = Ray::new(point, direction).set_time(time);
+                stats::record(|s| s.shadow_rays += 1);
+                world.objects.iter().filter(|o| o.casts_shadow).all(|o| {
+                    stats::record(|s| s.primitive_tests += 1);
+                    o.intersect(ray)
+                        .iter()
+                        .all(|i| i.t <= 0. || i.t >= self.distance)
+                })
+            })
+            .count();
+        open as f64 / self.samples as f64
+    }
+
+    /// Returns visibility with random numbers seeded by the point
+    ///
+    /// Renderers without their own random numbers get the same noise for the same point.
+    pub fn visibility_at(&self, world: &World, point: Point, normalv: Vector, time: f64) -> f64 {
+        let seed = [point.x, point.y, point.z, time]
+            .iter()
+            .fold(0_u64, |h, v| {
+                (h ^ v.to_bits()).wrapping_mul(0x100_0000_01B3)
+            });
+        self.visibility(world, point, normalv, time, &mut Rng::new(seed))
+    }
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            samples: 16,
+            distance: 1.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::objects::{ObjectType, Shape};
+    use crate::units::tuple::Tuple;
+    use crate::units::Transformable;
+
+    #[test]
+    fn visibility() {
+        let floor = Shape::new(ObjectType::Plane);
+        let up = Vector::new(0, 1, 0);
+        let p = Point::new(0., 0.0001, 0.);
+        let ao = AmbientOcclusion::new(64, 100.);
+        let mut rng = Rng::new(1);
+
+        // Open sky above a floor
+        let w = World::new().set_objects(vec![floor.clone()]);
+        assert_eq!(ao.visibility(&w, p, up, 0., &mut rng), 1.);
+
+        // A ceiling close above hides everything
+        let ceiling = Shape::new(ObjectType::Plane).translate(0, 1, 0);
+        let w = World::new().set_objects(vec![floor.clone(), ceiling.clone()]);
+        assert_eq!(ao.visibility(&w, p, up, 0., &mut rng), 0.);
+
+        // Hidden from the camera it still occludes, without casting shadows it doesn't
+        let hidden = ceiling.set_visible(false, false);
+        let w = World::new().set_objects(vec![floor.clone(), hidden.clone()]);
+        assert_eq!(ao.visibility(&w, p, up, 0., &mut rng), 0.);
+        let w = World::new().set_objects(vec![floor.clone(), hidden.set_casts_shadow(false)]);
+        assert_eq!(ao.visibility(&w, p, up, 0., &mut rng), 1.);
+        let w = World::new().set_objects(vec![floor.clone(), ceiling]);
+
+        // Beyond the maximum distance it doesn't occlude
+        let far = AmbientOcclusion::new(64, 0.5);
+        assert_eq!(far.visibility(&w, p, up, 0., &mut rng), 1.);
+
+        // A sphere resting on the floor darkens the point next to it
+        let ball = Shape::default().translate(1.2, 1., 0.);
+        let w = World::new().set_objects(vec![floor, ball]);
+        let v = ao.visibility(&w, p, up, 0., &mut rng);
+        assert!(v > 0.3 && v < 1.);
+        assert_eq!(
+            ao.visibility_at(&w, p, up, 0.),
+            ao.visibility_at(&w, p, up, 0.)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Conversion of rendered colors to displayable values
+
+/// Operator that compresses bright colors into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    /// Cuts off everything above white
+    #[default]
+    Clamp,
+    /// `c / (1 + c)`, keeps detail in highlights
+    Reinhard,
+    /// Filmic curve fitted to the ACES reference transform
+    Aces,
+}
+
+impl ToneMap {
+    /// Maps a linear channel value to the range [0, 1]
+    pub fn map(&self, value: f64) -> f64 {
+        let value = value.max(0.);
+        let mapped = match self {
+            ToneMap::Clamp => value,
+            ToneMap::Reinhard => value / (1. + value),
+            ToneMap::Aces => {
+                (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+            }
+        };
+        mapped.clamp(0., 1.)
+    }
+}
+
+/// Output pipeline applied when a Canvas is written
+///
+/// Colors are scaled by the exposure, tone mapped and optionally sRGB encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Output {
+    /// exposure in stops, every stop doubles the brightness
+    pub exposure: f64,
+    /// tone mapping operator
+    pub tone_map: ToneMap,
+    /// encode with the sRGB transfer function instead of writing linear values
+    pub srgb: bool,
+}
+
+impl Output {
+    pub fn set_exposure(&self, exposure: f64) -> Output {
+        Output { exposure, ..*self }
+    }
+
+    pub fn set_tone_map(&self, tone_map: ToneMap) -> Output {
+        Output { tone_map, ..*self }
+    }
+
+    pub fn set_srgb(&self, srgb: bool) -> Output {
+        Output { srgb, ..*self }
+    }
+
+    /// Maps a single linear channel where 1.0 is white to an 8 bit value
+    ///
+    /// Exposure and tone mapping run on the unquantized value, it is rounded only at the end.
+    pub fn map_channel(&self, channel: f64) -> u8 {
+        let linear = channel * self.exposure.exp2();
+        let mut value = self.tone_map.map(linear);
+        if self.srgb {
+            value = srgb_encode(value);
+        }
+        (value * 255.).round() as u8
+    }
+
+    /// Maps linear radiance to 8 bit red, green and blue
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::raytracer::render::Output;
+    /// let rgb = Output::default().map([1.2, 0.5, -0.1]);
+    /// assert_eq!(rgb, [255, 128, 0]);
+    /// ```
+    pub fn map(&self, radiance: [f32; 3]) -> [u8; 3] {
+        radiance.map(|c| self.map_channel(c as f64))
+    }
+}
+
+/// Applies the sRGB transfer function to a linear value in [0, 1]
+fn srgb_encode(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::utils;
+
+    #[test]
+    fn tone_map() {
+        // Clamp keeps values below white
+        assert_eq!(ToneMap::Clamp.map(0.5), 0.5);
+        assert_eq!(ToneMap::Clamp.map(4.), 1.);
+
+        // Reinhard maps 1 to a half and never reaches white
+        assert_eq!(ToneMap::Reinhard.map(1.), 0.5);
+        assert!(ToneMap::Reinhard.map(1000.) < 1.);
+
+        // ACES keeps black and saturates bright values
+        assert!(utils::float_eq(ToneMap::Aces.map(0.), 0.));
+        assert_eq!(ToneMap::Aces.map(100.), 1.);
+        assert!(ToneMap::Aces.map(0.2) < ToneMap::Aces.map(0.3));
+    }
+
+    #[test]
+    fn map() {
+        // The default output writes values unchanged
+        let output = Output::default();
+        assert_eq!(output.map([0., 100. / 255., 1.]), [0, 100, 255]);
+
+        // Every stop of exposure doubles the brightness
+        let output = Output::default().set_exposure(1.);
+        assert_eq!(output.map([0., 100. / 255., 200. / 255.]), [0, 200, 255]);
+
+        // sRGB brightens the mid tones
+        let output = Output::default().set_srgb(true);
+        assert_eq!(output.map([0., 55. / 255., 1.]), [0, 128, 255]);
+
+        // Light far below an 8 bit step still comes out when exposed
+        let output = Output::default().set_exposure(8.);
+        assert_eq!(output.map([0.001, 0.002, 0.]), [65, 131, 0]);
+    }
+}
@@ -0,0 +1,454 @@
+//! Photon mapping of caustics, light focused on surfaces by mirrors and glass
+use crate::render::World;
+use crate::units::sampling::{self, orthonormal_basis, Rng};
+use crate::units::tuple::{Point, Tuple, Vector};
+use crate::units::{Intersection, Ray};
+use crate::world::PointLight;
+use rayon::prelude::*;
+use std::collections::BinaryHeap;
+use std::f64::consts;
+use std::ops::Range;
+
+/// Most bounces a photon makes before it is dropped
+const MAX_BOUNCES: usize = 8;
+
+/// Photon mapping settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Caustics {
+    /// Number of photons shot from the light
+    pub photons: usize,
+    /// Number of nearest photons gathered for an estimate
+    pub nearest: usize,
+    /// Photons further than this from a point are not gathered
+    pub radius: f64,
+}
+
+impl Caustics {
+    /// Creates new Caustics
+    pub fn new(photons: usize, nearest: usize, radius: f64) -> Caustics {
+        Caustics {
+            photons,
+            nearest,
+            radius,
+        }
+    }
+}
+
+impl Default for Caustics {
+    fn default() -> Self {
+        Caustics {
+            photons: 100_000,
+            nearest: 50,
+            radius: 0.25,
+        }
+    }
+}
+
+/// Light that landed on a surface after bouncing off mirrors or passing through glass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Photon {
+    pub position: Point,
+    /// Normalized direction the photon travelled in
+    pub direction: Vector,
+    /// Carried light per channel, 1.0 spread over unit area is the light's intensity
+    pub power: [f64; 3],
+}
+
+/// Photons stored in a balanced kd-tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhotonMap {
+    settings: Caustics,
+    /// Each range of the tree keeps its median photon in the middle
+    photons: Vec<Photon>,
+    /// Axis the median photon of the same index splits its range along
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    /// Shoots photons from the world's light and keeps the ones landing after a specular bounce
+    ///
+    /// Photons are aimed at the shapes that reflect or refract, so only caustic paths get
+    /// traced. Shapes are placed where they are at time 0.
+    pub fn build(world: &World, settings: Caustics) -> PhotonMap {
+        let photons = match world.light {
+            Some(light) => {
+                let cones = emission_cones(world, light.position);
+                (0..settings.photons)
+                    .into_par_iter()
+                    .flat_map_iter(|i| {
+                        let mut rng = Rng::new(i as u64);
+                        trace_photon(world, light, &cones, settings.photons, &mut rng)
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        PhotonMap::from_photons(settings, photons)
+    }
+
+    /// Creates a map of given photons
+    pub fn from_photons(settings: Caustics, mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        balance(&mut photons, &mut axes);
+        PhotonMap {
+            settings,
+            photons,
+            axes,
+        }
+    }
+
+    /// Returns number of stored photons
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Returns the nearest photons within the radius with their squared distance, nearest first
+    pub fn nearest(&self, point: Point) -> Vec<(f64, &Photon)> {
+        let mut heap = BinaryHeap::new();
+        let mut max_distance = self.settings.radius.powi(2);
+        self.search(0..self.photons.len(), point, &mut max_distance, &mut heap);
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|(distance, i)| (f64::from_bits(distance), &self.photons[i]))
+            .collect()
+    }
+
+    /// Walks the tree keeping the nearest photons in a max heap of squared distances
+    fn search(
+        &self,
+        range: Range<usize>,
+        point: Point,
+        max_distance: &mut f64,
+        heap: &mut BinaryHeap<(u64, usize)>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        let mid = range.start + range.len() / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid] as usize;
+        let delta = coordinate(point, axis) - coordinate(photon.position, axis);
+        let (near, far) = if delta < 0. {
+            (range.start..mid, mid + 1..range.end)
+        } else {
+            (mid + 1..range.end, range.start..mid)
+        };
+        self.search(near, point, max_distance, heap);
+        let distance = (point - photon.position).magnitude().powi(2);
+        if distance < *max_distance {
+            // Bits of positive floats sort like the floats do
+            heap.push((distance.to_bits(), mid));
+            if heap.len() > self.settings.nearest {
+                heap.pop();
+            }
+            if heap.len() == self.settings.nearest {
+                *max_distance = f64::from_bits(heap.peek().unwrap().0);
+            }
+        }
+        if delta * delta < *max_distance {
+            self.search(far, point, max_distance, heap);
+        }
+    }
+
+    /// Returns caustic light reaching a point per channel, 1.0 is the point light's intensity
+    ///
+    /// Only photons arriving at the side of the surface the normal points to are counted.
+    pub fn irradiance(&self, point: Point, normalv: Vector) -> [f64; 3] {
+        let found = self.nearest(point);
+        let area = if found.len() < self.settings.nearest {
+            consts::PI * self.settings.radius.powi(2)
+        } else {
+            consts::PI * found.last().map_or(0., |(distance, _)| *distance)
+        };
+        let mut irradiance = [0.; 3];
+        for (_, photon) in found.iter().filter(|(_, p)| p.direction.dot(normalv) < 0.) {
+            for (e, p) in irradiance.iter_mut().zip(photon.power) {
+                *e += p / area;
+            }
+        }
+        irradiance
+    }
+}
+
+/// Cone of directions photons are shot in
+#[derive(Debug, Clone, Copy)]
+struct Cone {
+    axis: Vector,
+    cos_max: f64,
+}
+
+impl Cone {
+    fn solid_angle(&self) -> f64 {
+        2. * consts::PI * (1. - self.cos_max)
+    }
+
+    fn contains(&self, direction: Vector) -> bool {
+        direction.dot(self.axis) >= self.cos_max
+    }
+}
+
+/// Returns cones around the shapes that reflect or refract, seen from the light
+///
+/// Unbounded shapes or shapes around the light need the whole sphere of directions.
+fn emission_cones(world: &World, light: Point) -> Vec<Cone> {
+    let everywhere = vec![Cone {
+        axis: Vector::new(0, 0, 1),
+        cos_max: -1.,
+    }];
+    let mut cones = Vec::new();
+    for o in world.objects.iter().filter(|o| o.interior.is_none()) {
+        if o.material.reflect <= 0. && o.material.transparent <= 0. {
+            continue;
+        }
+        let (center, radius) = match o.bounding_sphere(0.) {
+            Some(bounds) => bounds,
+            None => return everywhere,
+        };
+        let to_center = center - light;
+        let distance = to_center.magnitude();
+        if distance <= radius {
+            return everywhere;
+        }
+        cones.push(Cone {
+            axis: to_center.normalize(),
+            cos_max: (1. - (radius / distance).powi(2)).sqrt(),
+        });
+    }
+    cones
+}
+
+/// Follows a photon through reflections and refractions, returning where it landed
+///
+/// # Arguments
+/// * `world` - world to trace the photon in
+/// * `light` - light the photon leaves
+/// * `cones` - directions photons are shot in
+/// * `emitted` - number of photons shot in total
+/// * `rng` - random numbers of the photon
+fn trace_photon(
+    world: &World,
+    light: PointLight,
+    cones: &[Cone],
+    emitted: usize,
+    rng: &mut Rng,
+) -> Vec<Photon> {
+    let solid_angle: f64 = cones.iter().map(Cone::solid_angle).sum();
+    let mut pick = rng.next_f64() * solid_angle;
+    let cone = match cones.iter().find(|c| {
+        pick -= c.solid_angle();
+        pick < 0.
+    }) {
+        Some(cone) => cone,
+        None => return Vec::new(),
+    };
+    let (x, y, z) = sampling::uniform_cone(rng.next_f64(), rng.next_f64(), cone.cos_max);
+    let (tangent, bitangent) = orthonormal_basis(cone.axis);
+    let direction = tangent * x + bitangent * y + cone.axis * z;
+    // Overlapping cones shoot more photons in the directions they share
+    let overlap = cones
+        .iter()
+        .filter(|c| c.contains(direction))
+        .count()
+        .max(1);
+    let intensity = light.intensity;
+    let intensity = [intensity.r, intensity.g, intensity.b].map(|c| c as f64 / 255.);
+
+    let mut ray = Ray::new(light.position, direction).set_secondary(true);
+    let mut power: Option<[f64; 3]> = None;
+    let mut specular = false;
+    let mut stored = Vec::new();
+    for _ in 0..MAX_BOUNCES {
+        let intersections = world.intersect(ray);
+        let hit = match Intersection::hit(intersections.clone()) {
+            Some(hit) => hit,
+            None => break,
+        };
+        if hit.object.interior.is_some() {
+            ray = ray.continue_past(hit.t);
+            continue;
+        }
+        let mut c = hit.computations(ray, &intersections);
+        // Without falloff the light covers the area around the first hit with its intensity
+        let mut carried = power.unwrap_or_else(|| {
+            intensity.map(|i| i * c.t.powi(2) * solid_angle / (emitted * overlap) as f64)
+        });
+        if ray.channel.is_none() && c.is_dispersive() {
+            let channel = ((rng.next_f64() * 3.) as usize).min(2);
+            for (i, p) in carried.iter_mut().enumerate() {
+                *p *= if i == channel { 3. } else { 0. };
+            }
+            ray = ray.set_channel(Some(channel));
+            c = c.set_channel(channel);
+        }
+        if let Some(medium) = c.medium {
+            let transmittance = medium.material.transmittance(c.t);
+            for (p, t) in carried.iter_mut().zip(transmittance) {
+                *p *= t;
+            }
+        }
+        let object = c.object.at_time(c.time);
+        let material = &object.material;
+        if specular && material.diffuse > 0. {
+            stored.push(Photon {
+                position: c.over_point,
+                direction: ray.direction.normalize(),
+                power: carried,
+            });
+        }
+
+        // Same split between the mirror and the glass as in the Whitted tracer
+        let mut reflectance = object.reflectance(c.over_point, c.eyev, c.normalv);
+        let mut transparent = material.transparent;
+        if material.reflect > 0. && transparent > 0. {
+            let schlick = c.schlick();
+            reflectance = reflectance.map(|r| r * schlick);
+            transparent *= 1. - schlick;
+        }
+        let reflect = reflectance.iter().sum::<f64>() / 3.;
+        let lobes = (reflect + transparent).max(1.);
+        let pick = rng.next_f64() * lobes;
+        let (origin, direction) = if pick < reflect {
+            for (p, r) in carried.iter_mut().zip(reflectance) {
+                *p *= r * lobes / reflect;
+            }
+            (c.over_point, c.reflectv)
+        } else if pick < reflect + transparent {
+            carried.iter_mut().for_each(|p| *p *= lobes);
+            match c.refractv() {
+                Some(direction) => (c.under_point, direction),
+                None => break,
+            }
+        } else {
+            break;
+        };
+        power = Some(carried);
+        specular = true;
+        ray = Ray::new(origin, direction)
+            .set_time(c.time)
+            .set_channel(ray.channel)
+            .set_secondary(true);
+    }
+    stored
+}
+
+/// Orders photons into a balanced kd-tree, splitting every range along its widest axis
+fn balance(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let axis = (0..3)
+        .max_by(|&a, &b| {
+            let extent = |axis| {
+                let values = photons.iter().map(|p| coordinate(p.position, axis));
+                values.clone().fold(f64::MIN, f64::max) - values.fold(f64::MAX, f64::min)
+            };
+            extent(a).total_cmp(&extent(b))
+        })
+        .unwrap();
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        coordinate(a.position, axis).total_cmp(&coordinate(b.position, axis))
+    });
+    axes[mid] = axis as u8;
+    let (left, rest) = photons.split_at_mut(mid);
+    let (left_axes, rest_axes) = axes.split_at_mut(mid);
+    balance(left, left_axes);
+    balance(&mut rest[1..], &mut rest_axes[1..]);
+}
+
+fn coordinate(point: Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::color::WHITE;
+    use crate::units::objects::{ObjectType, Shape};
+    use crate::units::Transformable;
+    use crate::world::Material;
+
+    fn photon(x: f64, y: f64, z: f64) -> Photon {
+        Photon {
+            position: Point::new(x, y, z),
+            direction: Vector::new(0, -1, 0),
+            power: [1.; 3],
+        }
+    }
+
+    #[test]
+    fn nearest() {
+        // The tree finds the same photons as checking all of them
+        let mut rng = Rng::new(5);
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| photon(rng.next_f64(), rng.next_f64(), rng.next_f64()))
+            .collect();
+        let map = PhotonMap::from_photons(Caustics::new(0, 10, 0.3), photons.clone());
+        assert_eq!(map.len(), 500);
+        let point = Point::new(0.5, 0.5, 0.5);
+        let mut expected: Vec<f64> = photons
+            .iter()
+            .map(|p| (point - p.position).magnitude().powi(2))
+            .collect();
+        expected.sort_by(f64::total_cmp);
+        let found: Vec<f64> = map.nearest(point).iter().map(|(d, _)| *d).collect();
+        assert_eq!(found, expected[..10]);
+
+        // Nothing is found beyond the radius
+        assert!(map.nearest(Point::new(5, 5, 5)).is_empty());
+    }
+
+    #[test]
+    fn irradiance() {
+        // A grid of photons with unit power per unit area gives unit irradiance
+        let photons = (0..100)
+            .flat_map(|x| (0..100).map(move |z| photon(x as f64 * 0.1, 0., z as f64 * 0.1)))
+            .map(|p| Photon {
+                power: [0.01; 3],
+                ..p
+            })
+            .collect();
+        let map = PhotonMap::from_photons(Caustics::new(0, 200, 1.), photons);
+        let up = Vector::new(0, 1, 0);
+        let e = map.irradiance(Point::new(5, 0, 5), up);
+        assert!((e[0] - 1.).abs() < 0.1);
+
+        // Photons arriving from behind the surface don't count
+        let e = map.irradiance(Point::new(5, 0, 5), Vector::new(0, -1, 0));
+        assert_eq!(e, [0.; 3]);
+    }
+
+    #[test]
+    fn build() {
+        let floor = Shape::new(ObjectType::Plane).set_material(Material::new(WHITE));
+        let light = PointLight::new(Point::new(0, 10, 0), WHITE);
+        let settings = Caustics::new(2000, 50, 0.25);
+
+        // Without glass or mirrors there are no caustics
+        let world = World::new()
+            .set_light(Some(light))
+            .set_objects(vec![floor.clone()]);
+        assert!(PhotonMap::build(&world, settings).is_empty());
+
+        // A glass ball focuses the light into a bright spot under it
+        let glass = Shape::glass_sphere().translate(0, 2, 0);
+        let world = world.set_objects(vec![floor, glass]);
+        let map = PhotonMap::build(&world, settings);
+        assert!(!map.is_empty());
+        let up = Vector::new(0, 1, 0);
+        let focus = map.irradiance(Point::new(0, 0, 0), up);
+        assert!(focus[0] > 1.);
+        assert_eq!(map.irradiance(Point::new(4, 0, 4), up), [0.; 3]);
+
+        // Photons have their own random numbers, so building again gives the same map
+        assert_eq!(map, PhotonMap::build(&world, settings));
+    }
+}
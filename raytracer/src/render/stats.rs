@@ -0,0 +1,204 @@
+//! Render statistics, counted per thread and merged when the render is done
+use std::cell::Cell;
+use std::fmt;
+use std::time::Duration;
+
+/// Counts of the work done by a render
+///
+/// Bounces of path tracers count as diffuse, reflection or refraction rays by the lobe
+/// they sample, and as scatter rays inside media. Ambient occlusion and visibility tests
+/// count as shadow rays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub camera_rays: u64,
+    pub shadow_rays: u64,
+    pub reflection_rays: u64,
+    pub refraction_rays: u64,
+    /// Bounces off diffuse and rough surfaces
+    pub diffuse_rays: u64,
+    /// Bounces off particles of a medium
+    pub scatter_rays: u64,
+    /// Ray and shape intersection tests
+    pub primitive_tests: u64,
+    /// Bounding volume hierarchy nodes visited, stays 0 as worlds test every shape
+    pub bvh_nodes: u64,
+    /// Deepest bounce of any path
+    pub max_depth: u64,
+}
+
+impl Stats {
+    /// Returns number of rays of every kind
+    pub fn rays(&self) -> u64 {
+        self.camera_rays
+            + self.shadow_rays
+            + self.reflection_rays
+            + self.refraction_rays
+            + self.diffuse_rays
+            + self.scatter_rays
+    }
+
+    /// Returns counts of both, adding them up and keeping the deeper depth
+    pub fn merge(&self, other: &Stats) -> Stats {
+        Stats {
+            camera_rays: self.camera_rays + other.camera_rays,
+            shadow_rays: self.shadow_rays + other.shadow_rays,
+            reflection_rays: self.reflection_rays + other.reflection_rays,
+            refraction_rays: self.refraction_rays + other.refraction_rays,
+            diffuse_rays: self.diffuse_rays + other.diffuse_rays,
+            scatter_rays: self.scatter_rays + other.scatter_rays,
+            primitive_tests: self.primitive_tests + other.primitive_tests,
+            bvh_nodes: self.bvh_nodes + other.bvh_nodes,
+            max_depth: self.max_depth.max(other.max_depth),
+        }
+    }
+
+    /// Returns the counts as a JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"camera_rays\": {}, \"shadow_rays\": {}, \"reflection_rays\": {}, \
+             \"refraction_rays\": {}, \"diffuse_rays\": {}, \"scatter_rays\": {}, \
+             \"primitive_tests\": {}, \"bvh_nodes\": {}, \"max_depth\": {}}}",
+            self.camera_rays,
+            self.shadow_rays,
+            self.reflection_rays,
+            self.refraction_rays,
+            self.diffuse_rays,
+            self.scatter_rays,
+            self.primitive_tests,
+            self.bvh_nodes,
+            self.max_depth
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Camera rays:      {:>14}", self.camera_rays)?;
+        writeln!(f, "Shadow rays:      {:>14}", self.shadow_rays)?;
+        writeln!(f, "Reflection rays:  {:>14}", self.reflection_rays)?;
+        writeln!(f, "Refraction rays:  {:>14}", self.refraction_rays)?;
+        writeln!(f, "Diffuse rays:     {:>14}", self.diffuse_rays)?;
+        writeln!(f, "Scatter rays:     {:>14}", self.scatter_rays)?;
+        writeln!(f, "Primitive tests:  {:>14}", self.primitive_tests)?;
+        writeln!(f, "BVH nodes:        {:>14}", self.bvh_nodes)?;
+        write!(f, "Max depth:        {:>14}", self.max_depth)
+    }
+}
+
+/// Time spent in each phase of rendering a scene file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    /// Reading the scene file
+    pub parse: Duration,
+    /// Building the world and camera, including photon maps
+    pub build: Duration,
+    /// Tracing the images and filtering them
+    pub render: Duration,
+    /// Writing images
+    pub write: Duration,
+}
+
+impl Timings {
+    /// Returns the times in seconds as a JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"parse\": {}, \"build\": {}, \"render\": {}, \"write\": {}}}",
+            self.parse.as_secs_f64(),
+            self.build.as_secs_f64(),
+            self.render.as_secs_f64(),
+            self.write.as_secs_f64()
+        )
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Parse:            {:>14.3?}", self.parse)?;
+        writeln!(f, "Build:            {:>14.3?}", self.build)?;
+        writeln!(f, "Render:           {:>14.3?}", self.render)?;
+        write!(f, "Write:            {:>14.3?}", self.write)
+    }
+}
+
+thread_local! {
+    static STATS: Cell<Stats> = Cell::new(Stats::default());
+    static DEPTH: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Updates the counts of the current thread
+pub(crate) fn record(update: impl FnOnce(&mut Stats)) {
+    STATS.with(|cell| {
+        let mut stats = cell.get();
+        update(&mut stats);
+        cell.set(stats);
+    });
+}
+
+/// Records that a path reached a depth
+pub(crate) fn reach_depth(depth: usize) {
+    record(|s| s.max_depth = s.max_depth.max(depth as u64));
+}
+
+/// Runs a recursive trace one level deeper than the current one
+pub(crate) fn nested<T>(trace: impl FnOnce() -> T) -> T {
+    let depth = DEPTH.with(|d| {
+        d.set(d.get() + 1);
+        d.get()
+    });
+    reach_depth(depth as usize);
+    let result = trace();
+    DEPTH.with(|d| d.set(d.get() - 1));
+    result
+}
+
+/// Returns counts of the current thread and resets them
+pub fn take() -> Stats {
+    STATS.with(|cell| cell.replace(Stats::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        take();
+        super::record(|s| s.camera_rays += 2);
+        nested(|| nested(|| super::record(|s| s.primitive_tests += 3)));
+        let stats = take();
+        assert_eq!(stats.camera_rays, 2);
+        assert_eq!(stats.primitive_tests, 3);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(take(), Stats::default());
+
+        // Other threads count for themselves
+        std::thread::spawn(|| super::record(|s| s.shadow_rays += 1))
+            .join()
+            .unwrap();
+        assert_eq!(take().shadow_rays, 0);
+    }
+
+    #[test]
+    fn merge() {
+        let a = Stats {
+            camera_rays: 1,
+            reflection_rays: 2,
+            max_depth: 4,
+            ..Stats::default()
+        };
+        let b = Stats {
+            camera_rays: 3,
+            shadow_rays: 5,
+            scatter_rays: 3,
+            max_depth: 2,
+            ..Stats::default()
+        };
+        let merged = a.merge(&b);
+        assert_eq!(merged.camera_rays, 4);
+        assert_eq!(merged.max_depth, 4);
+        assert_eq!(merged.rays(), 14);
+        assert!(merged.to_json().contains("\"shadow_rays\": 5"));
+        assert!(merged.to_json().contains("\"scatter_rays\": 3"));
+        assert!(Timings::default().to_json().starts_with("{\"parse\": 0,"));
+    }
+}
@@ -59,21 +59,9 @@ impl QuantColor {
     /// ```
     pub fn clamp(&self) -> QuantColor {
         let mut return_color = *self;
-        if return_color.r > 255 {
-            return_color.r = 255;
-        } else if return_color.r < 0 {
-            return_color.r = 0;
-        }
-        if return_color.g > 255 {
-            return_color.g = 255;
-        } else if return_color.g < 0 {
-            return_color.g = 0;
-        }
-        if return_color.b > 255 {
-            return_color.b = 255;
-        } else if return_color.b < 0 {
-            return_color.b = 0;
-        }
+        return_color.r = return_color.r.clamp(0, 255);
+        return_color.g = return_color.g.clamp(0, 255);
+        return_color.b = return_color.b.clamp(0, 255);
         return_color
     }
 }
@@ -153,6 +141,21 @@ impl From<[i64; 3]> for QuantColor {
     }
 }
 
+/// Returns color as floats where 1.0 is white
+pub(crate) fn rgb(color: QuantColor) -> [f64; 3] {
+    [
+        color.r as f64 / 255.,
+        color.g as f64 / 255.,
+        color.b as f64 / 255.,
+    ]
+}
+
+/// Returns color of floats where 1.0 is white, rounded to the nearest QuantColor
+pub(crate) fn quantize(rgb: [f64; 3]) -> QuantColor {
+    let [r, g, b] = rgb.map(|c| (c * 255.).round() as i64);
+    QuantColor::new(r, g, b)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -34,6 +34,14 @@ pub struct Computations<'a> {
     pub inside: bool,
     pub n1: f64,
     pub n2: f64,
+    /// Object the ray travelled through to reach the point, None in open space
+    pub medium: Option<&'a Shape>,
+    /// Object a ray refracted at the point travels through, None in open space
+    pub next_medium: Option<&'a Shape>,
+    /// Color channel of the ray, None for all three
+    pub channel: Option<usize>,
+    /// Time of the ray that produced the intersection
+    pub time: f64,
 }
 
 impl<'a> Intersection<'a> {
@@ -58,13 +66,16 @@ impl<'a> Intersection<'a> {
         }
     }
     /// Returns base computations, that is computations with n1 and n2 set to 1.
-    pub fn base_computations(&self, ray: Ray) -> Computations {
+    pub fn base_computations(&self, ray: Ray) -> Computations<'_> {
         let position = ray.position(self.t);
-        let mut normalv = self.object.normal(position);
+        let mut geometric = self.object.normal_at(position, ray.time);
         let eyev = -ray.direction;
-        let inside = normalv.dot(eyev) < 0.;
+        let inside = geometric.dot(eyev) < 0.;
 
+        // Bumps only change shading, offset points follow the real surface
+        let mut normalv = self.object.shading_normal_at(position, ray.time);
         if inside {
+            geometric = -geometric;
             normalv = -normalv;
         }
 
@@ -75,14 +86,18 @@ impl<'a> Intersection<'a> {
             eyev: -ray.direction,
             normalv,
             inside,
-            over_point: position + normalv * utils::EPSILON,
-            under_point: Point::from(position - Point::from(normalv * utils::EPSILON)),
+            over_point: position + geometric * utils::EPSILON,
+            under_point: Point::from(position - Point::from(geometric * utils::EPSILON)),
             reflectv: ray.direction.reflect(normalv),
             n1: 1.,
             n2: 1.,
+            medium: None,
+            next_medium: None,
+            channel: ray.channel,
+            time: ray.time,
         }
     }
-    /// Returns computations with the computed n1 and n2.
+    /// Returns computations with the computed n1, n2 and media.
     ///
     /// # Arguments
     /// * `r` - A ray at which the Computations are computed.
@@ -90,31 +105,32 @@ impl<'a> Intersection<'a> {
     ///
     /// # Returns
     /// Populated Computations
-    pub fn computations(&self, r: Ray, intersections: &Vec<Intersection>) -> Computations {
+    pub fn computations(&self, r: Ray, intersections: &Vec<Intersection<'a>>) -> Computations<'_> {
         let mut comps = self.base_computations(r);
-        let (n1, n2) = self.compute_refraction_indexes(intersections);
-        comps.n1 = n1;
-        comps.n2 = n2;
-        comps
+        let (medium, next_medium) = self.compute_media(intersections);
+        comps.medium = medium;
+        comps.next_medium = next_medium;
+        comps.set_indexes()
     }
 
-    /// Computes refraction indexes for the given computation at the intersections
+    /// Computes objects containing the ray on both sides of the intersection
     ///
     /// # Arguments
     /// * `intersections` - Pointer to a vector with intersections
     ///
     /// # Returns
-    /// (n1, n2)
-    fn compute_refraction_indexes(&self, intersections: &Vec<Intersection>) -> (f64, f64) {
+    /// (object containing the ray before the intersection, object containing it after)
+    fn compute_media(
+        &self,
+        intersections: &Vec<Intersection<'a>>,
+    ) -> (Option<&'a Shape>, Option<&'a Shape>) {
         let mut containers: Vec<&Shape> = Vec::new();
-        let mut n1 = 1.;
-        let mut n2 = 1.;
+        let mut medium = None;
+        let mut next_medium = None;
 
         for intersection in intersections {
             if self.t == intersection.t {
-                if !containers.is_empty() {
-                    n1 = containers.last().unwrap().material.refractive_index;
-                }
+                medium = containers.last().copied();
             }
 
             if containers.contains(&intersection.object) {
@@ -128,16 +144,53 @@ impl<'a> Intersection<'a> {
             }
 
             if self.t == intersection.t {
-                if !containers.is_empty() {
-                    n2 = containers.last().unwrap().material.refractive_index;
-                }
+                next_medium = containers.last().copied();
                 break;
             }
         }
-        (n1, n2)
+        (medium, next_medium)
     }
 }
 impl Computations<'_> {
+    /// Returns the computations with n1 and n2 taken from the media for the ray's channel
+    fn set_indexes(self) -> Self {
+        let index = |medium: Option<&Shape>| medium.map_or(1., |m| m.material.index(self.channel));
+        Computations {
+            n1: index(self.medium),
+            n2: index(self.next_medium),
+            ..self
+        }
+    }
+
+    /// Returns the computations for a ray carrying a single color channel
+    pub fn set_channel(&self, channel: usize) -> Self {
+        Computations {
+            channel: Some(channel),
+            ..self.clone()
+        }
+        .set_indexes()
+    }
+
+    /// Whether the refractive indexes on either side depend on the color channel
+    pub fn is_dispersive(&self) -> bool {
+        [self.medium, self.next_medium]
+            .iter()
+            .flatten()
+            .any(|m| m.material.dispersion.is_some())
+    }
+
+    /// Returns direction of the refracted ray, None on total internal reflection
+    pub fn refractv(&self) -> Option<Vector> {
+        let n_ratio = self.n1 / self.n2;
+        let cos_i = self.eyev.dot(self.normalv);
+        let sin2_t = n_ratio.powi(2) * (1. - cos_i.powi(2));
+        if sin2_t > 1. {
+            return None;
+        }
+        let cos_t = (1. - sin2_t).sqrt();
+        Some(self.normalv * (n_ratio * cos_i - cos_t) - self.eyev * n_ratio)
+    }
+
     /// Computes how much schlick refraction is applied
     pub fn schlick(&self) -> f64 {
         let mut cos = self.eyev.dot(self.normalv);
@@ -157,7 +210,7 @@ impl Computations<'_> {
 
         let r0 = ((self.n1 - self.n2) / (self.n1 + self.n2)).powi(2);
 
-        return r0 + (1.0 - r0) * (1.0 - cos).powi(5);
+        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
     }
 }
 
@@ -183,13 +236,7 @@ impl<'a> PartialEq for Intersection<'a> {
 
 impl<'a> PartialOrd for Intersection<'a> {
     fn partial_cmp(&self, other: &Intersection) -> Option<Ordering> {
-        if (self.t - other.t) > utils::EPSILON {
-            Some(Ordering::Greater)
-        } else if (self.t - other.t) < utils::EPSILON {
-            Some(Ordering::Less)
-        } else {
-            Some(Ordering::Equal)
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -323,6 +370,13 @@ mod tests {
         assert_eq!(1.5, comps.n1);
         assert_eq!(1., comps.n2);
 
+        // The medium is the innermost object the ray travelled through
+        assert!(ints[0].computations(r, &ints).medium.is_none());
+        let medium = ints[2].computations(r, &ints).medium.unwrap();
+        assert!(std::ptr::eq(medium, &b));
+        let medium = ints[5].computations(r, &ints).medium.unwrap();
+        assert!(std::ptr::eq(medium, &a));
+
         // The under point is offset below the surface
         let r = Ray::new(Point::new(0, 0, -5), Vector::new(0, 0, 1));
         let shape = Shape::glass_sphere().translate(0, 0, 1);
@@ -99,7 +99,6 @@ impl Matrix {
     ///     ]);
     /// let b = a.submatrix(2, 2);
     /// ```
-
     pub fn submatrix(&self, row: usize, col: usize) -> Matrix {
         let mut values = [[0.0; 4]; 4];
         let width = self.width - 1;
@@ -131,7 +130,6 @@ impl Matrix {
     /// let a = Matrix::from([[1.0, 5.0], [-3.0, 2.0]]);
     /// let b = a.determinant();
     /// ```
-
     pub fn determinant(&self) -> f64 {
         // match self.width {
         //     2 => self[0][0] * self[1][1] - self[0][1] * self[1][0],
@@ -165,7 +163,6 @@ impl Matrix {
     ///      ]);
     /// let b = a.cofactor(2, 2);
     /// ```
-
     pub fn cofactor(&self, row: usize, col: usize) -> f64 {
         // let sign = if row + col % 2 == 1 { -1.0 } else { 1.0 };
         // // println!("{:?}", self.minor(row, col));
@@ -198,7 +195,6 @@ impl Matrix {
     ///     ]);
     /// let b = a.minor(2, 2);
     /// ```
-
     pub fn minor(&self, row: usize, col: usize) -> f64 {
         self.submatrix(row, col).determinant()
     }
@@ -218,7 +214,6 @@ impl Matrix {
     /// let b = a.invert().unwrap();
     ///
     /// ```
-
     pub fn invert(&self) -> Result<Matrix, &'static str> {
         if self.determinant() == 0.0 {
             return Err("Matrix is impossible to invert");
@@ -289,7 +284,7 @@ impl Matrix {
     pub fn rotate_x<T: Into<f64> + Copy>(r: T) -> Matrix {
         let mut return_matrix = IDENTITY_MATRIX;
         return_matrix[1][1] = r.into().cos();
-        return_matrix[1][2] = -1.0 * r.into().sin();
+        return_matrix[1][2] = -r.into().sin();
         return_matrix[2][1] = r.into().sin();
         return_matrix[2][2] = r.into().cos();
         return_matrix
@@ -310,7 +305,7 @@ impl Matrix {
         let mut return_matrix = IDENTITY_MATRIX;
         return_matrix[0][0] = r.into().cos();
         return_matrix[0][2] = r.into().sin();
-        return_matrix[2][0] = -1.0 * r.into().sin();
+        return_matrix[2][0] = -r.into().sin();
         return_matrix[2][2] = r.into().cos();
         return_matrix
     }
@@ -329,7 +324,7 @@ impl Matrix {
     pub fn rotate_z<T: Into<f64> + Copy>(r: T) -> Matrix {
         let mut return_matrix = IDENTITY_MATRIX;
         return_matrix[0][0] = r.into().cos();
-        return_matrix[0][1] = -1.0 * r.into().sin();
+        return_matrix[0][1] = -r.into().sin();
         return_matrix[1][0] = r.into().sin();
         return_matrix[1][1] = r.into().cos();
         return_matrix
@@ -345,7 +340,7 @@ impl Matrix {
     /// * `y_to_z` - y to z skew amount
     /// * `z_to_x` - z to x skew amount
     /// * `z_to_y` - z to y skew amount
-
+    ///
     /// # Examples
     /// ```
     ///
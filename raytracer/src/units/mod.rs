@@ -1,8 +1,10 @@
 pub mod color;
 pub mod intersect;
 pub mod matrix;
+pub mod motion;
 pub mod objects;
 pub mod ray;
+pub mod sampling;
 pub mod tuple;
 pub mod utils;
 
@@ -0,0 +1,342 @@
+//! Transformations that change over time
+use crate::units::tuple::{Tuple, Vector};
+use crate::units::{utils, Matrix, IDENTITY_MATRIX};
+use std::sync::Arc;
+
+/// Quaternion representing a rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    /// Creates new Quaternion
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    /// Creates a Quaternion from the rotation part of a matrix
+    ///
+    /// The upper 3x3 part of the matrix must be a pure rotation.
+    pub fn from_matrix(m: Matrix) -> Quaternion {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0. {
+            let s = (trace + 1.).sqrt() * 2.;
+            Quaternion::new(
+                s / 4.,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1. + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.;
+            Quaternion::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = (1. + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.;
+            Quaternion::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = (1. + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.;
+            Quaternion::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.,
+            )
+        };
+        q.normalize()
+    }
+
+    /// Returns rotation matrix of the Quaternion
+    pub fn to_matrix(&self) -> Matrix {
+        let Quaternion { w, x, y, z } = *self;
+        Matrix::from([
+            [
+                1. - 2. * (y * y + z * z),
+                2. * (x * y - w * z),
+                2. * (x * z + w * y),
+                0.,
+            ],
+            [
+                2. * (x * y + w * z),
+                1. - 2. * (x * x + z * z),
+                2. * (y * z - w * x),
+                0.,
+            ],
+            [
+                2. * (x * z - w * y),
+                2. * (y * z + w * x),
+                1. - 2. * (x * x + y * y),
+                0.,
+            ],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Returns dot product of two Quaternions
+    pub fn dot(&self, other: Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Returns Quaternion with a length of 1
+    pub fn normalize(&self) -> Quaternion {
+        let length = self.dot(*self).sqrt();
+        Quaternion::new(
+            self.w / length,
+            self.x / length,
+            self.y / length,
+            self.z / length,
+        )
+    }
+
+    /// Spherically interpolates between two rotations along the shortest arc
+    ///
+    /// # Arguments
+    /// * `other` - rotation at t = 1
+    /// * `t` - interpolation amount between 0 and 1
+    pub fn slerp(&self, other: Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut other = other;
+        if cos < 0. {
+            cos = -cos;
+            other = Quaternion::new(-other.w, -other.x, -other.y, -other.z);
+        }
+        let (a, b) = if cos > 1. - utils::EPSILON {
+            (1. - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1. - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quaternion::new(
+            a * self.w + b * other.w,
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+        )
+        .normalize()
+    }
+}
+
+/// Transformation that moves through keyframed matrices over time
+///
+/// Keyframe times are in the units of the camera shutter, so a ray's time selects
+/// the surrounding keyframes and is normalized by the interval between them.
+/// Every matrix is decomposed into translation, rotation and scale,
+/// so rotations are interpolated along an arc instead of shearing the object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionTransform {
+    /// Transformation at the first keyframe
+    pub start: Matrix,
+    /// Transformation at the last keyframe
+    pub end: Matrix,
+    keyframes: Arc<[Keyframe]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Keyframe {
+    time: f64,
+    matrix: Matrix,
+    translation: Vector,
+    rotation: Quaternion,
+    scale: Matrix,
+}
+
+impl MotionTransform {
+    /// Creates new MotionTransform moving from `start` at `shutter_open` to `end` at `shutter_close`
+    pub fn new(
+        start: Matrix,
+        end: Matrix,
+        shutter_open: f64,
+        shutter_close: f64,
+    ) -> MotionTransform {
+        MotionTransform::keyframed(&[(shutter_open, start), (shutter_close, end)])
+    }
+
+    /// Creates new MotionTransform passing through the given (time, matrix) keyframes
+    ///
+    /// Keyframes are sorted by time, panics if there are none.
+    pub fn keyframed(keyframes: &[(f64, Matrix)]) -> MotionTransform {
+        let mut keyframes: Vec<Keyframe> = keyframes
+            .iter()
+            .map(|&(time, matrix)| {
+                let (translation, rotation, scale) = decompose(matrix);
+                Keyframe {
+                    time,
+                    matrix,
+                    translation,
+                    rotation,
+                    scale,
+                }
+            })
+            .collect();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        MotionTransform {
+            start: keyframes.first().expect("Motion without keyframes").matrix,
+            end: keyframes[keyframes.len() - 1].matrix,
+            keyframes: keyframes.into(),
+        }
+    }
+
+    /// Returns the transformation at given time
+    ///
+    /// Before the first and after the last keyframe the transformation holds still.
+    pub fn interpolate(&self, time: f64) -> Matrix {
+        if time <= self.keyframes[0].time {
+            return self.start;
+        }
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == self.keyframes.len() {
+            return self.end;
+        }
+        let (a, b) = (self.keyframes[next - 1], self.keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+
+        let translation = a.translation * (1. - t) + b.translation * t;
+        let rotation = a.rotation.slerp(b.rotation, t);
+        let mut scale = IDENTITY_MATRIX;
+        for row in 0..3 {
+            for col in 0..3 {
+                scale[row][col] = a.scale[row][col] * (1. - t) + b.scale[row][col] * t;
+            }
+        }
+        Matrix::translate(translation.x, translation.y, translation.z)
+            * rotation.to_matrix()
+            * scale
+    }
+}
+
+/// Splits a matrix into translation, rotation and scale using polar decomposition
+fn decompose(m: Matrix) -> (Vector, Quaternion, Matrix) {
+    let translation = Vector::new(m[0][3], m[1][3], m[2][3]);
+
+    let mut linear = m;
+    for i in 0..3 {
+        linear[i][3] = 0.;
+        linear[3][i] = 0.;
+    }
+    linear[3][3] = 1.;
+
+    // Average the matrix with its inverse transpose until it converges to a rotation
+    let mut rotation = linear;
+    for _ in 0..100 {
+        let inverse_transpose = match rotation.invert() {
+            Ok(inverted) => inverted.transpose(),
+            Err(_) => break,
+        };
+        let mut next = IDENTITY_MATRIX;
+        let mut norm: f64 = 0.;
+        for row in 0..3 {
+            for col in 0..3 {
+                next[row][col] = 0.5 * (rotation[row][col] + inverse_transpose[row][col]);
+                norm = norm.max((next[row][col] - rotation[row][col]).abs());
+            }
+        }
+        rotation = next;
+        if norm < utils::EPSILON / 10. {
+            break;
+        }
+    }
+
+    let scale = match rotation.invert() {
+        Ok(inverted) => inverted * linear,
+        Err(_) => IDENTITY_MATRIX,
+    };
+    (translation, Quaternion::from_matrix(rotation), scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::tuple::Point;
+    use std::f64::consts;
+
+    #[test]
+    fn quaternion() {
+        // A rotation survives the round trip through a quaternion
+        let m = Matrix::rotate_y(consts::FRAC_PI_3) * Matrix::rotate_x(0.4);
+        let q = Quaternion::from_matrix(m);
+        let p = Point::new(1, 2, 3);
+        assert_eq!(Point::from(q.to_matrix() * p), Point::from(m * p));
+
+        // Slerp halfway rotates halfway
+        let a = Quaternion::from_matrix(Matrix::rotate_z(0.));
+        let b = Quaternion::from_matrix(Matrix::rotate_z(consts::FRAC_PI_2));
+        let m = a.slerp(b, 0.5).to_matrix();
+        let p = Point::new(1, 0, 0);
+        assert_eq!(
+            Point::from(m * p),
+            Point::from(Matrix::rotate_z(consts::FRAC_PI_4) * p)
+        );
+    }
+
+    #[test]
+    fn interpolate() {
+        // The ends of the motion are the given matrices
+        let start = Matrix::translate(0, 0, 0);
+        let end = Matrix::translate(4, 2, 0) * Matrix::rotate_y(consts::FRAC_PI_2);
+        let motion = MotionTransform::new(start, end, 0., 1.);
+        assert_eq!(motion.interpolate(0.), start);
+        assert_eq!(motion.interpolate(1.), end);
+        assert_eq!(motion.interpolate(-1.), start);
+
+        // Halfway translates halfway and rotates halfway
+        let m = motion.interpolate(0.5);
+        let expected = Matrix::translate(2, 1, 0) * Matrix::rotate_y(consts::FRAC_PI_4);
+        let p = Point::new(1, 0, 0);
+        assert_eq!(Point::from(m * p), Point::from(expected * p));
+
+        // Scale is interpolated separately from the rotation
+        let start = Matrix::scale(1, 1, 1);
+        let end = Matrix::rotate_x(consts::FRAC_PI_2) * Matrix::scale(3, 3, 3);
+        let m = MotionTransform::new(start, end, 0., 1.).interpolate(0.5);
+        let expected = Matrix::rotate_x(consts::FRAC_PI_4) * Matrix::scale(2, 2, 2);
+        let p = Point::new(0, 1, 0);
+        assert_eq!(Point::from(m * p), Point::from(expected * p));
+    }
+
+    #[test]
+    fn shutter() {
+        // Time is normalized by the shutter interval instead of clamped to [0, 1]
+        let start = Matrix::translate(0, 0, 0);
+        let end = Matrix::translate(4, 0, 0);
+        let motion = MotionTransform::new(start, end, 2., 6.);
+        assert_eq!(motion.interpolate(1.), start);
+        assert_eq!(motion.interpolate(2.), start);
+        assert_eq!(motion.interpolate(3.), Matrix::translate(1, 0, 0));
+        assert_eq!(motion.interpolate(6.), end);
+        assert_eq!(motion.interpolate(7.), end);
+    }
+
+    #[test]
+    fn keyframed() {
+        // Each segment interpolates between its own keyframes, given in any order
+        let motion = MotionTransform::keyframed(&[
+            (1., Matrix::translate(2, 0, 0)),
+            (0., Matrix::translate(0, 0, 0)),
+            (3., Matrix::translate(2, 4, 0)),
+        ]);
+        assert_eq!(motion.start, Matrix::translate(0, 0, 0));
+        assert_eq!(motion.end, Matrix::translate(2, 4, 0));
+        assert_eq!(motion.interpolate(0.5), Matrix::translate(1, 0, 0));
+        assert_eq!(motion.interpolate(1.), Matrix::translate(2, 0, 0));
+        assert_eq!(motion.interpolate(2.), Matrix::translate(2, 2, 0));
+
+        // A single keyframe holds still
+        let motion = MotionTransform::keyframed(&[(0.5, Matrix::translate(1, 2, 3))]);
+        assert_eq!(motion.interpolate(0.), Matrix::translate(1, 2, 3));
+        assert_eq!(motion.interpolate(1.), Matrix::translate(1, 2, 3));
+    }
+}
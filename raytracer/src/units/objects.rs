@@ -1,19 +1,32 @@
-use crate::units::color::{QuantColor, BLACK};
+use crate::units::color::{rgb, QuantColor};
+use crate::units::motion::MotionTransform;
+use crate::units::sampling::orthonormal_basis;
 use crate::units::tuple::{Point, Tuple, Vector, ORIGIN};
 use crate::units::utils;
 use crate::units::{Intersection, Matrix, Ray, Transformable, IDENTITY_MATRIX};
-use crate::world::{Material, PointLight};
+use crate::world::{Material, Medium, PointLight, Shading};
+use std::f64::consts;
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ObjectType {
     Sphere,
     Plane,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Shape {
     pub transformation_matrix: Matrix,
     pub material: Material,
     pub object_type: ObjectType,
+    /// Optional movement during the exposure, overrides `transformation_matrix`
+    pub motion: Option<MotionTransform>,
+    /// Whether the shape blocks light on its way to other objects
+    pub casts_shadow: bool,
+    /// Whether camera rays see the shape
+    pub visible_to_camera: bool,
+    /// Whether reflected, refracted and bounced rays see the shape
+    pub visible_in_secondary: bool,
+    /// Medium filling the shape, its surface then only bounds the medium and isn't shaded
+    pub interior: Option<Medium>,
 }
 
 impl Shape {
@@ -32,8 +45,78 @@ impl Shape {
         )
     }
 
-    pub fn intersect(&self, ray: Ray) -> Vec<Intersection> {
-        let local_ray = ray.transform(self.transformation_matrix.invert().unwrap());
+    pub fn set_casts_shadow(&self, casts_shadow: bool) -> Shape {
+        Shape {
+            casts_shadow,
+            ..self.clone()
+        }
+    }
+
+    pub fn set_interior(&self, interior: Option<Medium>) -> Shape {
+        Shape {
+            interior,
+            ..self.clone()
+        }
+    }
+
+    /// Hides the shape from camera rays, secondary rays or both
+    pub fn set_visible(&self, camera: bool, secondary: bool) -> Shape {
+        Shape {
+            visible_to_camera: camera,
+            visible_in_secondary: secondary,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the shape can be hit by a ray
+    pub fn is_visible_to(&self, ray: Ray) -> bool {
+        if ray.secondary {
+            self.visible_in_secondary
+        } else {
+            self.visible_to_camera
+        }
+    }
+
+    /// Makes the shape move from its current transformation at `shutter_open` to `end` at `shutter_close`
+    pub fn set_motion(&self, end: Matrix, shutter_open: f64, shutter_close: f64) -> Shape {
+        Shape {
+            motion: Some(MotionTransform::new(
+                self.transformation_matrix,
+                end,
+                shutter_open,
+                shutter_close,
+            )),
+            ..self.clone()
+        }
+    }
+
+    /// Makes the shape move through the given (time, transformation) keyframes
+    pub fn set_keyframes(&self, keyframes: &[(f64, Matrix)]) -> Shape {
+        Shape {
+            motion: Some(MotionTransform::keyframed(keyframes)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the transformation of the shape at given time
+    pub fn transformation_at(&self, time: f64) -> Matrix {
+        match &self.motion {
+            Some(motion) => motion.interpolate(time),
+            None => self.transformation_matrix,
+        }
+    }
+
+    /// Returns a still copy of the shape frozen at given time
+    pub fn at_time(&self, time: f64) -> Shape {
+        Shape {
+            transformation_matrix: self.transformation_at(time),
+            motion: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn intersect(&self, ray: Ray) -> Vec<Intersection<'_>> {
+        let local_ray = ray.transform(self.transformation_at(ray.time).invert().unwrap());
         match self.object_type {
             ObjectType::Sphere => self.intersect_sphere(local_ray),
             ObjectType::Plane => self.intersect_plane(local_ray),
@@ -41,11 +124,16 @@ impl Shape {
     }
 
     pub fn set_material(&self, material: Material) -> Shape {
-        Shape { material, ..*self }
+        Shape {
+            material,
+            ..self.clone()
+        }
     }
 
-    fn intersect_sphere(&self, local_ray: Ray) -> Vec<Intersection> {
-        let Ray { origin, direction } = local_ray;
+    fn intersect_sphere(&self, local_ray: Ray) -> Vec<Intersection<'_>> {
+        let Ray {
+            origin, direction, ..
+        } = local_ray;
 
         let distance = origin - Point::new(0, 0, 0);
 
@@ -72,7 +160,7 @@ impl Shape {
         ]
     }
 
-    fn intersect_plane(&self, local_ray: Ray) -> Vec<Intersection> {
+    fn intersect_plane(&self, local_ray: Ray) -> Vec<Intersection<'_>> {
         if local_ray.direction.y.abs() < utils::EPSILON {
             vec![]
         } else {
@@ -84,16 +172,103 @@ impl Shape {
     }
 
     pub fn normal(&self, point: Point) -> Vector {
-        let local_point = Point::from(self.transformation_matrix.invert().unwrap() * point);
+        self.normal_at(point, 0.)
+    }
+
+    /// Returns the normal at a point for the shape's position at given time
+    pub fn normal_at(&self, point: Point, time: f64) -> Vector {
+        let inverse = self.transformation_at(time).invert().unwrap();
+        let local_point = Point::from(inverse * point);
         let local_normal = match self.object_type {
             ObjectType::Sphere => local_point - ORIGIN,
             ObjectType::Plane => Vector::new(0, 1, 0),
         };
 
-        Vector::from(self.transformation_matrix.invert().unwrap().transpose() * local_normal)
-            .normalize()
+        Vector::from(inverse.transpose() * local_normal).normalize()
+    }
+
+    /// Returns the normal at a point tilted by the material's bump, if it has one
+    pub fn shading_normal_at(&self, point: Point, time: f64) -> Vector {
+        let bump = match &self.material.bump {
+            Some(bump) => bump,
+            None => return self.normal_at(point, time),
+        };
+        let inverse = self.transformation_at(time).invert().unwrap();
+        let local_point = Point::from(inverse * point);
+        let (local_normal, tangent) = match self.object_type {
+            ObjectType::Sphere => (
+                (local_point - ORIGIN).normalize(),
+                Vector::new(-local_point.z, 0., local_point.x),
+            ),
+            ObjectType::Plane => (Vector::new(0, 1, 0), Vector::new(1, 0, 0)),
+        };
+        // Sphere poles have no direction of growing u, any tangent will do
+        let tangent = if tangent.magnitude() < utils::EPSILON {
+            orthonormal_basis(local_normal).0
+        } else {
+            tangent.normalize()
+        };
+        let bitangent = tangent.cross(local_normal);
+        let uv = self.uv(local_point);
+        let local_normal = bump.perturb(local_point, uv, local_normal, tangent, bitangent);
+
+        Vector::from(inverse.transpose() * local_normal).normalize()
+    }
+
+    /// Returns texture coordinates of a point in object space
+    ///
+    /// Spheres wrap u around the equator and run v from the bottom pole to the top one,
+    /// planes repeat the unit square.
+    pub fn uv(&self, local_point: Point) -> (f64, f64) {
+        match self.object_type {
+            ObjectType::Sphere => {
+                let theta = local_point.x.atan2(local_point.z);
+                let radius = (local_point - ORIGIN).magnitude();
+                let phi = (local_point.y / radius).clamp(-1., 1.).acos();
+                (
+                    1. - (theta / (2. * consts::PI) + 0.5),
+                    1. - phi / consts::PI,
+                )
+            }
+            ObjectType::Plane => (local_point.x.rem_euclid(1.), local_point.z.rem_euclid(1.)),
+        }
+    }
+
+    /// Returns center and radius of a world space sphere enclosing the shape at given time,
+    /// None for unbounded shapes
+    pub fn bounding_sphere(&self, time: f64) -> Option<(Point, f64)> {
+        match self.object_type {
+            ObjectType::Sphere => {
+                let m = self.transformation_at(time);
+                let axes = [
+                    Vector::new(1, 0, 0),
+                    Vector::new(0, 1, 0),
+                    Vector::new(0, 0, 1),
+                ];
+                // Bounds the longest axis of the ellipsoid even for skewed transformations
+                let radius = axes
+                    .iter()
+                    .map(|&a| Vector::from(m * a).magnitude().powi(2))
+                    .sum::<f64>()
+                    .sqrt();
+                Some((Point::from(m * ORIGIN), radius))
+            }
+            ObjectType::Plane => None,
+        }
+    }
+
+    /// Returns unlit surface color at a world point, from the pattern or the base color
+    pub fn color_at(&self, position: Point) -> QuantColor {
+        match self.material.pattern {
+            Some(pattern) => pattern.color_at_object(self, position),
+            None => self.material.color,
+        }
     }
 
+    /// Returns Phong shading of a point lit by a point light as linear radiance, 1.0 is white
+    ///
+    /// Light intensity scales the surface color proportionally, 255 lets all of it through,
+    /// and the result is not clamped, so bright lights give colors above white.
     pub fn lightning(
         &self,
         light: PointLight,
@@ -101,48 +276,75 @@ impl Shape {
         eyev: Vector,
         normalv: Vector,
         in_shadow: bool,
-    ) -> QuantColor {
-        let intensity = QuantColor::new(
-            light.intensity.r / 255,
-            light.intensity.g / 255,
-            light.intensity.b / 255,
-        );
-        let color = if self.material.pattern.is_some() {
-            self.material
-                .pattern
-                .unwrap()
-                .color_at_object(*self, position)
+    ) -> [f64; 3] {
+        let ambient = self
+            .effective_color(light, position)
+            .map(|c| c * self.material.ambient);
+        if in_shadow {
+            ambient
         } else {
-            self.material.color
-        };
+            let direct = self.direct_light(light, position, eyev, normalv);
+            [0, 1, 2].map(|i| ambient[i] + direct[i])
+        }
+    }
 
-        let effective_color = (color * intensity).clamp();
-        let lightv = (light.position - position).normalize();
+    /// Returns unlit surface color as floats, 1.0 is white
+    pub fn base_color(&self, position: Point) -> [f64; 3] {
+        rgb(self.color_at(position))
+    }
 
-        let ambient = (effective_color * self.material.ambient as f64).clamp();
-        let diffuse;
-        let specular;
+    /// Returns how much of the mirror direction is reflected per channel
+    ///
+    /// Phong materials reflect their `reflect` level, microfacet materials reflect
+    /// their Fresnel reflectance, fading out as they get rougher.
+    pub fn reflectance(&self, position: Point, eyev: Vector, normalv: Vector) -> [f64; 3] {
+        match self.material.shading {
+            Shading::Phong => [self.material.reflect; 3],
+            Shading::Microfacet(microfacet) => {
+                let fresnel = microfacet.fresnel(self.base_color(position), eyev.dot(normalv));
+                let glossiness = (1. - microfacet.roughness).powi(2);
+                fresnel.map(|f| f * glossiness)
+            }
+        }
+    }
+
+    /// Returns surface color lit by the light's color
+    fn effective_color(&self, light: PointLight, position: Point) -> [f64; 3] {
+        let color = self.base_color(position);
+        let intensity = rgb(light.intensity);
+        [0, 1, 2].map(|i| color[i] * intensity[i])
+    }
 
+    /// Returns diffuse and specular light arriving straight from an unblocked light
+    pub fn direct_light(
+        &self,
+        light: PointLight,
+        position: Point,
+        eyev: Vector,
+        normalv: Vector,
+    ) -> [f64; 3] {
+        let lightv = (light.position - position).normalize();
+        let intensity = rgb(light.intensity);
+        if let Shading::Microfacet(microfacet) = self.material.shading {
+            // Point light intensity is the irradiance it gives a surface facing it
+            let reflected = microfacet.eval(self.base_color(position), normalv, eyev, lightv);
+            return [0, 1, 2].map(|i| intensity[i] * reflected[i] * consts::PI);
+        }
         let light_dot_normal = lightv.dot(normalv);
         if light_dot_normal < 0. {
-            diffuse = BLACK;
-            specular = BLACK;
-        } else {
-            diffuse = (effective_color * self.material.diffuse as f64 * light_dot_normal).clamp();
-            let reflectv = (-lightv).reflect(normalv);
-            let reflect_dot_eye = reflectv.dot(eyev);
-
-            if reflect_dot_eye <= 0. {
-                specular = BLACK;
-            } else {
-                let factor = reflect_dot_eye.powf(self.material.shine);
-                specular = (light.intensity * self.material.specular as f64 * factor).clamp();
-            }
+            return [0.; 3];
         }
-        if in_shadow {
-            ambient
+        let diffuse = self
+            .effective_color(light, position)
+            .map(|c| c * self.material.diffuse * light_dot_normal);
+        let reflectv = (-lightv).reflect(normalv);
+        let reflect_dot_eye = reflectv.dot(eyev);
+
+        if reflect_dot_eye <= 0. {
+            diffuse
         } else {
-            ambient + diffuse + specular
+            let factor = reflect_dot_eye.powf(self.material.shine);
+            [0, 1, 2].map(|i| diffuse[i] + intensity[i] * self.material.specular * factor)
         }
     }
 }
@@ -151,31 +353,31 @@ impl Transformable for Shape {
     fn translate<T: Into<f64>>(&self, x: T, y: T, z: T) -> Shape {
         Shape {
             transformation_matrix: self.transformation_matrix * Matrix::translate(x, y, z),
-            ..*self
+            ..self.clone()
         }
     }
     fn scale<T: Into<f64>>(&self, x: T, y: T, z: T) -> Shape {
         Shape {
             transformation_matrix: self.transformation_matrix * Matrix::scale(x, y, z),
-            ..*self
+            ..self.clone()
         }
     }
     fn rotate_x<T: Into<f64> + Copy>(&self, r: T) -> Shape {
         Shape {
             transformation_matrix: self.transformation_matrix * Matrix::rotate_x(r),
-            ..*self
+            ..self.clone()
         }
     }
     fn rotate_y<T: Into<f64> + Copy>(&self, r: T) -> Shape {
         Shape {
             transformation_matrix: self.transformation_matrix * Matrix::rotate_y(r),
-            ..*self
+            ..self.clone()
         }
     }
     fn rotate_z<T: Into<f64> + Copy>(&self, r: T) -> Shape {
         Shape {
             transformation_matrix: self.transformation_matrix * Matrix::rotate_z(r),
-            ..*self
+            ..self.clone()
         }
     }
     fn skew<T: Into<f64> + Copy>(
@@ -190,13 +392,13 @@ impl Transformable for Shape {
         Shape {
             transformation_matrix: self.transformation_matrix
                 * Matrix::skew(x_to_y, x_to_z, y_to_x, y_to_z, z_to_x, z_to_y),
-            ..*self
+            ..self.clone()
         }
     }
     fn transform(&self, transformation_matrix: Matrix) -> Self {
         Shape {
             transformation_matrix,
-            ..*self
+            ..self.clone()
         }
     }
 }
@@ -207,6 +409,11 @@ impl Default for Shape {
             transformation_matrix: IDENTITY_MATRIX,
             material: Material::default(),
             object_type: ObjectType::Sphere,
+            motion: None,
+            casts_shadow: true,
+            visible_to_camera: true,
+            visible_in_secondary: true,
+            interior: None,
         }
     }
 }
@@ -214,7 +421,8 @@ impl Default for Shape {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::units::color::WHITE;
+    use crate::units::color::{quantize, WHITE};
+    use crate::world::Microfacet;
     use std::f64::consts;
     #[test]
     fn new_sphere() {
@@ -237,6 +445,15 @@ mod tests {
     }
     // TODO: new_plane()
 
+    #[test]
+    fn bounding_sphere() {
+        let sphere = Shape::default().translate(1, 2, 3).scale(2, 1, 1);
+        let (center, radius) = sphere.bounding_sphere(0.).unwrap();
+        assert_eq!(center, Point::new(1, 2, 3));
+        assert!(radius >= 2.);
+        assert_eq!(Shape::new(ObjectType::Plane).bounding_sphere(0.), None);
+    }
+
     #[test]
     fn intersect() {
         // Ray intersects sphere at two points.
@@ -300,6 +517,31 @@ mod tests {
         assert_eq!(i.len(), 0);
     }
 
+    #[test]
+    fn motion() {
+        // A moving sphere is hit at its position at the ray's time
+        let s = Shape::new(ObjectType::Sphere).set_motion(Matrix::translate(0, 4, 0), 0., 1.);
+        let r = Ray::new(Point::new(0, 0, -5), Vector::new(0, 0, 1));
+        assert_eq!(s.intersect(r).len(), 2);
+        assert_eq!(s.intersect(r.set_time(1.)).len(), 0);
+        let r = Ray::new(Point::new(0, 2, -5), Vector::new(0, 0, 1)).set_time(0.5);
+        let xs = s.intersect(r);
+        assert_eq!(xs.len(), 2);
+        assert_eq!(xs[0].t, 4.);
+
+        // The normal follows the moving shape
+        let n = s.normal_at(Point::new(0, 3, 0), 0.5);
+        assert_eq!(n, Vector::new(0, 1, 0));
+
+        // A frozen copy has no motion
+        let frozen = s.at_time(0.5);
+        assert!(frozen.motion.is_none());
+        assert_eq!(
+            Point::from(frozen.transformation_matrix * ORIGIN),
+            Point::new(0, 2, 0)
+        );
+    }
+
     #[test]
     fn plane_intersect() {
         // Intersect with a ray parallel to the plane
@@ -345,49 +587,43 @@ mod tests {
 
         //The normal on a sphere at non axial point
         let n = s.normal(Point::new(
-            (3. as f64).sqrt() / 3.,
-            (3. as f64).sqrt() / 3.,
-            (3. as f64).sqrt() / 3.,
+            3_f64.sqrt() / 3.,
+            3_f64.sqrt() / 3.,
+            3_f64.sqrt() / 3.,
         ));
         assert_eq!(
             n,
-            Vector::new(
-                (3. as f64).sqrt() / 3.,
-                (3. as f64).sqrt() / 3.,
-                (3. as f64).sqrt() / 3.,
-            )
+            Vector::new(3_f64.sqrt() / 3., 3_f64.sqrt() / 3., 3_f64.sqrt() / 3.,)
         );
 
         //The normal on a sphere at non axial point
         let n = s.normal(Point::new(
-            (3. as f64).sqrt() / 3.,
-            (3. as f64).sqrt() / 3.,
-            (3. as f64).sqrt() / 3.,
+            3_f64.sqrt() / 3.,
+            3_f64.sqrt() / 3.,
+            3_f64.sqrt() / 3.,
         ));
         assert_eq!(
             n,
-            Vector::new(
-                (3. as f64).sqrt() / 3.,
-                (3. as f64).sqrt() / 3.,
-                (3. as f64).sqrt() / 3.,
-            )
-            .normalize()
+            Vector::new(3_f64.sqrt() / 3., 3_f64.sqrt() / 3., 3_f64.sqrt() / 3.,).normalize()
         );
 
         // Computing a normal to a translated sphere
         let s = Shape::new(ObjectType::Sphere).transform(Matrix::translate(0, 1, 0));
-        let n = s.normal(Point::new(0., 1.70711, -0.70711));
-        assert_eq!(n, Vector::new(0., 0.7071067811865475, -0.7071067811865476));
+        let n = s.normal(Point::new(
+            0.,
+            1. + consts::FRAC_1_SQRT_2,
+            -consts::FRAC_1_SQRT_2,
+        ));
+        assert_eq!(
+            n,
+            Vector::new(0., consts::FRAC_1_SQRT_2, -consts::FRAC_1_SQRT_2)
+        );
 
         // Computing a normal to a transformed sphere
         let s = Shape::new(ObjectType::Sphere);
         let m = Matrix::scale(1., 0.5, 1.) * Matrix::rotate_z(consts::PI / 5.);
         let s = s.transform(m);
-        let n = s.normal(Point::new(
-            0.,
-            ((2. as f64).sqrt()) / 2.,
-            (-(2. as f64).sqrt()) / 2.,
-        ));
+        let n = s.normal(Point::new(0., (2_f64.sqrt()) / 2., (-2_f64.sqrt()) / 2.));
         assert_eq!(n, Vector::new(0., 0.9701425001453319, -0.24253562503633294));
     }
 
@@ -401,6 +637,14 @@ mod tests {
 
     #[test]
     fn lightning() {
+        // Radiance is compared to the reference values of the Ray Tracer Challenge. The
+        // integer pipeline these tests used to go through truncated after every product,
+        // which left its colors one or two steps below them, like 483 for 1.9.
+        let assert_radiance = |res: [f64; 3], expected: [f64; 3]| {
+            for i in 0..3 {
+                assert!((res[i] - expected[i]).abs() < 1e-4, "{:?}", res);
+            }
+        };
         let o = Shape::default();
         let p = Point::new(0, 0, 0);
 
@@ -409,41 +653,114 @@ mod tests {
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 0, -10), QuantColor::new(255, 255, 255));
         let res = o.lightning(light, p, eyev, normalv, false);
-        assert_eq!(res, QuantColor::new(483, 483, 483));
+        assert_radiance(res, [1.9; 3]);
 
         // Lighting with the eye between light and surface, eye offset 45°
-        let eyev = Vector::new(0., (2.0 as f64).sqrt() / 2., (2.0 as f64).sqrt() / 2.);
+        let eyev = Vector::new(0., 2.0_f64.sqrt() / 2., 2.0_f64.sqrt() / 2.);
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 0, -10), WHITE);
-        let res = o.lightning(light, p, eyev, normalv, false).clamp();
-        assert_eq!(res, QuantColor::new(254, 254, 254));
+        let res = o.lightning(light, p, eyev, normalv, false);
+        assert_radiance(res, [1.; 3]);
 
         // Lighting with eye opposite surface, light offset 45°
         let eyev = Vector::new(0, 0, -1);
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 10, -10), QuantColor::new(255, 255, 255));
         let res = o.lightning(light, p, eyev, normalv, false);
-        assert_eq!(res, QuantColor::new(186, 186, 186));
+        assert_radiance(res, [0.7364; 3]);
 
         // Lighting with eye in the path of the reflection vector
-        let eyev = Vector::new(0., -(2.0 as f64).sqrt() / 2., -(2.0 as f64).sqrt() / 2.);
+        let eyev = Vector::new(0., -2.0_f64.sqrt() / 2., -2.0_f64.sqrt() / 2.);
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 10, -10), QuantColor::new(255, 255, 255));
         let res = o.lightning(light, p, eyev, normalv, false);
-        assert_eq!(res, QuantColor::new(415, 415, 415));
+        assert_radiance(res, [1.6364; 3]);
 
         // Lighting with the light behind the surface
         let eyev = Vector::new(0, 0, -1);
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 10, 10), QuantColor::new(255, 255, 255));
         let res = o.lightning(light, p, eyev, normalv, false);
-        assert_eq!(res, QuantColor::new(25, 25, 25));
+        assert_radiance(res, [0.1; 3]);
 
         // Lighting with the surface in shadow
         let eyev = Vector::new(0, 0, -1);
         let normalv = Vector::new(0, 0, -1);
         let light = PointLight::new(Point::new(0, 10, 10), QuantColor::new(255, 255, 255));
         let res = o.lightning(light, p, eyev, normalv, true);
-        assert_eq!(res, QuantColor::new(25, 25, 25));
+        assert_radiance(res, [0.1; 3]);
+
+        // Dim lights scale the surface color in proportion
+        let light = PointLight::new(Point::new(0, 0, -10), QuantColor::new(128, 128, 128));
+        let res = quantize(o.lightning(light, p, eyev, normalv, false));
+        assert_eq!(res, QuantColor::new(243, 243, 243));
+
+        // Bright lights are not clamped to white
+        let light = PointLight::new(Point::new(0, 0, -10), QuantColor::new(510, 510, 510));
+        let res = quantize(o.lightning(light, p, eyev, normalv, false));
+        assert_eq!(res, QuantColor::new(969, 969, 969));
+    }
+
+    #[test]
+    fn microfacet() {
+        let p = Point::new(0, 0, 0);
+        let eyev = Vector::new(0, 0, -1);
+        let normalv = Vector::new(0, 0, -1);
+        let light = PointLight::new(Point::new(0, 0, -10), WHITE);
+        let gold = Shape::default().set_material(
+            Material::default()
+                .set_color(QuantColor::new(255, 200, 50))
+                .set_shading(Shading::Microfacet(Microfacet::new(0.5, 1., 0.))),
+        );
+
+        // Metals reflect light tinted by their color
+        let res = gold.direct_light(light, p, eyev, normalv);
+        assert!(res[0] > res[1] && res[1] > res[2] && res[2] > 0.);
+
+        // Nothing is lit from behind
+        let behind = PointLight::new(Point::new(0, 0, 10), WHITE);
+        assert_eq!(gold.direct_light(behind, p, eyev, normalv), [0.; 3]);
+
+        // Mirror reflections fade as the surface gets rougher
+        let rough = gold.reflectance(p, eyev, normalv);
+        let polished = gold
+            .set_material(
+                gold.material
+                    .set_shading(Shading::Microfacet(Microfacet::new(0., 1., 0.))),
+            )
+            .reflectance(p, eyev, normalv);
+        assert!(polished[0] > rough[0]);
+        assert_eq!(polished[0], 1.);
+
+        // Phong materials reflect their reflect level
+        let phong = Shape::default().set_material(Material::default().set_reflect(0.3));
+        assert_eq!(phong.reflectance(p, eyev, normalv), [0.3; 3]);
+    }
+
+    #[test]
+    fn uv() {
+        let sphere = Shape::default();
+        assert_eq!(sphere.uv(Point::new(0, 0, 1)), (0.5, 0.5));
+        assert_eq!(sphere.uv(Point::new(0, 1, 0)).1, 1.);
+        assert_eq!(sphere.uv(Point::new(-1, 0, 0)), (0.75, 0.5));
+        let plane = Shape::new(ObjectType::Plane);
+        assert_eq!(plane.uv(Point::new(0.25, 0., -0.5)), (0.25, 0.5));
+    }
+
+    #[test]
+    fn shading_normal_at() {
+        // Without a bump the shading normal is the geometric one
+        let sphere = Shape::default().translate(1, 0, 0);
+        let p = Point::new(1., 0., -1.);
+        assert_eq!(sphere.shading_normal_at(p, 0.), sphere.normal_at(p, 0.));
+
+        // Normal maps tilt the normal toward the tangent in world space
+        let tilted = std::sync::Arc::new(crate::world::Texture::new(1, 1, vec![[1., 0.5, 1.]]));
+        let plane = Shape::new(ObjectType::Plane).set_material(
+            Material::default().set_bump(Some(crate::world::Bump::NormalMap(tilted, 1.))),
+        );
+        let normal = plane.shading_normal_at(Point::new(0.3, 0., 0.6), 0.);
+        assert!(utils::float_eq(normal.x, normal.y));
+        assert!(utils::float_eq(normal.z, 0.));
     }
 }
@@ -1,6 +1,6 @@
 //! Ray operations
 use crate::units::tuple::{Point, Vector};
-use crate::units::Matrix;
+use crate::units::{utils, Matrix};
 /// Ray is a simply line.
 ///
 #[derive(Debug, Copy, Clone)]
@@ -9,6 +9,12 @@ pub struct Ray {
     pub origin: Point,
     /// Direction of a Ray.
     pub direction: Vector,
+    /// Moment in time the Ray is cast at.
+    pub time: f64,
+    /// Color channel the Ray carries alone through dispersive materials, None for all three.
+    pub channel: Option<usize>,
+    /// Whether the Ray was spawned by a reflection, refraction or bounce instead of the camera.
+    pub secondary: bool,
 }
 
 impl Ray {
@@ -30,7 +36,36 @@ impl Ray {
     /// let r = Ray::new(Point::new(0, 0, 0), Vector::new(0, 1, 0));
     /// ```
     pub fn new(origin: Point, direction: Vector) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.,
+            channel: None,
+            secondary: false,
+        }
+    }
+
+    /// Returns the same Ray cast at a different moment in time.
+    pub fn set_time(&self, time: f64) -> Ray {
+        Ray { time, ..*self }
+    }
+
+    /// Returns the same Ray carrying a single color channel.
+    pub fn set_channel(&self, channel: Option<usize>) -> Ray {
+        Ray { channel, ..*self }
+    }
+
+    /// Returns the same Ray marked as spawned by a reflection, refraction or bounce.
+    pub fn set_secondary(&self, secondary: bool) -> Ray {
+        Ray { secondary, ..*self }
+    }
+
+    /// Returns the same Ray restarted just past the point at given travel time.
+    pub fn continue_past(&self, t: f64) -> Ray {
+        Ray {
+            origin: self.position(t) + self.direction.normalize() * utils::EPSILON,
+            ..*self
+        }
     }
 
     /// Returns a Tuple point from given travel time
@@ -57,6 +92,7 @@ impl Ray {
         Ray {
             origin: Point::from(m * self.origin),
             direction: Vector::from(m * self.direction),
+            ..self
         }
     }
 }
@@ -106,5 +142,10 @@ mod tests {
 
         assert_eq!(r2.origin, Point::new(2, 6, 12));
         assert_eq!(r2.direction, Vector::new(0, 3, 0));
+
+        // Transforming a ray keeps its time
+        let r1 = Ray::new(Point::new(1, 2, 3), Vector::new(0, 1, 0)).set_time(0.25);
+        let r2 = r1.transform(Matrix::translate(3, 4, 5));
+        assert_eq!(r2.time, 0.25);
     }
 }
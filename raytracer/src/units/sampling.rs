@@ -0,0 +1,470 @@
+//! Random sampling helpers used by multi-sample rendering
+//!
+//! Renders are bit-reproducible across machines and thread counts: random numbers come
+//! from per-sample seeds instead of shared state, and the generators and sequences only
+//! use integer arithmetic and exact conversions to f64. The warps use `sin`, `cos` and
+//! `sqrt`, `sqrt` is exact and the trigonometry matches wherever the platform math
+//! library does.
+use crate::units::tuple::{Tuple, Vector};
+use std::f64::consts;
+
+/// Small deterministic random number generator (SplitMix64).
+///
+/// Each pixel sample gets its own generator seeded from its coordinates,
+/// so the numbers it produces do not depend on the order rayon schedules pixels in.
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates new Rng from a seed
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Creates new Rng for the given sample of a pixel
+    ///
+    /// # Arguments
+    /// * `x` - pixel x coordinate
+    /// * `y` - pixel y coordinate
+    /// * `sample` - index of the sample inside of the pixel
+    pub fn for_sample(x: usize, y: usize, sample: usize) -> Rng {
+        let mut seed = Rng::new(x as u64).next_u64();
+        seed = Rng::new(seed ^ y as u64).next_u64();
+        seed = Rng::new(seed ^ sample as u64).next_u64();
+        Rng::new(seed)
+    }
+
+    /// Creates new Rng shared by all samples of a pixel, like rotations of a sequence
+    pub fn for_pixel(x: usize, y: usize) -> Rng {
+        // No pixel takes that many samples
+        Rng::for_sample(x, y, usize::MAX)
+    }
+
+    /// Returns next random u64
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns next random f64 in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Source of the numbers placing samples in the pixel, on the lens and in time
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sequence {
+    /// Independent random numbers of each sample's Rng
+    #[default]
+    Random,
+    /// Halton sequence, one prime base per dimension
+    Halton,
+    /// Sobol sequence in base 2
+    Sobol,
+    /// R2 sequence, covers the pixel plane only
+    R2,
+}
+
+impl Sequence {
+    /// Returns one coordinate of a point of the sequence, None where the sequence doesn't
+    /// reach and random numbers take over
+    ///
+    /// # Arguments
+    /// * `index` - index of the point, the sample inside of the pixel
+    /// * `dimension` - coordinate of the point, starting at 0
+    pub fn sample(&self, index: usize, dimension: usize) -> Option<f64> {
+        match self {
+            Sequence::Random => None,
+            Sequence::Halton => PRIMES
+                .get(dimension)
+                .map(|&base| radical_inverse(base, index as u64)),
+            Sequence::Sobol => {
+                (dimension < SOBOL_DIMENSIONS).then(|| sobol(index as u32, dimension))
+            }
+            Sequence::R2 => (dimension < 2).then(|| r2(index as u64)[dimension]),
+        }
+    }
+}
+
+/// Bases of the Halton sequence
+const PRIMES: [u64; 8] = [2, 3, 5, 7, 11, 13, 17, 19];
+
+/// Returns digits of the index in a base mirrored around the decimal point
+///
+/// In base 2 this is the van der Corput sequence.
+pub fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let mut digits = 0_u64;
+    let mut scale = 1_u64;
+    while index > 0 && scale <= u64::MAX / base {
+        digits = digits * base + index % base;
+        scale *= base;
+        index /= base;
+    }
+    digits as f64 / scale as f64
+}
+
+/// Returns a point of the Halton sequence
+///
+/// # Arguments
+/// * `index` - index of the point
+/// * `dimension` - coordinate, its base is the prime of that position, up to 19
+pub fn halton(index: u64, dimension: usize) -> f64 {
+    radical_inverse(PRIMES[dimension], index)
+}
+
+/// Dimensions the Sobol sequence has direction numbers for
+const SOBOL_DIMENSIONS: usize = 5;
+
+/// Primitive polynomial degree, coefficients and initial numbers by Joe and Kuo,
+/// for dimensions after the first
+const SOBOL_POLYNOMIALS: [(usize, u32, [u32; 3]); SOBOL_DIMENSIONS - 1] = [
+    (1, 0, [1, 0, 0]),
+    (2, 1, [1, 3, 0]),
+    (3, 1, [1, 3, 1]),
+    (3, 2, [1, 1, 1]),
+];
+
+/// Direction numbers of every Sobol dimension, computed once at compile time
+const SOBOL_DIRECTIONS: [[u32; 32]; SOBOL_DIMENSIONS] = {
+    let mut directions = [[0_u32; 32]; SOBOL_DIMENSIONS];
+    let mut dimension = 0;
+    while dimension < SOBOL_DIMENSIONS {
+        directions[dimension] = sobol_directions(dimension);
+        dimension += 1;
+    }
+    directions
+};
+
+/// Returns 32 direction numbers of a Sobol dimension as binary fractions
+const fn sobol_directions(dimension: usize) -> [u32; 32] {
+    let mut v = [0_u32; 32];
+    if dimension == 0 {
+        let mut k = 0;
+        while k < 32 {
+            v[k] = 1 << (31 - k);
+            k += 1;
+        }
+        return v;
+    }
+    let (degree, coefficients, initial) = SOBOL_POLYNOMIALS[dimension - 1];
+    let mut k = 0;
+    while k < 32 {
+        v[k] = if k < degree {
+            initial[k] << (31 - k)
+        } else {
+            let mut value = v[k - degree] ^ (v[k - degree] >> degree);
+            let mut j = 1;
+            while j < degree {
+                if (coefficients >> (degree - 1 - j)) & 1 == 1 {
+                    value ^= v[k - j];
+                }
+                j += 1;
+            }
+            value
+        };
+        k += 1;
+    }
+    v
+}
+
+/// Returns a point of the Sobol sequence
+///
+/// # Arguments
+/// * `index` - index of the point
+/// * `dimension` - coordinate, up to 4
+pub fn sobol(index: u32, dimension: usize) -> f64 {
+    let directions = &SOBOL_DIRECTIONS[dimension];
+    let bits = (0..32)
+        .filter(|bit| (index >> bit) & 1 == 1)
+        .fold(0_u32, |bits, bit| bits ^ directions[bit]);
+    bits as f64 / (1_u64 << 32) as f64
+}
+
+/// Returns a point of the R2 sequence, steps by powers of the inverse plastic number
+pub fn r2(index: u64) -> [f64; 2] {
+    // 1/g and 1/g^2 as 64 bit binary fractions, g^3 = g + 1
+    const STEPS: [u64; 2] = [0xc13f_a9a9_02a6_328f, 0x91e1_0da5_c79e_7b1c];
+    STEPS.map(|step| {
+        let bits = (1_u64 << 63).wrapping_add(step.wrapping_mul(index));
+        (bits >> 11) as f64 / (1_u64 << 53) as f64
+    })
+}
+
+/// Shifts a sequence value by an offset, wrapping around [0, 1)
+///
+/// A different offset per pixel (Cranley–Patterson rotation) hides the structure of a
+/// sequence shared by all pixels.
+pub fn cranley_patterson(value: f64, offset: f64) -> f64 {
+    let shifted = value + offset;
+    if shifted >= 1. {
+        shifted - 1.
+    } else {
+        shifted
+    }
+}
+
+/// Maps a point of the unit square onto the unit disk, preserving stratification.
+///
+/// # Arguments
+/// * `u` - first coordinate in [0, 1)
+/// * `v` - second coordinate in [0, 1)
+///
+/// # Returns
+/// (x, y) on the unit disk
+pub fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let u = 2. * u - 1.;
+    let v = 2. * v - 1.;
+    if u == 0. && v == 0. {
+        return (0., 0.);
+    }
+    let (r, theta) = if u.abs() > v.abs() {
+        (u, consts::FRAC_PI_4 * (v / u))
+    } else {
+        (v, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (u / v))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Maps a point of the unit square onto the hemisphere around +z with density cos(theta) / pi
+///
+/// # Arguments
+/// * `u` - first coordinate in [0, 1)
+/// * `v` - second coordinate in [0, 1)
+///
+/// # Returns
+/// (x, y, z) of a unit direction with z >= 0
+pub fn cosine_hemisphere(u: f64, v: f64) -> (f64, f64, f64) {
+    let (x, y) = concentric_disk(u, v);
+    let z = (1. - x * x - y * y).max(0.).sqrt();
+    (x, y, z)
+}
+
+/// Maps a point of the unit square uniformly onto the hemisphere around +z
+///
+/// # Returns
+/// (x, y, z) of a unit direction with z >= 0
+pub fn uniform_hemisphere(u: f64, v: f64) -> (f64, f64, f64) {
+    uniform_cone(u, v, 0.)
+}
+
+/// Maps a point of the unit square uniformly onto a cone of directions around +z
+///
+/// # Arguments
+/// * `u` - first coordinate in [0, 1)
+/// * `v` - second coordinate in [0, 1)
+/// * `cos_max` - cosine of the cone's half angle, -1 for the whole sphere
+///
+/// # Returns
+/// (x, y, z) of a unit direction with z >= cos_max
+pub fn uniform_cone(u: f64, v: f64, cos_max: f64) -> (f64, f64, f64) {
+    let z = 1. - u * (1. - cos_max);
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * consts::PI * v;
+    (r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Returns two vectors that are perpendicular to the normal and to each other
+///
+/// Together with the normal they form the frame hemisphere samples are placed in.
+pub fn orthonormal_basis(normal: Vector) -> (Vector, Vector) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vector::new(0, 1, 0)
+    } else {
+        Vector::new(1, 0, 0)
+    };
+    let tangent = helper.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+/// Maps a point of the unit square onto a regular polygon inscribed in the unit circle.
+///
+/// # Arguments
+/// * `u` - first coordinate in [0, 1)
+/// * `v` - second coordinate in [0, 1)
+/// * `sides` - number of polygon sides, at least 3
+/// * `rotation` - polygon rotation in radians
+///
+/// # Returns
+/// (x, y) inside of the polygon
+pub fn regular_polygon(u: f64, v: f64, sides: usize, rotation: f64) -> (f64, f64) {
+    let sides = sides as f64;
+    let wedge = 2. * consts::PI / sides;
+    // Pick a triangle fan wedge, then sample that triangle uniformly.
+    let scaled = u * sides;
+    let index = scaled.floor().min(sides - 1.);
+    let u = scaled - index;
+    let (a, b) = if u + v > 1. { (1. - u, 1. - v) } else { (u, v) };
+    let start = rotation + index * wedge;
+    let end = start + wedge;
+    (
+        a * start.cos() + b * end.cos(),
+        a * start.sin() + b * end.sin(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::utils;
+
+    #[test]
+    fn rng() {
+        // The same sample always produces the same sequence
+        let mut a = Rng::for_sample(3, 7, 2);
+        let mut b = Rng::for_sample(3, 7, 2);
+        assert_eq!(a.next_u64(), b.next_u64());
+
+        // Different samples produce different sequences
+        let mut c = Rng::for_sample(7, 3, 2);
+        assert_ne!(a.next_u64(), c.next_u64());
+
+        // Floats are in [0, 1)
+        let mut r = Rng::new(42);
+        for _ in 0..1000 {
+            let f = r.next_f64();
+            assert!((0. ..1.).contains(&f));
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        // Radical inverses mirror the digits of the index
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert_eq!(halton(5, 1), 7. / 9.);
+
+        // The second Sobol dimension follows the known sequence
+        let values: Vec<f64> = (0..4).map(|i| sobol(i, 1)).collect();
+        assert_eq!(values, [0., 0.5, 0.75, 0.25]);
+
+        // Each 1/4 by 1/4 cell of the first 16 Sobol points holds one point
+        let mut cells = [0; 16];
+        for i in 0..16 {
+            cells[(sobol(i, 1) * 4.) as usize * 4 + (sobol(i, 0) * 4.) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+
+        // Every dimension puts the first 8 points into different eighths
+        for dimension in 0..5 {
+            let mut eighths = [0; 8];
+            for i in 0..8 {
+                eighths[(sobol(i, dimension) * 8.) as usize] += 1;
+            }
+            assert_eq!(eighths, [1; 8]);
+        }
+
+        // R2 points are spread evenly, every cell of a 4 by 4 grid gets about its share
+        let mut cells = [0; 16];
+        for i in 0..1600 {
+            let [x, y] = r2(i);
+            assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+            cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| (95..=105).contains(&count)));
+
+        // Sequences stop at their dimensions, random numbers take over
+        assert_eq!(Sequence::Random.sample(3, 0), None);
+        assert_eq!(Sequence::Halton.sample(5, 1), Some(7. / 9.));
+        assert_eq!(Sequence::Sobol.sample(5, 5), None);
+        assert_eq!(Sequence::R2.sample(5, 1), Some(r2(5)[1]));
+        assert_eq!(Sequence::R2.sample(5, 2), None);
+    }
+
+    #[test]
+    fn cranley_patterson() {
+        assert_eq!(super::cranley_patterson(0.25, 0.5), 0.75);
+        assert_eq!(super::cranley_patterson(0.75, 0.5), 0.25);
+        // Rotations per pixel are stable
+        assert_eq!(
+            Rng::for_pixel(4, 2).next_u64(),
+            Rng::for_pixel(4, 2).next_u64()
+        );
+    }
+
+    #[test]
+    fn concentric_disk() {
+        // The center of the square maps to the center of the disk
+        assert_eq!(super::concentric_disk(0.5, 0.5), (0., 0.));
+
+        // Samples stay inside of the unit disk
+        let mut r = Rng::new(1);
+        for _ in 0..1000 {
+            let (x, y) = super::concentric_disk(r.next_f64(), r.next_f64());
+            assert!(x * x + y * y <= 1. + 1e-9);
+        }
+    }
+
+    #[test]
+    fn regular_polygon() {
+        // Samples stay inside of the hexagon's inscribed circle bound
+        let mut r = Rng::new(2);
+        for _ in 0..1000 {
+            let (x, y) = super::regular_polygon(r.next_f64(), r.next_f64(), 6, 0.);
+            assert!(x * x + y * y <= 1. + 1e-9);
+        }
+    }
+
+    #[test]
+    fn orthonormal_basis() {
+        for normal in [
+            Vector::new(0, 1, 0),
+            Vector::new(1, 0, 0),
+            Vector::new(1, 2, 3).normalize(),
+        ] {
+            let (t, b) = super::orthonormal_basis(normal);
+            assert!(utils::float_eq(t.dot(normal), 0.));
+            assert!(utils::float_eq(b.dot(normal), 0.));
+            assert!(utils::float_eq(t.dot(b), 0.));
+            assert!(utils::float_eq(b.magnitude(), 1.));
+        }
+    }
+
+    #[test]
+    fn cosine_hemisphere() {
+        // Directions are unit length, point up and favour the pole
+        let mut r = Rng::new(3);
+        let mut z_sum = 0.;
+        for _ in 0..10000 {
+            let (x, y, z) = super::cosine_hemisphere(r.next_f64(), r.next_f64());
+            assert!((x * x + y * y + z * z - 1.).abs() < 1e-9);
+            assert!(z >= 0.);
+            z_sum += z;
+        }
+        // The mean of cos(theta) under a cosine density is 2/3
+        assert!((z_sum / 10000. - 2. / 3.).abs() < 0.01);
+    }
+
+    #[test]
+    fn uniform_hemisphere() {
+        // Directions point up and have a mean cos(theta) of 1/2
+        let mut r = Rng::new(5);
+        let mut z_sum = 0.;
+        for _ in 0..10000 {
+            let (x, y, z) = super::uniform_hemisphere(r.next_f64(), r.next_f64());
+            assert!((x * x + y * y + z * z - 1.).abs() < 1e-9);
+            assert!(z >= 0.);
+            z_sum += z;
+        }
+        assert!((z_sum / 10000. - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn uniform_cone() {
+        // Directions are unit length, stay in the cone and cover it evenly
+        let mut r = Rng::new(4);
+        let mut z_sum = 0.;
+        for _ in 0..10000 {
+            let (x, y, z) = super::uniform_cone(r.next_f64(), r.next_f64(), 0.5);
+            assert!((x * x + y * y + z * z - 1.).abs() < 1e-9);
+            assert!(z >= 0.5);
+            z_sum += z;
+        }
+        assert!((z_sum / 10000. - 0.75).abs() < 0.01);
+    }
+}
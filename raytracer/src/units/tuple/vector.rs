@@ -188,10 +188,10 @@ mod tests {
         assert_eq!(t1.magnitude(), 1.0);
 
         let t1 = Vector::new(1.0, 2.0, 3.0);
-        assert_eq!(t1.magnitude(), (14.0 as f64).sqrt());
+        assert_eq!(t1.magnitude(), 14.0_f64.sqrt());
 
         let t1 = Vector::new(-1.0, -2.0, -3.0);
-        assert_eq!(t1.magnitude(), (14.0 as f64).sqrt());
+        assert_eq!(t1.magnitude(), 14.0_f64.sqrt());
     }
 
     #[test]
@@ -205,9 +205,9 @@ mod tests {
         assert_eq!(
             t2,
             Vector::new(
-                1.0 / (14.0 as f64).sqrt(),
-                2.0 / (14.0 as f64).sqrt(),
-                3.0 / (14.0 as f64).sqrt()
+                1.0 / 14.0_f64.sqrt(),
+                2.0 / 14.0_f64.sqrt(),
+                3.0 / 14.0_f64.sqrt()
             )
         );
 
@@ -239,7 +239,7 @@ mod tests {
         assert_eq!(r, Vector::new(1, 1, 0));
 
         let v = Vector::new(0, -1, 0);
-        let n = Vector::new((2.0 as f64).sqrt() / 2., (2.0 as f64).sqrt() / 2., 0.);
+        let n = Vector::new(2.0_f64.sqrt() / 2., 2.0_f64.sqrt() / 2., 0.);
         let r = v.reflect(n);
         assert_eq!(r, Vector::new(1, 0, 0));
     }
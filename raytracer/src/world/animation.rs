@@ -0,0 +1,224 @@
+//! Keyframe animation
+use crate::units::color::QuantColor;
+use crate::units::tuple::{Point, Vector};
+
+/// Easing defines how a value travels from one keyframe to the next
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Easing {
+    /// Constant speed
+    #[default]
+    Linear,
+    /// Holds the value until the next keyframe
+    Step,
+    /// Cubic bezier curve through (0, 0) and (1, 1) with control points (x1, y1) and (x2, y2)
+    Bezier(f64, f64, f64, f64),
+}
+
+impl Easing {
+    /// Returns eased progress for the linear progress `t` between 0 and 1
+    pub fn ease(&self, t: f64) -> f64 {
+        match *self {
+            Easing::Linear => t,
+            Easing::Step => 0.,
+            Easing::Bezier(x1, y1, x2, y2) => {
+                let s = bezier_parameter(t, x1, x2);
+                bezier(s, y1, y2)
+            }
+        }
+    }
+}
+
+/// One coordinate of a cubic bezier curve starting at 0 and ending at 1
+fn bezier(s: f64, p1: f64, p2: f64) -> f64 {
+    let inv = 1. - s;
+    3. * inv * inv * s * p1 + 3. * inv * s * s * p2 + s * s * s
+}
+
+/// Finds the curve parameter at which the x coordinate equals `x`
+fn bezier_parameter(x: f64, x1: f64, x2: f64) -> f64 {
+    let (mut low, mut high) = (0., 1.);
+    let mut s = x;
+    for _ in 0..64 {
+        let value = bezier(s, x1, x2);
+        if (value - x).abs() < 1e-9 {
+            break;
+        }
+        if value < x {
+            low = s;
+        } else {
+            high = s;
+        }
+        s = (low + high) / 2.;
+    }
+    s
+}
+
+/// Values that can be blended between keyframes
+pub trait Interpolate: Copy {
+    /// Returns value that is `t` of the way from self to other
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &f64, t: f64) -> f64 {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for [f64; 3] {
+    fn interpolate(&self, other: &[f64; 3], t: f64) -> [f64; 3] {
+        [
+            self[0].interpolate(&other[0], t),
+            self[1].interpolate(&other[1], t),
+            self[2].interpolate(&other[2], t),
+        ]
+    }
+}
+
+impl Interpolate for Point {
+    fn interpolate(&self, other: &Point, t: f64) -> Point {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Interpolate for Vector {
+    fn interpolate(&self, other: &Vector, t: f64) -> Vector {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Interpolate for QuantColor {
+    fn interpolate(&self, other: &QuantColor, t: f64) -> QuantColor {
+        let lerp = |a: i64, b: i64| (a as f64).interpolate(&(b as f64), t).round() as i64;
+        QuantColor::new(
+            lerp(self.r, other.r),
+            lerp(self.g, other.g),
+            lerp(self.b, other.b),
+        )
+    }
+}
+
+/// Value of a track at a moment in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<T> {
+    /// Time of the keyframe
+    pub time: f64,
+    /// Value at the keyframe
+    pub value: T,
+    /// Easing used from this keyframe to the next one
+    pub easing: Easing,
+}
+
+/// Track is a list of keyframes of a single animated value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    /// Keyframes ordered by time
+    pub keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Interpolate> Track<T> {
+    /// Creates new empty Track
+    pub fn new() -> Track<T> {
+        Track {
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Inserts a keyframe, keeping keyframes ordered by time
+    pub fn add_keyframe(&mut self, time: f64, value: T, easing: Easing) {
+        let index = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(
+            index,
+            Keyframe {
+                time,
+                value,
+                easing,
+            },
+        );
+    }
+
+    /// Returns value of the track at given time
+    ///
+    /// Before the first and after the last keyframe the value is held.
+    /// Returns None if the track has no keyframes.
+    pub fn value_at(&self, time: f64) -> Option<T> {
+        let first = self.keyframes.first()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        for pair in self.keyframes.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            if time < to.time {
+                let t = (time - from.time) / (to.time - from.time);
+                return Some(from.value.interpolate(&to.value, from.easing.ease(t)));
+            }
+        }
+        self.keyframes.last().map(|k| k.value)
+    }
+}
+
+impl<T: Interpolate> Default for Track<T> {
+    fn default() -> Track<T> {
+        Track::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::tuple::Tuple;
+    use crate::units::utils;
+
+    #[test]
+    fn ease() {
+        // Linear easing is the identity
+        assert_eq!(Easing::Linear.ease(0.3), 0.3);
+
+        // Step easing holds the start value
+        assert_eq!(Easing::Step.ease(0.9), 0.);
+
+        // A linear bezier is the identity
+        let e = Easing::Bezier(0.25, 0.25, 0.75, 0.75);
+        assert!(utils::float_eq(e.ease(0.3), 0.3));
+
+        // Ease in and out is symmetric and slow at the ends
+        let e = Easing::Bezier(0.42, 0., 0.58, 1.);
+        assert!(utils::float_eq(e.ease(0.5), 0.5));
+        assert!(e.ease(0.1) < 0.1);
+        assert!(e.ease(0.9) > 0.9);
+    }
+
+    #[test]
+    fn value_at() {
+        // An empty track has no value
+        let t: Track<f64> = Track::new();
+        assert!(t.value_at(0.).is_none());
+
+        // Values are interpolated between keyframes and held outside of them
+        let mut t = Track::new();
+        t.add_keyframe(2., 10., Easing::Linear);
+        t.add_keyframe(0., 0., Easing::Linear);
+        t.add_keyframe(3., 0., Easing::Step);
+        assert_eq!(t.value_at(-1.), Some(0.));
+        assert_eq!(t.value_at(1.), Some(5.));
+        assert_eq!(t.value_at(2.5), Some(5.));
+        assert_eq!(t.value_at(10.), Some(0.));
+
+        // Step easing jumps at the next keyframe
+        let mut t = Track::new();
+        t.add_keyframe(0., Point::new(0, 0, 0), Easing::Step);
+        t.add_keyframe(1., Point::new(1, 1, 1), Easing::Linear);
+        assert_eq!(t.value_at(0.99), Some(Point::new(0, 0, 0)));
+        assert_eq!(t.value_at(1.), Some(Point::new(1, 1, 1)));
+
+        // Colors are interpolated per channel
+        let mut t = Track::new();
+        t.add_keyframe(0., QuantColor::new(0, 100, 255), Easing::Linear);
+        t.add_keyframe(1., QuantColor::new(255, 100, 0), Easing::Linear);
+        assert_eq!(t.value_at(0.5), Some(QuantColor::new(128, 100, 128)));
+    }
+}
@@ -0,0 +1,340 @@
+//! What rays see when they miss every object
+use crate::units::color::{rgb, QuantColor, BLACK};
+use crate::units::tuple::{Tuple, Vector};
+use crate::world::animation::Interpolate;
+use image::codecs::hdr::HdrDecoder;
+use image::ImageResult;
+use std::f64::consts;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Background of the world
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    /// Single color
+    Color(QuantColor),
+    /// Vertical gradient from the color straight up to the color straight down
+    Gradient(QuantColor, QuantColor),
+    /// Black that is left out of the alpha coverage, for compositing
+    Transparent,
+    /// Equirectangular environment map, lights the scene in path traced renders
+    Map(Arc<EnvironmentMap>),
+}
+
+impl Background {
+    /// Returns linear radiance seen by a ray going in given direction, 1.0 is white
+    pub fn radiance(&self, direction: Vector) -> [f64; 3] {
+        match *self {
+            Background::Map(ref map) => map.radiance(direction),
+            Background::Gradient(top, bottom) => {
+                let t = 0.5 * (1. - direction.normalize().y);
+                let (top, bottom) = (rgb(top), rgb(bottom));
+                [0, 1, 2].map(|i| top[i].interpolate(&bottom[i], t))
+            }
+            _ => rgb(self.color(direction)),
+        }
+    }
+
+    /// Returns color seen by a ray going in given direction
+    pub fn color(&self, direction: Vector) -> QuantColor {
+        match *self {
+            Background::Color(color) => color,
+            Background::Gradient(top, bottom) => {
+                let t = 0.5 * (1. - direction.normalize().y);
+                top.interpolate(&bottom, t)
+            }
+            Background::Transparent => BLACK,
+            Background::Map(ref map) => map.color(direction),
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Background {
+        Background::Color(BLACK)
+    }
+}
+
+/// Equirectangular image of the light arriving from every direction
+///
+/// The top row looks straight up and the center of the image looks down -z,
+/// matching the equirectangular camera projection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    /// Linear radiance row by row, 1.0 is white
+    pub pixels: Vec<[f32; 3]>,
+    /// Brightness multiplier
+    pub intensity: f64,
+    /// Rotation around the y axis in radians
+    pub rotation: f64,
+    /// Distribution of rows, height + 1 values from 0 to 1
+    marginal: Vec<f64>,
+    /// Distribution of pixels inside of each row, width + 1 values per row
+    conditional: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    /// Creates new EnvironmentMap and its sampling distribution
+    ///
+    /// Pixels are sampled proportionally to their luminance and the solid angle they cover.
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> EnvironmentMap {
+        assert_eq!(pixels.len(), width * height, "Wrong number of pixels");
+        let mut conditional = Vec::with_capacity(height * (width + 1));
+        let mut row_weights = Vec::with_capacity(height);
+        for row in 0..height {
+            let sin_theta = (consts::PI * (row as f64 + 0.5) / height as f64).sin();
+            let start = conditional.len();
+            let mut sum = 0.;
+            conditional.push(0.);
+            for p in &pixels[row * width..(row + 1) * width] {
+                sum += luminance(p) * sin_theta;
+                conditional.push(sum);
+            }
+            normalize(&mut conditional[start..]);
+            row_weights.push(sum);
+        }
+        let mut marginal = vec![0.];
+        let mut sum = 0.;
+        for weight in row_weights {
+            sum += weight;
+            marginal.push(sum);
+        }
+        normalize(&mut marginal);
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            intensity: 1.,
+            rotation: 0.,
+            marginal,
+            conditional,
+        }
+    }
+
+    /// Loads EnvironmentMap from a Radiance HDR file or any 8 bit sRGB image
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<EnvironmentMap> {
+        let is_hdr = path
+            .as_ref()
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?.into_iter().map(|p| p.0).collect();
+            Ok(EnvironmentMap::new(
+                metadata.width as usize,
+                metadata.height as usize,
+                pixels,
+            ))
+        } else {
+            let image = image::open(path)?.to_rgb8();
+            let decode = |v: u8| {
+                let v = v as f32 / 255.;
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            };
+            let pixels = image
+                .pixels()
+                .map(|p| [decode(p[0]), decode(p[1]), decode(p[2])])
+                .collect();
+            Ok(EnvironmentMap::new(
+                image.width() as usize,
+                image.height() as usize,
+                pixels,
+            ))
+        }
+    }
+
+    pub fn set_intensity(mut self, intensity: f64) -> EnvironmentMap {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn set_rotation(mut self, rotation: f64) -> EnvironmentMap {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Returns image coordinates in [0, 1) of a direction
+    fn uv(&self, direction: Vector) -> (f64, f64) {
+        let d = direction.normalize();
+        let longitude = d.x.atan2(-d.z) - self.rotation;
+        let latitude = d.y.clamp(-1., 1.).asin();
+        let u = (0.5 - longitude / (2. * consts::PI)).rem_euclid(1.);
+        let v = (0.5 - latitude / consts::PI).clamp(0., 1.);
+        (u, v)
+    }
+
+    /// Returns direction of image coordinates
+    fn direction(&self, u: f64, v: f64) -> Vector {
+        let longitude = (0.5 - u) * 2. * consts::PI + self.rotation;
+        let latitude = (0.5 - v) * consts::PI;
+        Vector::new(
+            longitude.sin() * latitude.cos(),
+            latitude.sin(),
+            -longitude.cos() * latitude.cos(),
+        )
+    }
+
+    /// Returns column and row of the pixel seen in a direction
+    fn pixel(&self, direction: Vector) -> (usize, usize) {
+        let (u, v) = self.uv(direction);
+        let column = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row = ((v * self.height as f64) as usize).min(self.height - 1);
+        (column, row)
+    }
+
+    /// Returns radiance arriving from a direction, 1.0 is white
+    pub fn radiance(&self, direction: Vector) -> [f64; 3] {
+        let (column, row) = self.pixel(direction);
+        let p = self.pixels[row * self.width + column];
+        [
+            p[0] as f64 * self.intensity,
+            p[1] as f64 * self.intensity,
+            p[2] as f64 * self.intensity,
+        ]
+    }
+
+    /// Returns radiance arriving from a direction as a QuantColor
+    pub fn color(&self, direction: Vector) -> QuantColor {
+        let [r, g, b] = self.radiance(direction);
+        QuantColor::new(
+            (r * 255.).round() as i64,
+            (g * 255.).round() as i64,
+            (b * 255.).round() as i64,
+        )
+    }
+
+    /// Picks a direction with probability proportional to the light coming from it
+    ///
+    /// # Arguments
+    /// * `u` - first random number in [0, 1)
+    /// * `v` - second random number in [0, 1)
+    ///
+    /// # Returns
+    /// direction and its probability density per solid angle
+    pub fn sample(&self, u: f64, v: f64) -> (Vector, f64) {
+        let (row, row_offset) = sample_cdf(&self.marginal, v);
+        let cdf = &self.conditional[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let (column, column_offset) = sample_cdf(cdf, u);
+        let direction = self.direction(
+            (column as f64 + column_offset) / self.width as f64,
+            (row as f64 + row_offset) / self.height as f64,
+        );
+        (direction, self.pdf(direction))
+    }
+
+    /// Returns probability density per solid angle of sampling a direction
+    pub fn pdf(&self, direction: Vector) -> f64 {
+        let (column, row) = self.pixel(direction);
+        let row_probability = self.marginal[row + 1] - self.marginal[row];
+        let start = row * (self.width + 1);
+        let column_probability =
+            self.conditional[start + column + 1] - self.conditional[start + column];
+        let sin_theta = (consts::PI * (row as f64 + 0.5) / self.height as f64).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        let image_pdf = row_probability * column_probability * (self.width * self.height) as f64;
+        image_pdf / (2. * consts::PI * consts::PI * sin_theta)
+    }
+}
+
+/// Returns luminance of a linear color
+fn luminance(p: &[f32; 3]) -> f64 {
+    0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64
+}
+
+/// Turns running sums into a distribution from 0 to 1, uniform when everything is black
+fn normalize(cdf: &mut [f64]) {
+    let total = cdf[cdf.len() - 1];
+    let steps = (cdf.len() - 1) as f64;
+    for (i, value) in cdf.iter_mut().enumerate() {
+        *value = if total > 0. {
+            *value / total
+        } else {
+            i as f64 / steps
+        };
+    }
+}
+
+/// Finds the bucket of a distribution a random number falls in and the offset inside of it
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let index = (cdf.partition_point(|c| *c <= u).max(1) - 1).min(cdf.len() - 2);
+    let width = cdf[index + 1] - cdf[index];
+    let offset = if width > 0. {
+        ((u - cdf[index]) / width).clamp(0., 1.)
+    } else {
+        0.5
+    };
+    (index, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::color::WHITE;
+    use crate::units::sampling::Rng;
+
+    #[test]
+    fn color() {
+        let direction = Vector::new(0, 1, 0);
+        assert_eq!(Background::default().color(direction), BLACK);
+        assert_eq!(Background::Transparent.color(direction), BLACK);
+
+        // Gradients blend by the height of the direction
+        let b = Background::Gradient(WHITE, BLACK);
+        assert_eq!(b.color(direction), WHITE);
+        assert_eq!(b.color(Vector::new(0, -2, 0)), BLACK);
+        assert_eq!(
+            b.color(Vector::new(1, 0, 0)),
+            QuantColor::new(128, 128, 128)
+        );
+    }
+
+    #[test]
+    fn environment_map() {
+        // A single bright pixel in the middle of the image is seen looking down -z
+        let mut pixels = vec![[0.; 3]; 8 * 4];
+        pixels[8 + 4] = [2., 1., 0.];
+        let map = EnvironmentMap::new(8, 4, pixels);
+        let forward = Vector::new(-0.1, 0.1, -1.);
+        assert_eq!(map.radiance(forward), [2., 1., 0.]);
+        assert_eq!(map.color(forward), QuantColor::new(510, 255, 0));
+        assert_eq!(map.radiance(Vector::new(0, 0, 1)), [0., 0., 0.]);
+
+        // Rotating the map turns the bright pixel to the side
+        let rotated = map.clone().set_rotation(consts::FRAC_PI_2);
+        assert_eq!(rotated.radiance(Vector::new(1., 0.1, -0.1)), [2., 1., 0.]);
+
+        // Every sample lands on the bright pixel
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let (direction, pdf) = map.sample(rng.next_f64(), rng.next_f64());
+            assert_eq!(map.radiance(direction), [2., 1., 0.]);
+            assert!(pdf > 0.);
+        }
+        assert_eq!(map.pdf(Vector::new(0, 0, 1)), 0.);
+    }
+
+    #[test]
+    fn pdf() {
+        // A uniform map samples the whole sphere evenly
+        let map = EnvironmentMap::new(16, 8, vec![[1.; 3]; 16 * 8]);
+        let mut rng = Rng::new(4);
+        let mut estimate = 0.;
+        for _ in 0..20000 {
+            let (_, pdf) = map.sample(rng.next_f64(), rng.next_f64());
+            estimate += 1. / pdf;
+        }
+        // The mean of 1 / pdf estimates the area of the unit sphere
+        assert!((estimate / 20000. - 4. * consts::PI).abs() < 0.3);
+    }
+}
@@ -0,0 +1,259 @@
+//! Perturbation of shading normals by noise, normal maps and height maps
+use crate::units::tuple::{Point, Tuple, Vector};
+use image::ImageResult;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Image sampled with texture coordinates, values are raw channels where 1.0 is 255
+///
+/// Bumps share textures through an `Arc`, so materials using the same image keep it in
+/// memory once and it is freed with the last of them.
+#[derive(Clone, PartialEq)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Texture {
+    /// Creates new Texture
+    ///
+    /// # Arguments
+    /// * `width` - width in pixels
+    /// * `height` - height in pixels
+    /// * `pixels` - rows from the top, left to right
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> Texture {
+        Texture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads an 8 bit image without color decoding, as normal and height maps store data
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Texture> {
+        let image = image::open(path)?.to_rgb8();
+        let pixels = image
+            .pixels()
+            .map(|p| [p[0], p[1], p[2]].map(|v| v as f32 / 255.))
+            .collect();
+        Ok(Texture::new(
+            image.width() as usize,
+            image.height() as usize,
+            pixels,
+        ))
+    }
+
+    /// Returns bilinearly filtered color at texture coordinates, repeating outside [0, 1)
+    ///
+    /// `v` goes from the bottom of the image to the top.
+    pub fn sample(&self, u: f64, v: f64) -> [f64; 3] {
+        let x = u.rem_euclid(1.) * self.width as f64 - 0.5;
+        let y = (1. - v.rem_euclid(1.)) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[y * self.width + x].map(f64::from)
+        };
+        let (a, b) = (pixel(x0, y0), pixel(x0 + 1., y0));
+        let (c, d) = (pixel(x0, y0 + 1.), pixel(x0 + 1., y0 + 1.));
+        let mut result = [0.; 3];
+        for i in 0..3 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            result[i] = top + (bottom - top) * fy;
+        }
+        result
+    }
+
+    /// Returns average of the channels at texture coordinates
+    fn height(&self, u: f64, v: f64) -> f64 {
+        self.sample(u, v).iter().sum::<f64>() / 3.
+    }
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Texture({}x{})", self.width, self.height)
+    }
+}
+
+/// Perturbation applied to the normal of a material before shading
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bump {
+    /// Procedural bumps from smooth noise in object space
+    Noise {
+        /// How far the normal tilts
+        strength: f64,
+        /// Size of a bump
+        scale: f64,
+    },
+    /// Tangent space normal map read with the object's texture coordinates, holds strength
+    NormalMap(Arc<Texture>, f64),
+    /// Height map read with the object's texture coordinates, holds strength
+    HeightMap(Arc<Texture>, f64),
+}
+
+impl Bump {
+    /// Returns the perturbed normal
+    ///
+    /// # Arguments
+    /// * `point` - point in object space
+    /// * `uv` - texture coordinates of the point
+    /// * `normalv` - normal in object space
+    /// * `tangent` - direction of growing `u`, perpendicular to the normal
+    /// * `bitangent` - direction of growing `v`, perpendicular to both
+    pub fn perturb(
+        &self,
+        point: Point,
+        uv: (f64, f64),
+        normalv: Vector,
+        tangent: Vector,
+        bitangent: Vector,
+    ) -> Vector {
+        let (u, v) = uv;
+        match *self {
+            Bump::Noise { strength, scale } => {
+                let p = Point::new(point.x / scale, point.y / scale, point.z / scale);
+                let gradient = noise_gradient(p);
+                // Only the part of the gradient along the surface tilts the normal
+                let along = gradient - normalv * gradient.dot(normalv);
+                (normalv - along * strength).normalize()
+            }
+            Bump::NormalMap(ref texture, strength) => {
+                let [x, y, z] = texture.sample(u, v).map(|c| c * 2. - 1.);
+                (tangent * (x * strength) + bitangent * (y * strength) + normalv * z).normalize()
+            }
+            Bump::HeightMap(ref texture, strength) => {
+                let du = 1. / texture.width as f64;
+                let dv = 1. / texture.height as f64;
+                let slope_u = texture.height(u + du, v) - texture.height(u - du, v);
+                let slope_v = texture.height(u, v + dv) - texture.height(u, v - dv);
+                (normalv - (tangent * slope_u + bitangent * slope_v) * (strength / 2.)).normalize()
+            }
+        }
+    }
+}
+
+/// Smooth value noise in [0, 1] with a lattice of random values at integer points
+pub fn noise(point: Point) -> f64 {
+    let (x0, y0, z0) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let smooth = |t: f64| t * t * (3. - 2. * t);
+    let (fx, fy, fz) = (
+        smooth(point.x - x0),
+        smooth(point.y - y0),
+        smooth(point.z - z0),
+    );
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let corner = |dx: f64, dy: f64, dz: f64| lattice(x0 + dx, y0 + dy, z0 + dz);
+    let plane = |dz: f64| {
+        lerp(
+            lerp(corner(0., 0., dz), corner(1., 0., dz), fx),
+            lerp(corner(0., 1., dz), corner(1., 1., dz), fx),
+            fy,
+        )
+    };
+    lerp(plane(0.), plane(1.), fz)
+}
+
+/// Returns gradient of the noise by central differences
+fn noise_gradient(point: Point) -> Vector {
+    const H: f64 = 1e-3;
+    let diff = |d: Vector| (noise(point + d) - noise(point + d * -1.)) / (2. * H);
+    Vector::new(
+        diff(Vector::new(H, 0., 0.)),
+        diff(Vector::new(0., H, 0.)),
+        diff(Vector::new(0., 0., H)),
+    )
+}
+
+/// Random value in [0, 1) for an integer lattice point
+fn lattice(x: f64, y: f64, z: f64) -> f64 {
+    let mut h = (x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as i64 as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 32;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::utils;
+
+    #[test]
+    fn sample() {
+        // Texture coordinates wrap around and blend neighbouring pixels
+        let texture = Texture::new(2, 1, vec![[0., 0., 0.], [1., 1., 1.]]);
+        assert_eq!(texture.sample(0.25, 0.5), [0.; 3]);
+        assert_eq!(texture.sample(0.75, 0.5), [1.; 3]);
+        assert_eq!(texture.sample(1.25, 0.5), [0.; 3]);
+        assert_eq!(texture.sample(0.5, 0.5), [0.5; 3]);
+        assert!(Texture::load("missing.png").is_err());
+
+        // Loading reads the file again, so changes on disk are picked up
+        let path = "../target/texture.png";
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(path)
+            .unwrap();
+        assert_eq!(Texture::load(path).unwrap().sample(0.5, 0.5), [1., 0., 0.]);
+        image::RgbImage::from_pixel(2, 2, image::Rgb([0, 255, 0]))
+            .save(path)
+            .unwrap();
+        assert_eq!(Texture::load(path).unwrap().sample(0.5, 0.5), [0., 1., 0.]);
+    }
+
+    #[test]
+    fn noise() {
+        // Noise is repeatable, bounded and continuous
+        let p = Point::new(0.3, 1.7, -2.2);
+        assert_eq!(super::noise(p), super::noise(p));
+        for i in 0..100 {
+            let n = super::noise(Point::new(i as f64 * 0.37, i as f64 * 0.11, 0.5));
+            assert!((0. ..=1.).contains(&n));
+        }
+        let near = super::noise(p + Vector::new(1e-6, 0., 0.));
+        assert!((super::noise(p) - near).abs() < 1e-4);
+    }
+
+    #[test]
+    fn perturb() {
+        let n = Vector::new(0, 0, 1);
+        let t = Vector::new(1, 0, 0);
+        let b = Vector::new(0, 1, 0);
+        let p = Point::new(0, 0, 0);
+
+        // A flat normal map keeps the normal
+        let flat = Arc::new(Texture::new(1, 1, vec![[0.5, 0.5, 1.]]));
+        let normal = Bump::NormalMap(flat, 1.).perturb(p, (0.5, 0.5), n, t, b);
+        assert!(utils::float_eq(normal.z, 1.));
+
+        // Normal maps tilt toward the tangent
+        let tilted = Arc::new(Texture::new(1, 1, vec![[1., 0.5, 1.]]));
+        let normal = Bump::NormalMap(tilted, 1.).perturb(p, (0.5, 0.5), n, t, b);
+        assert!(utils::float_eq(normal.x, normal.z));
+
+        // Height maps tilt away from the slope
+        let ramp = Arc::new(Texture::new(
+            4,
+            1,
+            vec![[0.; 3], [0.25; 3], [0.5; 3], [0.75; 3]],
+        ));
+        let normal = Bump::HeightMap(ramp, 1.).perturb(p, (0.375, 0.5), n, t, b);
+        assert!(normal.x < 0. && utils::float_eq(normal.y, 0.));
+
+        // Noise keeps unit length normals on the right side
+        let normal = Bump::Noise {
+            strength: 0.3,
+            scale: 0.5,
+        }
+        .perturb(Point::new(0.2, 0.4, 0.), (0., 0.), n, t, b);
+        assert!(utils::float_eq(normal.magnitude(), 1.));
+        assert!(normal.z > 0.);
+    }
+}
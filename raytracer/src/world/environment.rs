@@ -1,5 +1,7 @@
 //! Environment simulation
-use crate::units::tuple::{Point, Vector};
+use crate::units::objects::{ObjectType, Shape};
+use crate::units::tuple::{Point, Tuple, Vector, ORIGIN};
+use crate::world::animation::{Easing, Track};
 
 /// Projectile is a projectile
 #[derive(Debug, Copy, Clone)]
@@ -30,3 +32,360 @@ pub fn tick(env: &Environment, proj: &Projectile) -> Projectile {
     let velocity = proj.velocity + env.gravity + env.wind;
     Projectile { position, velocity }
 }
+
+/// Moves a point under constant acceleration for `dt` seconds with semi-implicit Euler,
+/// the velocity is updated first and then moves the point
+fn integrate(position: Point, velocity: Vector, acceleration: Vector, dt: f64) -> (Point, Vector) {
+    let velocity = velocity + acceleration * dt;
+    (position + velocity * dt, velocity)
+}
+
+/// Sphere that is moved around by a Simulation, a radius of 0 makes it a particle
+#[derive(Debug, Copy, Clone)]
+pub struct RigidSphere {
+    /// current position of the center
+    pub position: Point,
+    /// current velocity in units per second
+    pub velocity: Vector,
+    /// sphere radius
+    pub radius: f64,
+    /// sphere mass, used when spheres hit each other
+    pub mass: f64,
+    /// how much of the normal velocity is kept after a bounce, 1.0 is a perfect bounce
+    pub restitution: f64,
+    /// Coulomb friction coefficient applied on contact
+    pub friction: f64,
+}
+
+impl RigidSphere {
+    /// Creates new RigidSphere at rest with a mass of 1 and a perfect bounce
+    pub fn new(position: Point, radius: f64) -> RigidSphere {
+        RigidSphere {
+            position,
+            velocity: Vector::new(0, 0, 0),
+            radius,
+            mass: 1.,
+            restitution: 1.,
+            friction: 0.,
+        }
+    }
+
+    pub fn set_velocity(&self, velocity: Vector) -> RigidSphere {
+        RigidSphere { velocity, ..*self }
+    }
+
+    pub fn set_mass(&self, mass: f64) -> RigidSphere {
+        RigidSphere { mass, ..*self }
+    }
+
+    pub fn set_restitution(&self, restitution: f64) -> RigidSphere {
+        RigidSphere {
+            restitution,
+            ..*self
+        }
+    }
+
+    pub fn set_friction(&self, friction: f64) -> RigidSphere {
+        RigidSphere { friction, ..*self }
+    }
+
+    /// Resolves contact with a surface of given normal that the sphere penetrates by `depth`
+    fn bounce(&mut self, normal: Vector, depth: f64) {
+        self.position = self.position + normal * depth;
+        let normal_speed = self.velocity.dot(normal);
+        if normal_speed >= 0. {
+            return;
+        }
+        let normal_velocity = normal * normal_speed;
+        let tangent_velocity = self.velocity - normal_velocity;
+        let tangent_speed = tangent_velocity.magnitude();
+
+        // Friction impulse is proportional to the normal impulse, but can only stop sliding
+        let normal_impulse = (1. + self.restitution) * -normal_speed;
+        let tangent_scale = if tangent_speed > 0. {
+            (1. - self.friction * normal_impulse / tangent_speed).max(0.)
+        } else {
+            0.
+        };
+        self.velocity = tangent_velocity * tangent_scale - normal_velocity * self.restitution;
+    }
+}
+
+/// Static scene geometry bodies collide with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Collider {
+    /// Infinite plane through a point with a normal
+    Plane(Point, Vector),
+    /// Sphere with center and radius
+    Sphere(Point, f64),
+}
+
+impl Collider {
+    /// Creates a Collider for a scene shape
+    ///
+    /// Spheres are expected to be scaled uniformly.
+    pub fn from_shape(shape: &Shape) -> Collider {
+        let center = Point::from(shape.transformation_matrix * ORIGIN);
+        match shape.object_type {
+            ObjectType::Plane => Collider::Plane(center, shape.normal(center)),
+            ObjectType::Sphere => {
+                let edge = Vector::from(shape.transformation_matrix * Vector::new(1, 0, 0));
+                Collider::Sphere(center, edge.magnitude())
+            }
+        }
+    }
+
+    /// Returns contact normal and penetration depth if the sphere touches the collider
+    ///
+    /// Planes push the sphere back to the side its center was on before the step, so fast
+    /// spheres crossing a plane within one step don't pass through it.
+    ///
+    /// # Arguments
+    /// * `body` - sphere after the step
+    /// * `previous` - center of the sphere before the step
+    fn contact(&self, body: &RigidSphere, previous: Point) -> Option<(Vector, f64)> {
+        let (normal, distance) = match *self {
+            Collider::Plane(point, normal) => {
+                let normal = if (previous - point).dot(normal) < 0. {
+                    -normal
+                } else {
+                    normal
+                };
+                (normal, (body.position - point).dot(normal))
+            }
+            Collider::Sphere(center, radius) => {
+                let v = body.position - center;
+                let length = v.magnitude();
+                if length == 0. {
+                    return None;
+                }
+                (v / length, length - radius)
+            }
+        };
+        let depth = body.radius - distance;
+        if depth > 0. {
+            Some((normal, depth))
+        } else {
+            None
+        }
+    }
+}
+
+/// Fixed timestep simulation of spheres moved by an Environment
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// gravity and wind, as accelerations in units per second squared
+    pub environment: Environment,
+    /// length of a single step in seconds
+    pub timestep: f64,
+    /// simulated bodies
+    pub bodies: Vec<RigidSphere>,
+    /// static geometry
+    pub colliders: Vec<Collider>,
+    /// simulated time in seconds
+    pub time: f64,
+}
+
+impl Simulation {
+    /// Creates new empty Simulation
+    pub fn new(environment: Environment, timestep: f64) -> Simulation {
+        Simulation {
+            environment,
+            timestep,
+            bodies: Vec::new(),
+            colliders: Vec::new(),
+            time: 0.,
+        }
+    }
+
+    /// Advances the simulation by a single timestep
+    pub fn step(&mut self) {
+        let acceleration = self.environment.gravity + self.environment.wind;
+        let previous: Vec<Point> = self.bodies.iter().map(|body| body.position).collect();
+        for body in self.bodies.iter_mut() {
+            let (position, velocity) =
+                integrate(body.position, body.velocity, acceleration, self.timestep);
+            body.position = position;
+            body.velocity = velocity;
+        }
+
+        for (body, &previous) in self.bodies.iter_mut().zip(&previous) {
+            for collider in &self.colliders {
+                if let Some((normal, depth)) = collider.contact(body, previous) {
+                    body.bounce(normal, depth);
+                }
+            }
+        }
+
+        for i in 0..self.bodies.len() {
+            for j in (i + 1)..self.bodies.len() {
+                let (left, right) = self.bodies.split_at_mut(j);
+                collide_bodies(&mut left[i], &mut right[0]);
+            }
+        }
+        self.time += self.timestep;
+    }
+
+    /// Runs the simulation for given duration and returns a position track per body
+    ///
+    /// Every step becomes a linear keyframe, so the tracks can drive object transforms.
+    pub fn run(&mut self, duration: f64) -> Vec<Track<Point>> {
+        let mut tracks: Vec<Track<Point>> = self.bodies.iter().map(|_| Track::new()).collect();
+        let end = self.time + duration;
+        loop {
+            for (track, body) in tracks.iter_mut().zip(&self.bodies) {
+                track.add_keyframe(self.time, body.position, Easing::Linear);
+            }
+            if self.time >= end - self.timestep / 2. {
+                break;
+            }
+            self.step();
+        }
+        tracks
+    }
+}
+
+/// Resolves a collision between two moving spheres
+fn collide_bodies(a: &mut RigidSphere, b: &mut RigidSphere) {
+    let v = a.position - b.position;
+    let distance = v.magnitude();
+    let depth = a.radius + b.radius - distance;
+    if depth <= 0. || distance == 0. {
+        return;
+    }
+    let normal = v / distance;
+    let inverse_a = 1. / a.mass;
+    let inverse_b = 1. / b.mass;
+    let share = depth / (inverse_a + inverse_b);
+    a.position = a.position + normal * (share * inverse_a);
+    b.position = b.position + normal * -(share * inverse_b);
+
+    let closing = (a.velocity - b.velocity).dot(normal);
+    if closing >= 0. {
+        return;
+    }
+    let restitution = a.restitution.min(b.restitution);
+    let impulse = -(1. + restitution) * closing / (inverse_a + inverse_b);
+    a.velocity = a.velocity + normal * (impulse * inverse_a);
+    b.velocity = b.velocity - normal * (impulse * inverse_b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{utils, Transformable};
+
+    #[test]
+    fn step() {
+        // A falling sphere accelerates with gravity
+        let mut sim = Simulation::new(
+            Environment {
+                gravity: Vector::new(0., -10., 0.),
+                wind: Vector::new(0, 0, 0),
+            },
+            0.1,
+        );
+        sim.bodies.push(RigidSphere::new(Point::new(0, 10, 0), 1.));
+        sim.step();
+        assert_eq!(sim.bodies[0].velocity, Vector::new(0, -1, 0));
+        assert_eq!(sim.bodies[0].position, Point::new(0., 9.9, 0.));
+
+        // A sphere bounces off the floor and loses energy with restitution
+        let floor = Shape::new(ObjectType::Plane);
+        sim.colliders.push(Collider::from_shape(&floor));
+        sim.bodies[0] = RigidSphere::new(Point::new(0., 1.05, 0.), 1.)
+            .set_velocity(Vector::new(0, -1, 0))
+            .set_restitution(0.5);
+        sim.step();
+        assert!(sim.bodies[0].position.y >= 1.);
+        assert!(utils::float_eq(sim.bodies[0].velocity.y, 1.));
+    }
+
+    #[test]
+    fn tunneling() {
+        // A small fast sphere crossing the floor within one step still bounces off it
+        let mut sim = Simulation::new(
+            Environment {
+                gravity: Vector::new(0., -10., 0.),
+                wind: Vector::new(0, 0, 0),
+            },
+            0.1,
+        );
+        sim.colliders
+            .push(Collider::from_shape(&Shape::new(ObjectType::Plane)));
+        sim.bodies.push(
+            RigidSphere::new(Point::new(0., 0.5, 0.), 0.05)
+                .set_velocity(Vector::new(0, -20, 0))
+                .set_restitution(0.5),
+        );
+        sim.step();
+        assert!(sim.bodies[0].position.y >= 0.05);
+        assert!(sim.bodies[0].velocity.y > 0.);
+        for _ in 0..50 {
+            sim.step();
+        }
+        assert!(sim.bodies[0].position.y >= 0.05 - utils::EPSILON);
+    }
+
+    #[test]
+    fn friction() {
+        // Friction slows down a sphere sliding on the floor
+        let mut sim = Simulation::new(
+            Environment {
+                gravity: Vector::new(0., -10., 0.),
+                wind: Vector::new(0, 0, 0),
+            },
+            0.01,
+        );
+        sim.colliders
+            .push(Collider::from_shape(&Shape::new(ObjectType::Plane)));
+        sim.bodies.push(
+            RigidSphere::new(Point::new(0, 1, 0), 1.)
+                .set_velocity(Vector::new(1, 0, 0))
+                .set_restitution(0.)
+                .set_friction(0.5),
+        );
+        for _ in 0..100 {
+            sim.step();
+        }
+        assert!(sim.bodies[0].velocity.x.abs() < utils::EPSILON);
+        assert!(sim.bodies[0].position.y >= 1. - utils::EPSILON);
+    }
+
+    #[test]
+    fn collider() {
+        // Sphere colliders take the shape's transformation
+        let s = Shape::new(ObjectType::Sphere)
+            .translate(1, 2, 3)
+            .scale(2, 2, 2);
+        assert_eq!(
+            Collider::from_shape(&s),
+            Collider::Sphere(Point::new(1, 2, 3), 2.)
+        );
+
+        // Two equal spheres swap velocities on a head on collision
+        let mut a = RigidSphere::new(Point::new(0, 0, 0), 1.).set_velocity(Vector::new(1, 0, 0));
+        let mut b = RigidSphere::new(Point::new(1.9, 0., 0.), 1.);
+        collide_bodies(&mut a, &mut b);
+        assert_eq!(a.velocity, Vector::new(0, 0, 0));
+        assert_eq!(b.velocity, Vector::new(1, 0, 0));
+    }
+
+    #[test]
+    fn run() {
+        // Each step becomes a keyframe
+        let mut sim = Simulation::new(
+            Environment {
+                gravity: Vector::new(0., -10., 0.),
+                wind: Vector::new(0, 0, 0),
+            },
+            0.25,
+        );
+        sim.bodies.push(RigidSphere::new(Point::new(0, 10, 0), 1.));
+        let tracks = sim.run(1.);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].keyframes.len(), 5);
+        assert_eq!(tracks[0].value_at(0.), Some(Point::new(0, 10, 0)));
+        assert_eq!(tracks[0].value_at(1.), Some(sim.bodies[0].position));
+    }
+}
@@ -3,9 +3,48 @@
 use crate::units::color::QuantColor;
 // use crate::units::tuple::{Point, Vector};
 // use crate::world::light::PointLight;
+use crate::world::bump::Bump;
+use crate::world::microfacet::Microfacet;
 use crate::world::patterns::Pattern;
-/// Represents a material
+
+/// Reflection model of a material
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Shading {
+    /// Classic Phong with `diffuse`, `specular` and `shine`
+    #[default]
+    Phong,
+    /// Physically based GGX microfacet model, the color is its base color
+    Microfacet(Microfacet),
+}
+
+/// Wavelengths in micrometres the red, green and blue channels are refracted at
+pub const WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+/// Refractive index that changes with the wavelength of light
 #[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// Cauchy's equation `n = a + b / λ²`, holds `a` and `b` for λ in micrometres
+    Cauchy(f64, f64),
+    /// Sellmeier equation, holds the `B` and `C` coefficients for λ in micrometres
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl Dispersion {
+    /// Returns refractive index at a wavelength in micrometres
+    pub fn index(&self, wavelength: f64) -> f64 {
+        let l2 = wavelength * wavelength;
+        match *self {
+            Dispersion::Cauchy(a, b) => a + b / l2,
+            Dispersion::Sellmeier(b, c) => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+}
+
+/// Represents a material
+#[derive(Debug, Clone, PartialEq)]
 pub struct Material {
     /// Material Color
     pub color: QuantColor,
@@ -24,6 +63,16 @@ pub struct Material {
     pub transparent: f64,
     /// refractive index 1.0 is default
     pub refractive_index: f64,
+    /// Reflection model
+    pub shading: Shading,
+    /// Color light turns into after travelling one unit through the material at density 1
+    pub absorption: QuantColor,
+    /// Strength of the absorption, 0.0 is clear
+    pub density: f64,
+    /// Wavelength dependent refractive index, overrides `refractive_index` per channel
+    pub dispersion: Option<Dispersion>,
+    /// Perturbation of the shading normal
+    pub bump: Option<Bump>,
 }
 
 impl Material {
@@ -36,44 +85,117 @@ impl Material {
     }
 
     pub fn set_pattern(&self, pattern: Option<Pattern>) -> Material {
-        Material { pattern, ..*self }
+        Material {
+            pattern,
+            ..self.clone()
+        }
     }
 
     pub fn set_ambient(&self, ambient: f64) -> Material {
-        Material { ambient, ..*self }
+        Material {
+            ambient,
+            ..self.clone()
+        }
     }
 
     pub fn set_color(&self, color: QuantColor) -> Material {
-        Material { color, ..*self }
+        Material {
+            color,
+            ..self.clone()
+        }
     }
 
     pub fn set_diffuse(&self, diffuse: f64) -> Material {
-        Material { diffuse, ..*self }
+        Material {
+            diffuse,
+            ..self.clone()
+        }
     }
     pub fn set_shine(&self, shine: f64) -> Material {
-        Material { shine, ..*self }
+        Material {
+            shine,
+            ..self.clone()
+        }
     }
 
     pub fn set_specular(&self, specular: f64) -> Material {
-        Material { specular, ..*self }
+        Material {
+            specular,
+            ..self.clone()
+        }
     }
     pub fn set_reflect(&self, reflect: f64) -> Material {
-        Material { reflect, ..*self }
+        Material {
+            reflect,
+            ..self.clone()
+        }
     }
 
     pub fn set_transparency(&self, transparent: f64) -> Material {
         Material {
             transparent,
-            ..*self
+            ..self.clone()
+        }
+    }
+
+    pub fn set_shading(&self, shading: Shading) -> Material {
+        Material {
+            shading,
+            ..self.clone()
         }
     }
 
     pub fn set_refractive_index(&self, refractive_index: f64) -> Material {
         Material {
             refractive_index,
-            ..*self
+            ..self.clone()
+        }
+    }
+
+    pub fn set_absorption(&self, absorption: QuantColor, density: f64) -> Material {
+        Material {
+            absorption,
+            density,
+            ..self.clone()
         }
     }
+
+    pub fn set_dispersion(&self, dispersion: Option<Dispersion>) -> Material {
+        Material {
+            dispersion,
+            ..self.clone()
+        }
+    }
+
+    pub fn set_bump(&self, bump: Option<Bump>) -> Material {
+        Material {
+            bump,
+            ..self.clone()
+        }
+    }
+
+    /// Returns refractive index seen by a color channel, None for all of them
+    pub fn index(&self, channel: Option<usize>) -> f64 {
+        match (self.dispersion, channel) {
+            (Some(dispersion), Some(channel)) => dispersion.index(WAVELENGTHS[channel]),
+            _ => self.refractive_index,
+        }
+    }
+
+    /// Returns fraction of light per channel left after travelling through the material
+    ///
+    /// Follows the Beer–Lambert law, light fades exponentially with the distance.
+    ///
+    /// # Arguments
+    /// * `distance` - length of the path inside the material
+    pub fn transmittance(&self, distance: f64) -> [f64; 3] {
+        if self.density == 0. {
+            return [1.; 3];
+        }
+        let a = self.absorption;
+        let exponent = self.density * distance;
+        [a.r, a.g, a.b].map(|c| (c.clamp(0, 255) as f64 / 255.).powf(exponent))
+    }
 }
 
 impl Default for Material {
@@ -88,6 +210,11 @@ impl Default for Material {
             reflect: 0.,
             transparent: 0.,
             refractive_index: 1.,
+            shading: Shading::Phong,
+            absorption: QuantColor::new(255, 255, 255),
+            density: 0.,
+            dispersion: None,
+            bump: None,
         }
     }
 }
@@ -102,4 +229,40 @@ mod tests {
         assert_eq!(m.transparent, 0.);
         assert_eq!(m.refractive_index, 1.);
     }
+
+    #[test]
+    fn dispersion() {
+        // Crown glass bends blue light more than red light
+        let glass = Dispersion::Cauchy(1.5046, 0.0042);
+        assert!((glass.index(0.5) - 1.5214).abs() < 1e-9);
+        let bk7 = Dispersion::Sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        );
+        assert!((bk7.index(0.5876) - 1.5168).abs() < 1e-4);
+
+        let m = Material::default()
+            .set_refractive_index(1.5)
+            .set_dispersion(Some(bk7));
+        assert_eq!(m.index(None), 1.5);
+        assert!(m.index(Some(2)) > m.index(Some(1)));
+        assert!(m.index(Some(1)) > m.index(Some(0)));
+        assert_eq!(Material::default().index(Some(2)), 1.);
+    }
+
+    #[test]
+    fn transmittance() {
+        // Clear materials let all light through
+        assert_eq!(Material::default().transmittance(10.), [1.; 3]);
+
+        let m = Material::default().set_absorption(QuantColor::new(255, 51, 0), 1.);
+        assert_eq!(m.transmittance(0.), [1.; 3]);
+        assert_eq!(m.transmittance(1.), [1., 0.2, 0.]);
+        let t = m.transmittance(2.);
+        assert!((t[1] - 0.04).abs() < 1e-9);
+
+        // Density scales the distance
+        let dense = m.set_absorption(QuantColor::new(255, 51, 0), 2.);
+        assert_eq!(dense.transmittance(1.), t);
+    }
 }
@@ -0,0 +1,144 @@
+//! Homogeneous participating media like fog and smoke
+use crate::units::sampling::orthonormal_basis;
+use crate::units::tuple::Vector;
+use std::f64::consts;
+
+/// Angular distribution of light scattered in a medium
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Phase {
+    /// Same in every direction
+    #[default]
+    Isotropic,
+    /// Henyey–Greenstein lobe, holds asymmetry from -1 backward to 1 forward
+    HenyeyGreenstein(f64),
+}
+
+impl Phase {
+    /// Returns density of scattering by an angle
+    ///
+    /// # Arguments
+    /// * `cos` - cosine between the incoming and the scattered direction of travel
+    pub fn eval(&self, cos: f64) -> f64 {
+        match *self {
+            Phase::Isotropic => 1. / (4. * consts::PI),
+            Phase::HenyeyGreenstein(g) => {
+                let denominator = 1. + g * g - 2. * g * cos;
+                (1. - g * g) / (4. * consts::PI * denominator * denominator.sqrt())
+            }
+        }
+    }
+
+    /// Picks a scattered direction of travel, its density is `eval` of the cosine
+    ///
+    /// # Arguments
+    /// * `direction` - incoming direction of travel
+    /// * `u`, `v` - random numbers in [0, 1)
+    pub fn sample(&self, direction: Vector, u: f64, v: f64) -> Vector {
+        let cos = match *self {
+            Phase::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let s = (1. - g * g) / (1. - g + 2. * g * u);
+                (1. + g * g - s * s) / (2. * g)
+            }
+            _ => 1. - 2. * u,
+        }
+        .clamp(-1., 1.);
+        let sin = (1. - cos * cos).sqrt();
+        let phi = 2. * consts::PI * v;
+        let (tangent, bitangent) = orthonormal_basis(direction);
+        tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin()) + direction * cos
+    }
+}
+
+/// Homogeneous medium, coefficients are per unit of distance
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Medium {
+    /// Fraction of light absorbed
+    pub absorption: f64,
+    /// Fraction of light scattered to other directions
+    pub scattering: f64,
+    pub phase: Phase,
+}
+
+impl Medium {
+    /// Creates new isotropic Medium
+    pub fn new(absorption: f64, scattering: f64) -> Medium {
+        Medium {
+            absorption,
+            scattering,
+            phase: Phase::Isotropic,
+        }
+    }
+
+    pub fn set_phase(&self, phase: Phase) -> Medium {
+        Medium { phase, ..*self }
+    }
+
+    /// Returns fraction of light lost per unit of distance
+    pub fn extinction(&self) -> f64 {
+        self.absorption + self.scattering
+    }
+
+    /// Returns fraction of lost light that is scattered instead of absorbed
+    pub fn albedo(&self) -> f64 {
+        if self.extinction() > 0. {
+            self.scattering / self.extinction()
+        } else {
+            0.
+        }
+    }
+
+    /// Returns fraction of light left after travelling a distance through the medium
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        if self.extinction() == 0. {
+            1.
+        } else {
+            (-self.extinction() * distance).exp()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::sampling::Rng;
+    use crate::units::tuple::Tuple;
+    use crate::units::utils;
+
+    #[test]
+    fn phase() {
+        // Both phase functions integrate to one over the sphere
+        for phase in [Phase::Isotropic, Phase::HenyeyGreenstein(0.6)] {
+            let n = 20000;
+            let integral: f64 = (0..n)
+                .map(|i| {
+                    let cos = -1. + 2. * (i as f64 + 0.5) / n as f64;
+                    phase.eval(cos) * 2. * consts::PI * 2. / n as f64
+                })
+                .sum();
+            assert!((integral - 1.).abs() < 1e-3);
+        }
+
+        // Forward scattering prefers the direction of travel
+        let forward = Phase::HenyeyGreenstein(0.8);
+        assert!(forward.eval(1.) > forward.eval(-1.));
+        let d = Vector::new(0, 0, 1);
+        let mut rng = Rng::new(3);
+        let mean: f64 = (0..2000)
+            .map(|_| forward.sample(d, rng.next_f64(), rng.next_f64()))
+            .inspect(|s| assert!(utils::float_eq(s.magnitude(), 1.)))
+            .map(|s| s.z)
+            .sum::<f64>()
+            / 2000.;
+        assert!((mean - 0.8).abs() < 0.05);
+    }
+
+    #[test]
+    fn transmittance() {
+        let fog = Medium::new(0.1, 0.3);
+        assert_eq!(fog.extinction(), 0.4);
+        assert!(utils::float_eq(fog.albedo(), 0.75));
+        assert!(utils::float_eq(fog.transmittance(2.), (-0.8_f64).exp()));
+        assert_eq!(Medium::default().transmittance(10.), 1.);
+        assert_eq!(fog.transmittance(f64::INFINITY), 0.);
+    }
+}
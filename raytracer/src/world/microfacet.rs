@@ -0,0 +1,225 @@
+//! Physically based microfacet reflection
+use crate::units::sampling::{self, orthonormal_basis};
+use crate::units::tuple::Vector;
+use std::f64::consts;
+
+/// Cook-Torrance BRDF with a GGX distribution, Smith shadowing and Schlick Fresnel
+///
+/// Metals reflect in their base color, dielectrics add a diffuse base under a white coat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Microfacet {
+    /// 0.0 is a perfect mirror, 1.0 is completely rough
+    pub roughness: f64,
+    /// 0.0 is a dielectric such as plastic, 1.0 is a metal
+    pub metallic: f64,
+    /// How much dielectric highlights take the base color
+    pub specular_tint: f64,
+}
+
+impl Microfacet {
+    /// Creates new Microfacet
+    pub fn new(roughness: f64, metallic: f64, specular_tint: f64) -> Microfacet {
+        Microfacet {
+            roughness,
+            metallic,
+            specular_tint,
+        }
+    }
+
+    /// GGX alpha, clamped so perfect mirrors stay numerically stable
+    fn alpha(&self) -> f64 {
+        self.roughness.clamp(0.03, 1.).powi(2)
+    }
+
+    /// Reflectance at normal incidence
+    fn f0(&self, base: [f64; 3]) -> [f64; 3] {
+        let lum = luminance(base);
+        let mut f0 = [0.; 3];
+        for (i, f) in f0.iter_mut().enumerate() {
+            let tint = if lum > 0. { base[i] / lum } else { 1. };
+            let dielectric = 0.04 * (1. + (tint - 1.) * self.specular_tint);
+            *f = dielectric + (base[i] - dielectric) * self.metallic;
+        }
+        f0
+    }
+
+    /// Schlick's approximation of the Fresnel reflectance
+    pub fn fresnel(&self, base: [f64; 3], cos: f64) -> [f64; 3] {
+        let factor = (1. - cos.clamp(0., 1.)).powi(5);
+        self.f0(base).map(|f| f + (1. - f) * factor)
+    }
+
+    /// GGX normal distribution
+    fn distribution(&self, n_dot_h: f64) -> f64 {
+        let a2 = self.alpha().powi(2);
+        let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+        a2 / (consts::PI * d * d)
+    }
+
+    /// Smith shadowing of a single direction
+    fn shadowing(&self, n_dot_x: f64) -> f64 {
+        let a2 = self.alpha().powi(2);
+        2. * n_dot_x / (n_dot_x + (a2 + (1. - a2) * n_dot_x * n_dot_x).sqrt())
+    }
+
+    /// Returns the BRDF times the cosine of the light direction
+    ///
+    /// # Arguments
+    /// * `base` - base color, 1.0 is white
+    /// * `normalv` - surface normal
+    /// * `eyev` - direction to the viewer
+    /// * `lightv` - direction to the light
+    pub fn eval(&self, base: [f64; 3], normalv: Vector, eyev: Vector, lightv: Vector) -> [f64; 3] {
+        let n_dot_l = normalv.dot(lightv);
+        let n_dot_v = normalv.dot(eyev);
+        if n_dot_l <= 0. || n_dot_v <= 0. {
+            return [0.; 3];
+        }
+        let halfway = (lightv + eyev).normalize();
+        let n_dot_h = normalv.dot(halfway).max(0.);
+        let v_dot_h = eyev.dot(halfway).max(0.);
+
+        let d = self.distribution(n_dot_h);
+        let g = self.shadowing(n_dot_l) * self.shadowing(n_dot_v);
+        let fresnel = self.fresnel(base, v_dot_h);
+        let mut result = [0.; 3];
+        for i in 0..3 {
+            let specular = d * g * fresnel[i] / (4. * n_dot_l * n_dot_v);
+            let diffuse = (1. - self.metallic) * (1. - fresnel[i]) * base[i] / consts::PI;
+            result[i] = (specular + diffuse) * n_dot_l;
+        }
+        result
+    }
+
+    /// Probability of sampling the specular lobe instead of the diffuse one
+    fn specular_probability(&self) -> f64 {
+        0.5 + 0.5 * self.metallic
+    }
+
+    /// Returns probability density per solid angle of sampling a light direction
+    pub fn pdf(&self, normalv: Vector, eyev: Vector, lightv: Vector) -> f64 {
+        let n_dot_l = normalv.dot(lightv);
+        if n_dot_l <= 0. {
+            return 0.;
+        }
+        let halfway = (lightv + eyev).normalize();
+        let n_dot_h = normalv.dot(halfway).max(0.);
+        let v_dot_h = eyev.dot(halfway).abs().max(1e-9);
+        let specular = self.distribution(n_dot_h) * n_dot_h / (4. * v_dot_h);
+        let diffuse = n_dot_l / consts::PI;
+        let p = self.specular_probability();
+        p * specular + (1. - p) * diffuse
+    }
+
+    /// Picks a light direction for a path, from the GGX distribution or the diffuse lobe
+    ///
+    /// # Arguments
+    /// * `base` - base color, 1.0 is white
+    /// * `normalv` - surface normal
+    /// * `eyev` - direction to the viewer
+    /// * `u` - three random numbers in [0, 1)
+    ///
+    /// # Returns
+    /// direction, BRDF times cosine divided by the density, and the density
+    pub fn sample(
+        &self,
+        base: [f64; 3],
+        normalv: Vector,
+        eyev: Vector,
+        u: [f64; 3],
+    ) -> Option<(Vector, [f64; 3], f64)> {
+        let (tangent, bitangent) = orthonormal_basis(normalv);
+        let lightv = if u[0] < self.specular_probability() {
+            let a2 = self.alpha().powi(2);
+            let phi = 2. * consts::PI * u[1];
+            let cos_theta = ((1. - u[2]) / (1. + (a2 - 1.) * u[2])).sqrt();
+            let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+            let halfway = tangent * (sin_theta * phi.cos())
+                + bitangent * (sin_theta * phi.sin())
+                + normalv * cos_theta;
+            halfway * (2. * eyev.dot(halfway)) - eyev
+        } else {
+            let (x, y, z) = sampling::cosine_hemisphere(u[1], u[2]);
+            tangent * x + bitangent * y + normalv * z
+        };
+        let pdf = self.pdf(normalv, eyev, lightv);
+        if pdf <= 0. {
+            return None;
+        }
+        let value = self.eval(base, normalv, eyev, lightv);
+        Some((lightv, value.map(|v| v / pdf), pdf))
+    }
+}
+
+/// Returns luminance of a linear color
+fn luminance(c: [f64; 3]) -> f64 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::sampling::Rng;
+    use crate::units::tuple::Tuple;
+    use crate::units::utils;
+
+    #[test]
+    fn fresnel() {
+        // Dielectrics reflect 4% head on and everything at grazing angles
+        let m = Microfacet::new(0.5, 0., 0.);
+        let f = m.fresnel([1., 0., 0.], 1.);
+        assert!(utils::float_eq(f[1], 0.04));
+        assert!(utils::float_eq(m.fresnel([1., 0., 0.], 0.)[1], 1.));
+
+        // Metals reflect in their base color
+        let m = Microfacet::new(0.5, 1., 0.);
+        assert_eq!(m.fresnel([1., 0.5, 0.], 1.), [1., 0.5, 0.]);
+    }
+
+    #[test]
+    fn eval() {
+        let n = Vector::new(0, 1, 0);
+        let m = Microfacet::new(0.3, 0., 0.);
+
+        // Light from below the surface doesn't reflect
+        let below = Vector::new(0, -1, 0);
+        assert_eq!(m.eval([1.; 3], n, n, below), [0.; 3]);
+
+        // The highlight is brightest in the mirror direction
+        let eye = Vector::new(1, 1, 0).normalize();
+        let mirror = Vector::new(-1, 1, 0).normalize();
+        let off = Vector::new(-0.2, 1., 0.).normalize();
+        assert!(m.eval([1.; 3], n, eye, mirror)[0] > m.eval([1.; 3], n, eye, off)[0]);
+
+        // A rough white dielectric reflects less than all light in total
+        let mut rng = Rng::new(5);
+        let mut total = 0.;
+        for _ in 0..20000 {
+            let u = [rng.next_f64(), rng.next_f64(), rng.next_f64()];
+            if let Some((_, weight, _)) = m.sample([1.; 3], n, eye, u) {
+                total += weight[0];
+            }
+        }
+        let albedo = total / 20000.;
+        assert!(albedo > 0.6 && albedo < 1.01);
+    }
+
+    #[test]
+    fn pdf() {
+        // Density integrates to about one over the hemisphere
+        let n = Vector::new(0, 0, 1);
+        let eye = Vector::new(0.3, 0., 1.).normalize();
+        let m = Microfacet::new(0.6, 0.5, 0.);
+        let mut rng = Rng::new(9);
+        let mut hemisphere = 0.;
+        for _ in 0..50000 {
+            let (x, y, z) = sampling::cosine_hemisphere(rng.next_f64(), rng.next_f64());
+            let l = Vector::new(x, y, z);
+            // Estimate the integral of the pdf with cosine sampled directions
+            hemisphere += m.pdf(n, eye, l) / (z / consts::PI);
+        }
+        // Halfway vectors reflecting below the surface are lost, so it stays under one
+        let integral = hemisphere / 50000.;
+        assert!(integral > 0.85 && integral < 1.);
+    }
+}
@@ -1,7 +1,16 @@
+pub mod animation;
+pub mod background;
+pub mod bump;
 pub mod environment;
 pub mod light;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
 pub mod patterns;
-pub use environment::{tick, Environment, Projectile};
+pub use background::{Background, EnvironmentMap};
+pub use bump::{Bump, Texture};
+pub use environment::{tick, Collider, Environment, Projectile, RigidSphere, Simulation};
 pub use light::PointLight;
-pub use material::Material;
+pub use material::{Dispersion, Material, Shading};
+pub use medium::{Medium, Phase};
+pub use microfacet::Microfacet;
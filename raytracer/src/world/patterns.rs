@@ -100,7 +100,7 @@ impl Pattern {
         }
     }
 
-    pub fn color_at_object(&self, object: Shape, world_point: Point) -> QuantColor {
+    pub fn color_at_object(&self, object: &Shape, world_point: Point) -> QuantColor {
         let object_point =
             Point::from(object.transformation_matrix.invert().unwrap() * world_point);
         let pattern_point =
@@ -250,7 +250,7 @@ mod tests {
         let pattern = Pattern::new(PatternType::Stripe(WHITE, BLACK));
         assert_eq!(
             WHITE,
-            pattern.color_at_object(object, Point::new(1.5, 0., 0.))
+            pattern.color_at_object(&object, Point::new(1.5, 0., 0.))
         );
 
         // Stripes with a pattern transformation
@@ -259,7 +259,7 @@ mod tests {
         pattern.transformation_matrix = Matrix::scale(2, 2, 2);
         assert_eq!(
             WHITE,
-            pattern.color_at_object(object, Point::new(1.5, 0., 0.))
+            pattern.color_at_object(&object, Point::new(1.5, 0., 0.))
         );
 
         // Stripes with both an object and a pattern transformation
@@ -269,7 +269,7 @@ mod tests {
         pattern.transformation_matrix = Matrix::translate(0.5, 0., 0.);
         assert_eq!(
             WHITE,
-            pattern.color_at_object(object, Point::new(2.5, 0., 0.))
+            pattern.color_at_object(&object, Point::new(2.5, 0., 0.))
         );
     }
 }